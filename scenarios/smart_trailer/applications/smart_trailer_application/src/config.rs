@@ -0,0 +1,97 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+use digital_twin_model::units::{semantic_type, Unit};
+use digital_twin_providers_common::config::{
+    ChariottConfig, Config, DiscoveryConfig, MqttConfig, RetryConfig, TlsConfig,
+};
+use serde::Deserializer;
+use serde_derive::{Deserialize, Serialize};
+
+const MQTT_CLIENT_ID: &str = "smart-trailer-consumer";
+
+const DEFAULT_FREQUENCY_MS: u64 = 10000; // 10 seconds
+
+/// The smart trailer application's configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmartTrailerApplicationConfig {
+    pub chariott: ChariottConfig,
+    pub mqtt: MqttConfig,
+    pub subscription: SubscriptionConfig,
+    pub retry: RetryConfig,
+    pub discovery: DiscoveryConfig,
+    pub tls: TlsConfig,
+}
+
+impl Default for SmartTrailerApplicationConfig {
+    fn default() -> Self {
+        SmartTrailerApplicationConfig {
+            chariott: ChariottConfig::default(),
+            mqtt: MqttConfig::new(MQTT_CLIENT_ID),
+            subscription: SubscriptionConfig::default(),
+            retry: RetryConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Config for SmartTrailerApplicationConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.chariott.validate()?;
+        self.mqtt.validate()?;
+        self.subscription.validate()?;
+        self.retry.validate()?;
+        self.discovery.validate()?;
+        self.tls.validate()
+    }
+}
+
+/// The configuration of the trailer weight subscription.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionConfig {
+    /// The requested frequency of the trailer weight updates.
+    pub frequency_ms: u64,
+    /// The unit to log the trailer weight in. Either the unit's name or its symbol.
+    #[serde(deserialize_with = "deserialize_unit")]
+    pub weight_unit: Unit,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig {
+            frequency_ms: DEFAULT_FREQUENCY_MS,
+            weight_unit: Unit::Kilogram,
+        }
+    }
+}
+
+impl SubscriptionConfig {
+    /// Check that the configuration values are valid.
+    fn validate(&self) -> Result<(), String> {
+        if self.frequency_ms == 0 {
+            return Err("subscription.frequency_ms must be greater than 0".to_string());
+        }
+        if self.weight_unit.semantic_type() != semantic_type::MASS {
+            return Err(format!(
+                "subscription.weight_unit '{}' is not a unit of {}",
+                self.weight_unit.as_str(),
+                semantic_type::MASS
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserialize a unit from its name or its symbol.
+///
+/// # Arguments
+/// * `deserializer` - The deserializer.
+fn deserialize_unit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Unit, D::Error> {
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+    Unit::parse(&value).map_err(serde::de::Error::custom)
+}
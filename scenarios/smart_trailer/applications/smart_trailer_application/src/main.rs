@@ -2,82 +2,231 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::env;
+mod config;
 
+use digital_twin_model::model_mapping;
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::property_envelope::PropertyEnvelope;
 use digital_twin_model::trailer_v1;
-use digital_twin_providers_common::constants::chariott::{
-    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
-    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE, INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
-    INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE, INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION,
-};
+use digital_twin_model::units::Unit;
+use digital_twin_providers_common::config::ConfigLoader;
 use digital_twin_providers_common::constants::{
     constraint_type, digital_twin_operation, digital_twin_protocol,
 };
-use digital_twin_providers_common::utils::{
-    discover_digital_twin_provider_using_ibeji, discover_service_using_chariott,
-};
+use digital_twin_providers_common::discovery_cache::DiscoveryCache;
+use digital_twin_providers_common::errors::{is_connection_error, DiscoveryError};
+use digital_twin_providers_common::shutdown::{shutdown_on_signal, CancellationToken};
+use digital_twin_providers_common::tls::{configure_clients, connect};
+use digital_twin_providers_common::utils::{connection_error, RetryPolicy};
 use env_logger::{Builder, Target};
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::EndpointInfo;
 use invehicle_stack_interfaces::module::managed_subscribe::v1::managed_subscribe_client::ManagedSubscribeClient;
 use invehicle_stack_interfaces::module::managed_subscribe::v1::{
     Constraint, SubscriptionInfoRequest, SubscriptionInfoResponse,
 };
-use log::{debug, info, LevelFilter};
+use log::{debug, info, warn, LevelFilter};
 use paho_mqtt as mqtt;
-use tokio::signal;
+use serde_json::Value;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
-use tokio::time::{sleep, Duration};
-use tonic::{Request, Status};
+use tokio::time::Duration;
+use tonic::{Code, Request, Status};
 use uuid::Uuid;
 
-const FREQUENCY_MS_FLAG: &str = "freq_ms=";
-const MQTT_CLIENT_ID: &str = "smart-trailer-consumer";
+use crate::config::SmartTrailerApplicationConfig;
+
+const CONFIG_NAME: &str = "smart_trailer_application";
 
-// Note: These could be provided in configuration files.
-// We ignore the DevSkim warning because this is a sample application. In production, https should be used.
-const CHARIOTT_SERVICE_DISCOVERY_URI: &str = "http://0.0.0.0:50000"; // Devskim: ignore DS137138
+// Short flags that are kept for compatibility with the configuration keys that they set.
+const FREQUENCY_MS_ALIAS: &str = "freq_ms";
+const WEIGHT_UNIT_ALIAS: &str = "weight_unit";
 
-const DEFAULT_FREQUENCY_MS: u64 = 10000; // 10 seconds
+/// A v1 trailer property that the application subscribes to.
+#[derive(Clone, Copy, Debug)]
+struct Property {
+    name: &'static str,
+    id: &'static str,
+    unit: Option<Unit>,
+}
+
+const TRAILER_WEIGHT: Property = Property {
+    name: trailer_v1::trailer::trailer_weight::NAME,
+    id: trailer_v1::trailer::trailer_weight::ID,
+    unit: trailer_v1::trailer::trailer_weight::UNIT,
+};
+
+const IS_TRAILER_CONNECTED: Property = Property {
+    name: trailer_v1::trailer::is_trailer_connected::NAME,
+    id: trailer_v1::trailer::is_trailer_connected::ID,
+    unit: trailer_v1::trailer::is_trailer_connected::UNIT,
+};
+
+/// Find a provider for a property. A provider of the v1 property is preferred, otherwise a
+/// provider of any version of the property that can be mapped to v1 is used.
+/// Returns the id of the provided entity and the provider's endpoint.
+///
+/// # Arguments
+/// * `discovery_cache` - The cache of discovered providers.
+/// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI.
+/// * `property` - The property.
+async fn discover_provider(
+    discovery_cache: &DiscoveryCache,
+    invehicle_digital_twin_uri: &str,
+    property: Property,
+) -> Result<(String, EndpointInfo), DiscoveryError> {
+    let entity_ids = std::iter::once(property.id).chain(model_mapping::compatible_ids(property.id));
+
+    let mut errors = Vec::new();
+    for entity_id in entity_ids {
+        match discovery_cache
+            .discover_provider(
+                invehicle_digital_twin_uri,
+                entity_id,
+                digital_twin_protocol::GRPC,
+                &[digital_twin_operation::MANAGEDSUBSCRIBE.to_string()],
+            )
+            .await
+        {
+            Ok(endpoint_info) => return Ok((entity_id.to_string(), endpoint_info)),
+            // Another version of the property may have a suitable provider.
+            Err(
+                err @ (DiscoveryError::NotFound { .. } | DiscoveryError::ProtocolMismatch { .. }),
+            ) => errors.push(err.to_string()),
+            Err(err) => return Err(err),
+        }
+    }
 
-// Constants used for retry logic
-const MAX_RETRIES: i32 = 10; // for demo purposes we will retry a maximum of 10 times
-                             // By default we will wait 5 seconds between retry attempts
-const DURATION_BETWEEN_ATTEMPTS: Duration = Duration::from_secs(5);
+    Err(DiscoveryError::not_found(format!(
+        "A provider was not found in the digital twin service: {}",
+        errors.join("; ")
+    )))
+}
 
-/// Get trailer weight's subscription information from managed subscribe endpoint.
+/// Get an entity's subscription information from managed subscribe endpoint.
 ///
 /// # Arguments
 /// * `managed_subscribe_uri` - The managed subscribe URI.
+/// * `entity_id` - The id of the provided entity.
 /// * `constraints` - Constraints for the managed topic.
-async fn get_trailer_weight_subscription_info(
+/// * `retry_policy` - How the call is retried.
+async fn get_subscription_info(
     managed_subscribe_uri: &str,
+    entity_id: &str,
     constraints: Vec<Constraint>,
+    retry_policy: &RetryPolicy,
 ) -> Result<SubscriptionInfoResponse, Status> {
-    // Create gRPC client.
-    let mut client = ManagedSubscribeClient::connect(managed_subscribe_uri.to_string())
-        .await
-        .map_err(|err| Status::from_error(err.into()))?;
+    let response = retry_policy
+        .run("GetSubscriptionInfo", || {
+            let constraints = constraints.clone();
+            async move {
+                // Create gRPC client.
+                let mut client = ManagedSubscribeClient::new(
+                    connect(managed_subscribe_uri)
+                        .await
+                        .map_err(connection_error)?,
+                );
 
-    let request = Request::new(SubscriptionInfoRequest {
-        entity_id: trailer_v1::trailer::trailer_weight::ID.to_string(),
-        constraints,
-    });
+                let request = Request::new(SubscriptionInfoRequest {
+                    entity_id: entity_id.to_string(),
+                    constraints,
+                });
 
-    let response = client.get_subscription_info(request).await?;
+                client.get_subscription_info(request).await
+            }
+        })
+        .await?;
 
     Ok(response.into_inner())
 }
 
-/// Receive Trailer Weight updates.
+/// Discover the provider of a property and get the subscription information of a managed topic
+/// from it. Discovered URIs that cannot be reached are invalidated, so that they are
+/// rediscovered when this is retried.
+/// Returns the id of the provided entity and the subscription information.
+///
+/// # Arguments
+/// * `discovery_cache` - The cache of discovered services and providers.
+/// * `property` - The property.
+/// * `constraints` - Constraints for the managed topic.
+async fn subscribe(
+    discovery_cache: &DiscoveryCache,
+    property: Property,
+    constraints: Vec<Constraint>,
+) -> Result<(String, SubscriptionInfoResponse), Status> {
+    // Get the In-vehicle Digital Twin Uri from the service discovery system
+    let invehicle_digital_twin_uri = discovery_cache
+        .discover_invehicle_digital_twin_service()
+        .await?;
+
+    // Retrieve the provider URI.
+    let provider = discover_provider(discovery_cache, &invehicle_digital_twin_uri, property).await;
+    if let Err(DiscoveryError::Connection { .. }) = provider {
+        discovery_cache.invalidate_uri(&invehicle_digital_twin_uri);
+    }
+    let (entity_id, provider_endpoint_info) = provider?;
+    let managed_subscribe_uri = provider_endpoint_info.uri;
+    info!("The Managed Subscribe URI for the {entity_id} property's provider is {managed_subscribe_uri}");
+
+    // Get the subscription information for a managed topic with constraints.
+    let subscription_info = get_subscription_info(
+        &managed_subscribe_uri,
+        &entity_id,
+        constraints,
+        discovery_cache.retry_policy(),
+    )
+    .await;
+    if matches!(&subscription_info, Err(status) if is_connection_error(status)) {
+        discovery_cache.invalidate_uri(&managed_subscribe_uri);
+    }
+
+    Ok((entity_id, subscription_info?))
+}
+
+/// Check that a message of a property conforms to the model. Messages of other versions of the
+/// property are mapped to the v1 property.
+/// Returns the v1 property's envelope.
+///
+/// # Arguments
+/// * `model_registry` - The model registry.
+/// * `property` - The property.
+/// * `payload` - The message's payload.
+fn validate_message(
+    model_registry: &ModelRegistry,
+    property: Property,
+    payload: &[u8],
+) -> Result<PropertyEnvelope<Value>, String> {
+    let envelope = PropertyEnvelope::<Value>::decode(payload)?;
+    let mut envelope = model_mapping::adapt_envelope(envelope, property.id)?;
+
+    // Providers that predate units of measure publish the value in the model's unit.
+    if envelope.metadata.unit.is_none() {
+        envelope.metadata.unit = property.unit;
+    }
+
+    envelope.expect_entity(property.name, property.id)?;
+
+    model_registry.validate_envelope(&envelope)?;
+
+    Ok(envelope)
+}
+
+/// Receive the messages of a topic until shutdown.
 ///
 /// # Arguments
 /// * `broker_uri` - The broker URI.
 /// * `topic` - The topic.
-async fn receive_trailer_weight_updates(
+/// * `mqtt_client_id` - The MQTT client id, which is made unique for this connection.
+/// * `shutdown` - Stops receiving messages and disconnects from the broker when it is cancelled.
+/// * `on_message` - Handles a message.
+fn receive_updates(
     broker_uri: &str,
     topic: &str,
+    mqtt_client_id: &str,
+    shutdown: CancellationToken,
+    mut on_message: impl FnMut(&mqtt::Message) + Send + 'static,
 ) -> Result<JoinHandle<Result<(), String>>, String> {
     // Create a unique id for the client.
-    let client_id = format!("{MQTT_CLIENT_ID}-{}", Uuid::new_v4());
+    let client_id = format!("{mqtt_client_id}-{}", Uuid::new_v4());
 
     let create_opts = mqtt::CreateOptionsBuilder::new()
         .server_uri(broker_uri)
@@ -90,12 +239,12 @@ async fn receive_trailer_weight_updates(
     let receiver = client.start_consuming();
 
     // Setup task to handle clean shutdown.
-    let ctrlc_cli = client.clone();
+    let shutdown_cli = client.clone();
     tokio::spawn(async move {
-        _ = signal::ctrl_c().await;
+        shutdown.cancelled().await;
 
         // Tells the client to shutdown consuming thread.
-        ctrlc_cli.stop_consuming();
+        shutdown_cli.stop_consuming();
     });
 
     // Last Will and Testament
@@ -121,13 +270,11 @@ async fn receive_trailer_weight_updates(
     // Copy topic for separate thread.
     let topic_string = topic.to_string();
 
-    let sub_handle: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+    // The client blocks while it waits for messages, so it runs on the blocking thread pool.
+    let sub_handle = tokio::task::spawn_blocking(move || {
         for msg in receiver.iter() {
             if let Some(msg) = msg {
-                // Here we log the message received. This could be expanded to parsing the message,
-                // Obtaining the weight and making decisions based on the weight
-                // For example, adjusting body functions or powertrain of the towing vehicle.
-                info!("{}", msg);
+                on_message(&msg);
             } else if !client.is_connected() {
                 if client.reconnect().is_ok() {
                     client
@@ -156,6 +303,52 @@ async fn receive_trailer_weight_updates(
     Ok(sub_handle)
 }
 
+/// Subscribe to a property and receive its messages until shutdown. The subscription is retried
+/// while the property's provider is not found, since it may not have registered yet.
+/// Returns the task that receives the messages, or `None` if the application was stopped before
+/// it subscribed.
+///
+/// # Arguments
+/// * `discovery_cache` - The cache of discovered services and providers.
+/// * `retry_policy` - How the subscription is retried.
+/// * `property` - The property.
+/// * `constraints` - Constraints for the managed topic.
+/// * `mqtt_client_id` - The MQTT client id, which is made unique for each connection.
+/// * `shutdown` - Stops subscribing, or receiving the messages, when it is cancelled.
+/// * `on_message` - Handles a message.
+async fn follow_property(
+    discovery_cache: &DiscoveryCache,
+    retry_policy: &RetryPolicy,
+    property: Property,
+    constraints: Vec<Constraint>,
+    mqtt_client_id: &str,
+    shutdown: CancellationToken,
+    on_message: impl FnMut(&mqtt::Message) + Send + 'static,
+) -> Result<Option<JoinHandle<Result<(), String>>>, Status> {
+    let operation = format!("Subscribe to {}", property.name);
+    let subscription = retry_policy.run(&operation, || {
+        subscribe(discovery_cache, property, constraints.clone())
+    });
+    let (entity_id, subscription_info) = tokio::select! {
+        result = subscription => result?,
+        _ = shutdown.cancelled() => return Ok(None),
+    };
+    debug!("Received the subscription information for {entity_id}");
+
+    // Deconstruct subscription information.
+    let broker_uri = subscription_info.uri;
+    let topic = subscription_info.context;
+    info!(
+        "The broker URI for the {} property's provider is {broker_uri}",
+        property.name
+    );
+
+    // Subscribe to topic.
+    receive_updates(&broker_uri, &topic, mqtt_client_id, shutdown, on_message)
+        .map(Some)
+        .map_err(|err| Status::internal(format!("{err:?}")))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Setup logging.
@@ -166,91 +359,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("The Smart Trailer Application has started.");
 
-    // Get the In-vehicle Digital Twin Uri from the service discovery system
-    // This could be enhanced to add retries for robustness
-    let invehicle_digital_twin_uri = discover_service_using_chariott(
-        CHARIOTT_SERVICE_DISCOVERY_URI,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE,
-    )
-    .await?;
+    let config: SmartTrailerApplicationConfig = ConfigLoader::new(CONFIG_NAME)
+        .with_alias(FREQUENCY_MS_ALIAS, "subscription.frequency_ms")
+        .with_alias(WEIGHT_UNIT_ALIAS, "subscription.weight_unit")
+        .load()?;
+    configure_clients(&config.tls)?;
 
-    // Get subscription constraints.
-    let frequency_ms = env::args()
-        .find_map(|arg| {
-            if arg.contains(FREQUENCY_MS_FLAG) {
-                Some(arg.replace(FREQUENCY_MS_FLAG, ""))
-            } else {
-                None
-            }
-        })
-        .unwrap_or_else(|| DEFAULT_FREQUENCY_MS.to_string());
+    // Stop on control-c or SIGTERM.
+    let shutdown = shutdown_on_signal();
 
-    // Retrieve the provider URI.
-    let mut provider_endpoint_info = None;
-    let mut retries: i32 = 0;
-    while provider_endpoint_info.is_none() {
-        provider_endpoint_info = match discover_digital_twin_provider_using_ibeji(
-            &invehicle_digital_twin_uri,
-            trailer_v1::trailer::trailer_weight::ID,
-            digital_twin_protocol::GRPC,
-            &[digital_twin_operation::MANAGEDSUBSCRIBE.to_string()],
-        )
-        .await
-        {
-            Ok(response) => Some(response),
-            Err(status) => {
-                info!(
-                    "A provider was not found in the digital twin service for id '{}' with: '{:?}'",
-                    trailer_v1::trailer::trailer_weight::ID,
-                    status
-                );
-                None
-            }
-        };
-
-        if provider_endpoint_info.is_none() && retries < MAX_RETRIES {
-            info!("Retrying FindById to retrieve the properties provider endpoint in {DURATION_BETWEEN_ATTEMPTS:?}.");
-            sleep(DURATION_BETWEEN_ATTEMPTS).await;
-            retries += 1;
-        } else {
-            break;
-        }
-    }
+    // Load the model used to validate the received values.
+    let model_registry = ModelRegistry::with_builtin_models()?;
 
-    let managed_subscribe_uri = provider_endpoint_info.ok_or("Maximum amount of retries was reached while trying to retrieve the digital twin provider.")?.uri;
-    info!("The Managed Subscribe URI for the TrailerWeight property's provider is {managed_subscribe_uri}");
+    // The subscription is retried as a whole, so each of its calls is made once. The retries
+    // would otherwise multiply, and the deadline of a subscription attempt would cut the retries
+    // of its calls short.
+    let retry_policy = config.retry.policy();
+    let discovery_cache = config
+        .discovery
+        .cache(&config.chariott, retry_policy.single_attempt())?;
 
     // Create constraint for the managed subscribe call.
     let frequency_constraint = Constraint {
         r#type: constraint_type::FREQUENCY_MS.to_string(),
-        value: frequency_ms.to_string(),
+        value: config.subscription.frequency_ms.to_string(),
     };
 
-    // Get the subscription information for a managed topic with constraints.
-    let subscription_info =
-        get_trailer_weight_subscription_info(&managed_subscribe_uri, vec![frequency_constraint])
-            .await?;
+    // The providers may not have registered yet, so keep looking for them while they are not
+    // found.
+    let subscribe_retry_policy = retry_policy
+        .with_attempt_timeout(None)
+        .with_retryable_code(Code::NotFound);
+
+    // The trailer's connection state is published on every change, so the application learns
+    // right away when the trailer is disconnected, instead of when the weight stops arriving.
+    let (connected_sender, trailer_connected) = watch::channel(None);
+    let connection_registry = model_registry.clone();
+    let on_connection_message = move |msg: &mqtt::Message| match validate_message(
+        &connection_registry,
+        IS_TRAILER_CONNECTED,
+        msg.payload(),
+    )
+    .and_then(|envelope| {
+        envelope
+            .value
+            .as_bool()
+            .ok_or_else(|| format!("'{}' is not a boolean", envelope.value))
+    }) {
+        Ok(connected) => {
+            if connected_sender.send_replace(Some(connected)) == Some(connected) {
+                return;
+            }
+            if connected {
+                info!("The trailer is connected.");
+            } else {
+                info!("The trailer is disconnected, so its weight is ignored until it is connected again.");
+            }
+        }
+        Err(err) => warn!("Rejected message on {}: {err}", msg.topic()),
+    };
 
-    // Deconstruct subscription information.
-    let broker_uri = subscription_info.uri;
-    let topic = subscription_info.context;
-    info!("The broker URI for the TrailerWeight property's provider is {broker_uri}");
+    let weight_unit = config.subscription.weight_unit;
+    let on_weight_message = move |msg: &mqtt::Message| {
+        if *trailer_connected.borrow() == Some(false) {
+            debug!(
+                "Ignoring the message on {}, since the trailer is disconnected",
+                msg.topic()
+            );
+            return;
+        }
 
-    // Subscribe to topic.
-    let sub_handle = receive_trailer_weight_updates(&broker_uri, &topic)
-        .await
-        .map_err(|err| Status::internal(format!("{err:?}")))?;
+        // Here we log the message received if it conforms to the model. This could be
+        // expanded to obtaining the weight and making decisions based on the weight
+        // For example, adjusting body functions or powertrain of the towing vehicle.
+        match validate_message(&model_registry, TRAILER_WEIGHT, msg.payload())
+            .and_then(|envelope| Ok((envelope.value_in(weight_unit)?, envelope)))
+        {
+            Ok((weight, envelope)) => {
+                info!(
+                    "{}: {} = {weight:.1} {weight_unit}",
+                    msg.topic(),
+                    envelope.name
+                )
+            }
+            Err(err) => warn!("Rejected message on {}: {err}", msg.topic()),
+        }
+    };
 
-    signal::ctrl_c().await?;
+    let (connection_handle, weight_handle) = tokio::try_join!(
+        follow_property(
+            &discovery_cache,
+            &subscribe_retry_policy,
+            IS_TRAILER_CONNECTED,
+            Vec::new(),
+            &config.mqtt.client_id,
+            shutdown.clone(),
+            on_connection_message,
+        ),
+        follow_property(
+            &discovery_cache,
+            &subscribe_retry_policy,
+            TRAILER_WEIGHT,
+            vec![frequency_constraint],
+            &config.mqtt.client_id,
+            shutdown.clone(),
+            on_weight_message,
+        ),
+    )?;
+
+    shutdown.cancelled().await;
 
     info!("The Consumer has completed. Shutting down...");
 
-    // Wait for subscriber task to cleanly shutdown.
-    _ = sub_handle.await;
+    // Wait for subscriber tasks to cleanly shutdown. A property has no task if the Consumer was
+    // stopped before it subscribed.
+    for handle in [connection_handle, weight_handle].into_iter().flatten() {
+        _ = handle.await;
+    }
 
     Ok(())
 }
@@ -0,0 +1,499 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generates one Rust source file per DTDL document in the `dtdl` directory.
+//!
+//! For a document named `<model>.json` the file `$OUT_DIR/<model>.rs` is generated. It contains
+//! one module per Interface, and each Interface module contains one module per Property,
+//! Telemetry and Command with its `ID`, `NAME`, `DESCRIPTION`, `TYPE`, `WRITABLE`,
+//! `SEMANTIC_TYPE` and `UNIT`. Relationships get a module with their `TARGET`, and Components
+//! a module with their `SCHEMA` and the modules of their Interface's contents, with composite
+//! DTMIs such as `trailer::front_axle::left_tire::pressure::ID`. The documents are also listed in
+//! `$OUT_DIR/dtdl_documents.rs` so that they can be embedded in the crate.
+
+#[allow(dead_code)]
+#[path = "src/dtdl.rs"]
+mod dtdl;
+#[allow(dead_code)]
+#[path = "src/dtmi.rs"]
+mod dtmi;
+#[allow(dead_code)]
+#[path = "src/units.rs"]
+mod units;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use dtdl::{element_type, primitive_schema, ComplexSchema, Content, Interface, Schema};
+use dtmi::Dtmi;
+
+const DTDL_DIR: &str = "dtdl";
+
+/// The maximum nesting depth of Components. DTDL limits it to 8, which also catches cycles.
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+/// Rust keywords that cannot be used as plain identifiers.
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where",
+    "while",
+];
+
+/// Convert a DTDL name such as `IsTrailerConnected` to a snake case Rust identifier.
+///
+/// # Arguments
+/// * `name` - The DTDL name.
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut result = String::new();
+
+    for (index, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next_is_lower = chars.get(index + 1).is_some_and(|n| n.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                result.push('_');
+            }
+        }
+        result.extend(c.to_lowercase());
+    }
+
+    to_identifier(&result)
+}
+
+/// Convert a DTDL name to an upper camel case Rust identifier.
+///
+/// # Arguments
+/// * `name` - The DTDL name.
+fn to_camel_case(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Escape an identifier that collides with a Rust keyword.
+///
+/// # Arguments
+/// * `identifier` - The identifier.
+fn to_identifier(identifier: &str) -> String {
+    if RUST_KEYWORDS.contains(&identifier) {
+        format!("r#{identifier}")
+    } else {
+        identifier.to_string()
+    }
+}
+
+/// Get the name of the module for an Interface from the last segment of its DTMI.
+/// For example `dtmi:sdv:Trailer;1` becomes `trailer`.
+///
+/// # Arguments
+/// * `id` - The Interface's DTMI.
+fn interface_module_name(id: &str) -> Result<String, String> {
+    Ok(to_snake_case(Dtmi::parse(id)?.name()))
+}
+
+/// Get the Rust type for a primitive DTDL schema.
+///
+/// # Arguments
+/// * `schema` - The primitive schema name.
+fn primitive_rust_type(schema: &str) -> Option<&'static str> {
+    match schema {
+        primitive_schema::BOOLEAN => Some("bool"),
+        primitive_schema::DOUBLE => Some("f64"),
+        primitive_schema::FLOAT => Some("f32"),
+        primitive_schema::INTEGER => Some("i32"),
+        primitive_schema::LONG => Some("i64"),
+        primitive_schema::STRING
+        | primitive_schema::DATE
+        | primitive_schema::DATE_TIME
+        | primitive_schema::DURATION
+        | primitive_schema::TIME => Some("String"),
+        _ => None,
+    }
+}
+
+/// Generate the Rust type for a schema. Object schemas need a struct definition, which is
+/// appended to `definitions`.
+///
+/// # Arguments
+/// * `schema` - The schema.
+/// * `type_name` - The name to use for a generated struct.
+/// * `definitions` - Collects the generated struct definitions.
+fn rust_type(
+    schema: &Schema,
+    type_name: &str,
+    definitions: &mut Vec<String>,
+) -> Result<String, String> {
+    match schema {
+        Schema::Named(name) => primitive_rust_type(name)
+            .map(str::to_string)
+            .ok_or_else(|| format!("Unsupported schema '{name}'")),
+        Schema::Complex(complex) => complex_rust_type(complex, type_name, definitions),
+    }
+}
+
+/// Generate the Rust type for a complex schema.
+///
+/// # Arguments
+/// * `schema` - The complex schema.
+/// * `type_name` - The name to use for a generated struct.
+/// * `definitions` - Collects the generated struct definitions.
+fn complex_rust_type(
+    schema: &ComplexSchema,
+    type_name: &str,
+    definitions: &mut Vec<String>,
+) -> Result<String, String> {
+    match schema.r#type.as_str() {
+        element_type::ENUM => {
+            let value_schema = schema.value_schema.as_deref().unwrap_or_default();
+            primitive_rust_type(value_schema)
+                .map(str::to_string)
+                .ok_or_else(|| format!("Unsupported enum value schema '{value_schema}'"))
+        }
+        element_type::ARRAY => {
+            let element_schema = schema
+                .element_schema
+                .as_ref()
+                .ok_or_else(|| "An Array schema requires an elementSchema".to_string())?;
+            let element_type =
+                rust_type(element_schema, &format!("{type_name}Element"), definitions)?;
+            Ok(format!("Vec<{element_type}>"))
+        }
+        element_type::MAP => {
+            let map_value = schema
+                .map_value
+                .as_ref()
+                .ok_or_else(|| "A Map schema requires a mapValue".to_string())?;
+            let value_type = rust_type(
+                &map_value.schema,
+                &format!("{type_name}{}", to_camel_case(&map_value.name)),
+                definitions,
+            )?;
+            Ok(format!("std::collections::HashMap<String, {value_type}>"))
+        }
+        element_type::OBJECT => {
+            let mut definition = String::new();
+            writeln!(
+                definition,
+                "#[derive(Clone, Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]"
+            )
+            .unwrap();
+            writeln!(definition, "pub struct {type_name} {{").unwrap();
+            for field in &schema.fields {
+                let field_type = rust_type(
+                    &field.schema,
+                    &format!("{type_name}{}", to_camel_case(&field.name)),
+                    definitions,
+                )?;
+                writeln!(definition, "    #[serde(rename = {:?})]", field.name).unwrap();
+                writeln!(
+                    definition,
+                    "    pub {}: {field_type},",
+                    to_snake_case(&field.name)
+                )
+                .unwrap();
+            }
+            writeln!(definition, "}}").unwrap();
+            definitions.push(definition);
+            Ok(type_name.to_string())
+        }
+        other => Err(format!("Unsupported complex schema '{other}'")),
+    }
+}
+
+/// Generate the module for a Property, Telemetry, Command, Relationship or Component. A
+/// Component's module also contains the modules for the contents of its Interface, with
+/// composite DTMIs. Other elements are skipped.
+///
+/// # Arguments
+/// * `interface` - The Interface that contains the element.
+/// * `content` - The content element.
+/// * `id` - The element's DTMI.
+/// * `depth` - The nesting depth of the element's module.
+/// * `interfaces` - All Interfaces, by DTMI, used to resolve Components.
+/// * `out` - The generated code.
+fn generate_content(
+    interface: &Interface,
+    content: &Content,
+    id: &str,
+    depth: usize,
+    interfaces: &HashMap<String, Interface>,
+    out: &mut String,
+) -> Result<(), String> {
+    let is_value = content.is(element_type::PROPERTY)
+        || content.is(element_type::TELEMETRY)
+        || content.is(element_type::COMMAND);
+    if !is_value && !content.is(element_type::RELATIONSHIP) && !content.is(element_type::COMPONENT)
+    {
+        return Ok(());
+    }
+
+    let indent = "    ".repeat(depth);
+
+    writeln!(out).unwrap();
+    writeln!(out, "{indent}pub mod {} {{", to_snake_case(&content.name)).unwrap();
+    writeln!(out, "{indent}    pub const ID: &str = {id:?};").unwrap();
+    writeln!(
+        out,
+        "{indent}    pub const NAME: &str = {:?};",
+        content.name
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{indent}    pub const DESCRIPTION: &str = {:?};",
+        content.description_text()
+    )
+    .unwrap();
+
+    if is_value {
+        generate_value(interface, content, &indent, out)?;
+    } else if content.is(element_type::RELATIONSHIP) {
+        writeln!(
+            out,
+            "{indent}    pub const TARGET: Option<&str> = {:?};",
+            content.target
+        )
+        .unwrap();
+    } else {
+        let schema = content.component_schema().unwrap_or_default();
+        let component_interface = interfaces.get(schema).ok_or_else(|| {
+            format!(
+                "Component '{}' in '{}' refers to the unknown Interface '{schema}'",
+                content.name, interface.id
+            )
+        })?;
+        if depth > MAX_COMPONENT_DEPTH {
+            return Err(format!(
+                "Component '{}' in '{}' is nested too deeply; do its Components form a cycle?",
+                content.name, interface.id
+            ));
+        }
+
+        writeln!(out, "{indent}    pub const SCHEMA: &str = {schema:?};").unwrap();
+        for child in &component_interface.contents {
+            let child_id = dtdl::composite_entity_id(id, &child.name)?;
+            generate_content(
+                component_interface,
+                child,
+                &child_id,
+                depth + 1,
+                interfaces,
+                out,
+            )?;
+        }
+    }
+
+    writeln!(out, "{indent}}}").unwrap();
+
+    Ok(())
+}
+
+/// Generate the `TYPE`, `WRITABLE`, `SEMANTIC_TYPE` and `UNIT` of a Property, Telemetry or
+/// Command. Only a Property can be writable.
+///
+/// # Arguments
+/// * `interface` - The Interface that contains the element.
+/// * `content` - The content element.
+/// * `indent` - The indentation of the element's module.
+/// * `out` - The generated code.
+fn generate_value(
+    interface: &Interface,
+    content: &Content,
+    indent: &str,
+    out: &mut String,
+) -> Result<(), String> {
+    let schema = if content.is(element_type::COMMAND) {
+        content.response.as_ref().map(|response| &response.schema)
+    } else {
+        Some(
+            content
+                .schema
+                .as_ref()
+                .ok_or_else(|| format!("'{}' in '{}' has no schema", content.name, interface.id))?,
+        )
+    };
+
+    let mut definitions = Vec::new();
+    let rust_type = match schema {
+        Some(schema) => rust_type(schema, "Value", &mut definitions)
+            .map_err(|err| format!("'{}' in '{}': {err}", content.name, interface.id))?,
+        None => "()".to_string(),
+    };
+
+    writeln!(out, "{indent}    pub type TYPE = {rust_type};").unwrap();
+    writeln!(
+        out,
+        "{indent}    pub const WRITABLE: bool = {};",
+        content.is(element_type::PROPERTY) && content.writable
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{indent}    pub const SEMANTIC_TYPE: Option<&str> = {:?};",
+        content.semantic_type()
+    )
+    .unwrap();
+    let unit = content
+        .unit()
+        .map_err(|err| format!("In '{}': {err}", interface.id))?;
+    match unit {
+        Some(unit) => writeln!(
+            out,
+            "{indent}    pub const UNIT: Option<crate::units::Unit> = Some(crate::units::Unit::{unit:?});"
+        ),
+        None => writeln!(
+            out,
+            "{indent}    pub const UNIT: Option<crate::units::Unit> = None;"
+        ),
+    }
+    .unwrap();
+    for definition in definitions {
+        writeln!(out).unwrap();
+        for line in definition.lines() {
+            writeln!(out, "{indent}    {line}").unwrap();
+        }
+    }
+
+    Ok(())
+}
+
+/// Read and parse a DTDL document.
+///
+/// # Arguments
+/// * `path` - The path to the DTDL document.
+fn parse_document(path: &Path) -> Result<Vec<Interface>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read '{}' due to '{err}'", path.display()))?;
+
+    dtdl::parse_interfaces(&json)
+        .map_err(|err| format!("Failed to parse '{}': {err}", path.display()))
+}
+
+/// Generate the Rust source for a DTDL document.
+///
+/// # Arguments
+/// * `path` - The path to the DTDL document.
+/// * `document` - The Interfaces in the DTDL document.
+/// * `interfaces` - The Interfaces in all DTDL documents, by DTMI.
+fn generate_document(
+    path: &Path,
+    document: &[Interface],
+    interfaces: &HashMap<String, Interface>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// This file is @generated by the digital-twin-model build script from '{}'.",
+        path.display()
+    )
+    .unwrap();
+    writeln!(out, "// Do not edit it; edit the DTDL document instead.").unwrap();
+
+    for interface in document {
+        writeln!(out).unwrap();
+        writeln!(out, "pub mod {} {{", interface_module_name(&interface.id)?).unwrap();
+        writeln!(out, "    pub const ID: &str = {:?};", interface.id).unwrap();
+        writeln!(
+            out,
+            "    pub const DESCRIPTION: &str = {:?};",
+            interface
+                .description
+                .as_ref()
+                .map(dtdl::LocalizedString::text)
+                .unwrap_or_default()
+        )
+        .unwrap();
+
+        for content in &interface.contents {
+            let id = content.entity_id(interface);
+            generate_content(interface, content, &id, 1, interfaces, &mut out)?;
+        }
+
+        writeln!(out, "}}").unwrap();
+    }
+
+    Ok(out)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    println!("cargo:rerun-if-changed=src/dtdl.rs");
+    println!("cargo:rerun-if-changed=src/dtmi.rs");
+    println!("cargo:rerun-if-changed=src/units.rs");
+    println!("cargo:rerun-if-changed={DTDL_DIR}");
+
+    let mut documents: Vec<PathBuf> = fs::read_dir(DTDL_DIR)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    documents.sort();
+
+    // The documents are also embedded in the crate so that they can be loaded at runtime.
+    let mut embedded = String::new();
+    writeln!(
+        embedded,
+        "// This file is @generated by the digital-twin-model build script."
+    )?;
+    writeln!(embedded)?;
+    writeln!(
+        embedded,
+        "/// The DTDL documents in the crate's `dtdl` directory, as (name, contents) pairs."
+    )?;
+    writeln!(embedded, "pub const DTDL_DOCUMENTS: &[(&str, &str)] = &[")?;
+
+    // Components may refer to Interfaces in other documents, so all documents are parsed first.
+    let mut parsed = Vec::new();
+    let mut interfaces = HashMap::new();
+    for document in documents {
+        println!("cargo:rerun-if-changed={}", document.display());
+
+        let document_interfaces = parse_document(&document)?;
+        for interface in &document_interfaces {
+            if interfaces
+                .insert(interface.id.clone(), interface.clone())
+                .is_some()
+            {
+                return Err(
+                    format!("Interface '{}' is defined more than once", interface.id).into(),
+                );
+            }
+        }
+        parsed.push((document, document_interfaces));
+    }
+
+    for (document, document_interfaces) in parsed {
+        let stem = document
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("Invalid DTDL file name '{}'", document.display()))?;
+        let source = generate_document(&document, &document_interfaces, &interfaces)?;
+        fs::write(out_dir.join(format!("{stem}.rs")), source)?;
+
+        let absolute_path = fs::canonicalize(&document)?;
+        writeln!(
+            embedded,
+            "    ({stem:?}, include_str!({:?})),",
+            absolute_path.display()
+        )?;
+    }
+
+    writeln!(embedded, "];")?;
+    fs::write(out_dir.join("dtdl_documents.rs"), embedded)?;
+
+    Ok(())
+}
@@ -0,0 +1,459 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Object model for the subset of DTDL v3 used by the smart trailer models.
+//!
+//! This module is shared with the crate's build script, which uses it to generate the model
+//! modules, so it must only depend on `serde`, `serde_derive`, `serde_json` and the `dtmi` and
+//! `units` modules.
+
+use std::collections::HashMap;
+
+use serde::Deserializer;
+use serde_derive::Deserialize;
+
+use crate::dtmi::Dtmi;
+use crate::units::{self, Unit};
+
+/// The only DTDL context supported by this parser.
+pub const DTDL_V3_CONTEXT: &str = "dtmi:dtdl:context;3";
+
+/// DTDL element types.
+pub mod element_type {
+    pub const INTERFACE: &str = "Interface";
+    pub const PROPERTY: &str = "Property";
+    pub const TELEMETRY: &str = "Telemetry";
+    pub const COMMAND: &str = "Command";
+    pub const RELATIONSHIP: &str = "Relationship";
+    pub const COMPONENT: &str = "Component";
+    pub const ENUM: &str = "Enum";
+    pub const OBJECT: &str = "Object";
+    pub const ARRAY: &str = "Array";
+    pub const MAP: &str = "Map";
+
+    /// The element types of an Interface's contents.
+    pub const CONTENT: &[&str] = &[PROPERTY, TELEMETRY, COMMAND, RELATIONSHIP, COMPONENT];
+}
+
+/// DTDL primitive schemas.
+pub mod primitive_schema {
+    pub const BOOLEAN: &str = "boolean";
+    pub const DATE: &str = "date";
+    pub const DATE_TIME: &str = "dateTime";
+    pub const DOUBLE: &str = "double";
+    pub const DURATION: &str = "duration";
+    pub const FLOAT: &str = "float";
+    pub const INTEGER: &str = "integer";
+    pub const LONG: &str = "long";
+    pub const STRING: &str = "string";
+    pub const TIME: &str = "time";
+}
+
+/// Deserialize a JSON-LD value that may either be a single item or an array of items.
+///
+/// # Arguments
+/// * `deserializer` - The deserializer.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(
+        match <OneOrMany<T> as serde::Deserialize>::deserialize(deserializer)? {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        },
+    )
+}
+
+/// A DTDL string that may be localized.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum LocalizedString {
+    Plain(String),
+    Localized(HashMap<String, String>),
+}
+
+impl LocalizedString {
+    /// Get the text of this string, preferring the English localization.
+    pub fn text(&self) -> &str {
+        match self {
+            LocalizedString::Plain(text) => text,
+            LocalizedString::Localized(map) => map
+                .get("en")
+                .or_else(|| map.values().next())
+                .map(String::as_str)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A DTDL Interface.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Interface {
+    #[serde(rename = "@context", deserialize_with = "one_or_many", default)]
+    pub context: Vec<String>,
+    #[serde(rename = "@type")]
+    pub r#type: String,
+    #[serde(rename = "@id")]
+    pub id: String,
+    pub display_name: Option<LocalizedString>,
+    pub description: Option<LocalizedString>,
+    pub comment: Option<String>,
+    #[serde(deserialize_with = "one_or_many", default)]
+    pub extends: Vec<String>,
+    #[serde(default)]
+    pub contents: Vec<Content>,
+}
+
+/// An element of an Interface's contents: a Property, Telemetry, Command, Relationship or
+/// Component.
+///
+/// DTDL allows an element to carry semantic types next to its element type (for example
+/// `["Property", "Mass"]`), so the types are kept as a list rather than used as a serde tag.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    #[serde(rename = "@type", deserialize_with = "one_or_many")]
+    pub types: Vec<String>,
+    #[serde(rename = "@id")]
+    pub id: Option<String>,
+    pub name: String,
+    pub display_name: Option<LocalizedString>,
+    pub description: Option<LocalizedString>,
+    pub comment: Option<String>,
+    pub schema: Option<Schema>,
+    #[serde(default)]
+    pub writable: bool,
+    pub request: Option<CommandPayload>,
+    pub response: Option<CommandPayload>,
+    pub unit: Option<String>,
+    pub target: Option<String>,
+    pub min_multiplicity: Option<u32>,
+    pub max_multiplicity: Option<u32>,
+}
+
+impl Content {
+    /// Does this content element have the provided element type?
+    ///
+    /// # Arguments
+    /// * `element_type` - The element type, for example `Property`.
+    pub fn is(&self, element_type: &str) -> bool {
+        self.types.iter().any(|t| t == element_type)
+    }
+
+    /// Get the DTMI of this element. Elements that do not declare an `@id` get one derived from
+    /// the Interface, by appending their name to its path. For example `TrailerWeight` in
+    /// `dtmi:sdv:Trailer;1` becomes `dtmi:sdv:Trailer:TrailerWeight;1`.
+    ///
+    /// # Arguments
+    /// * `interface` - The Interface that contains this element.
+    pub fn entity_id(&self, interface: &Interface) -> String {
+        if let Some(id) = &self.id {
+            return id.clone();
+        }
+
+        match interface.id.split_once(';') {
+            Some((path, version)) => format!("{path}:{};{version}", self.name),
+            None => format!("{}:{}", interface.id, self.name),
+        }
+    }
+
+    /// Get the element's semantic type, for example `Mass`, if it has one. This is the first type
+    /// that is not a DTDL element type.
+    pub fn semantic_type(&self) -> Option<&str> {
+        self.types
+            .iter()
+            .map(String::as_str)
+            .find(|t| !element_type::CONTENT.contains(t))
+    }
+
+    /// Get the element's unit of measure, if it declares one.
+    pub fn unit(&self) -> Result<Option<Unit>, String> {
+        self.unit
+            .as_deref()
+            .map(|unit| Unit::from_dtdl_name(unit).map_err(|err| format!("'{}': {err}", self.name)))
+            .transpose()
+    }
+
+    /// Get the DTMI of the Interface that a Component is an instance of.
+    pub fn component_schema(&self) -> Option<&str> {
+        match &self.schema {
+            Some(Schema::Named(schema)) if self.is(element_type::COMPONENT) => Some(schema),
+            _ => None,
+        }
+    }
+
+    /// Get the element's description, or an empty string if it has none.
+    pub fn description_text(&self) -> &str {
+        self.description
+            .as_ref()
+            .map(LocalizedString::text)
+            .unwrap_or_default()
+    }
+}
+
+/// The request or response of a Command.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandPayload {
+    pub name: String,
+    pub description: Option<LocalizedString>,
+    pub schema: Schema,
+}
+
+/// A DTDL schema: a primitive schema name, a reference to a schema by DTMI, or a complex schema.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Schema {
+    Named(String),
+    Complex(Box<ComplexSchema>),
+}
+
+/// A complex DTDL schema: an Enum, Object, Array or Map.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComplexSchema {
+    #[serde(rename = "@type")]
+    pub r#type: String,
+    #[serde(rename = "@id")]
+    pub id: Option<String>,
+    pub value_schema: Option<String>,
+    #[serde(default)]
+    pub enum_values: Vec<EnumValue>,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+    pub element_schema: Option<Schema>,
+    pub map_value: Option<Field>,
+}
+
+/// A value of an Enum schema.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnumValue {
+    pub name: String,
+    pub enum_value: serde_json::Value,
+    pub description: Option<LocalizedString>,
+}
+
+/// A field of an Object schema, or the value of a Map schema.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Field {
+    pub name: String,
+    pub description: Option<LocalizedString>,
+    pub schema: Schema,
+}
+
+/// Get the composite DTMI of an element of a Component's Interface, by appending the element's
+/// name to the Component's DTMI. For example `Pressure` in the `LeftTire` Component with DTMI
+/// `dtmi:sdv:Trailer:FrontAxle:LeftTire;2` becomes `dtmi:sdv:Trailer:FrontAxle:LeftTire:Pressure;2`.
+/// This makes the DTMI unique for every instance of the Component.
+///
+/// # Arguments
+/// * `component_id` - The Component's DTMI.
+/// * `name` - The element's name.
+pub fn composite_entity_id(component_id: &str, name: &str) -> Result<String, String> {
+    Ok(Dtmi::parse(component_id)?.join(name)?.to_string())
+}
+
+/// Parse a DTDL document, which may contain either a single Interface or an array of them.
+///
+/// # Arguments
+/// * `json` - The contents of the DTDL document.
+pub fn parse_interfaces(json: &str) -> Result<Vec<Interface>, String> {
+    #[derive(Deserialize)]
+    struct Document(#[serde(deserialize_with = "one_or_many")] Vec<Interface>);
+
+    let Document(interfaces) = serde_json::from_str(json)
+        .map_err(|err| format!("Failed to parse the DTDL document due to '{err}'"))?;
+
+    for interface in &interfaces {
+        if interface.r#type != element_type::INTERFACE {
+            return Err(format!(
+                "Element '{}' has type '{}', expected '{}'",
+                interface.id,
+                interface.r#type,
+                element_type::INTERFACE
+            ));
+        }
+
+        if !interface.context.iter().any(|c| c == DTDL_V3_CONTEXT) {
+            return Err(format!(
+                "Interface '{}' does not use the '{DTDL_V3_CONTEXT}' context",
+                interface.id
+            ));
+        }
+
+        Dtmi::parse(&interface.id)?;
+        for content in &interface.contents {
+            Dtmi::parse(&content.entity_id(interface))?;
+            validate_semantic_type(interface, content)?;
+            validate_link(interface, content)?;
+        }
+    }
+
+    Ok(interfaces)
+}
+
+/// Check that a Component refers to an Interface by DTMI, and that a Relationship's target is
+/// a valid DTMI.
+///
+/// # Arguments
+/// * `interface` - The Interface that contains the element.
+/// * `content` - The element.
+fn validate_link(interface: &Interface, content: &Content) -> Result<(), String> {
+    if content.is(element_type::COMPONENT) {
+        let schema = content.component_schema().ok_or_else(|| {
+            format!(
+                "Component '{}' in '{}' must refer to an Interface by DTMI",
+                content.name, interface.id
+            )
+        })?;
+        Dtmi::parse(schema)
+            .map_err(|err| format!("Component '{}' in '{}': {err}", content.name, interface.id))?;
+    }
+
+    if content.is(element_type::RELATIONSHIP) {
+        if let Some(target) = &content.target {
+            Dtmi::parse(target).map_err(|err| {
+                format!(
+                    "Relationship '{}' in '{}': {err}",
+                    content.name, interface.id
+                )
+            })?;
+        }
+
+        if let (Some(min), Some(max)) = (content.min_multiplicity, content.max_multiplicity) {
+            if min > max {
+                return Err(format!(
+                    "Relationship '{}' in '{}' has a minMultiplicity greater than its maxMultiplicity",
+                    content.name, interface.id
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that an element's semantic type and unit are supported and consistent with each other
+/// and with its schema.
+///
+/// # Arguments
+/// * `interface` - The Interface that contains the element.
+/// * `content` - The element.
+fn validate_semantic_type(interface: &Interface, content: &Content) -> Result<(), String> {
+    let semantic_type = match content.semantic_type() {
+        Some(semantic_type) => semantic_type,
+        None if content.unit.is_some() => {
+            return Err(format!(
+                "'{}' in '{}' has a unit but no semantic type",
+                content.name, interface.id
+            ))
+        }
+        None => return Ok(()),
+    };
+
+    if !interface
+        .context
+        .iter()
+        .any(|c| c == units::QUANTITATIVE_TYPES_CONTEXT)
+    {
+        return Err(format!(
+            "Interface '{}' uses the semantic type '{semantic_type}' without the '{}' context",
+            interface.id,
+            units::QUANTITATIVE_TYPES_CONTEXT
+        ));
+    }
+
+    if !units::semantic_type::ALL.contains(&semantic_type) {
+        return Err(format!(
+            "'{}' in '{}' has the unsupported semantic type '{semantic_type}'",
+            content.name, interface.id
+        ));
+    }
+
+    let unit = content
+        .unit()
+        .map_err(|err| format!("In '{}': {err}", interface.id))?
+        .ok_or_else(|| {
+            format!(
+                "'{}' in '{}' has the semantic type '{semantic_type}' but no unit",
+                content.name, interface.id
+            )
+        })?;
+
+    if unit.semantic_type() != semantic_type {
+        return Err(format!(
+            "'{}' in '{}' has the unit '{}', which does not measure '{semantic_type}'",
+            content.name,
+            interface.id,
+            unit.as_str()
+        ));
+    }
+
+    let is_numeric = matches!(
+        &content.schema,
+        Some(Schema::Named(name)) if [
+            primitive_schema::INTEGER,
+            primitive_schema::LONG,
+            primitive_schema::DOUBLE,
+            primitive_schema::FLOAT,
+        ]
+        .contains(&name.as_str())
+    );
+    if !is_numeric {
+        return Err(format!(
+            "'{}' in '{}' has a unit, so its schema must be numeric",
+            content.name, interface.id
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document with a trailer weight in the provided unit.
+    fn weight_document(unit: &str) -> String {
+        format!(
+            r#"{{
+                "@context": ["dtmi:dtdl:context;3", "dtmi:dtdl:extension:quantitativeTypes;1"],
+                "@type": "Interface",
+                "@id": "dtmi:sdv:Trailer;1",
+                "contents": [
+                    {{
+                        "@type": ["Property", "Mass"],
+                        "name": "Weight",
+                        "schema": "double",
+                        "unit": "{unit}"
+                    }}
+                ]
+            }}"#
+        )
+    }
+
+    #[test]
+    fn parse_interfaces_accepts_dtdl_unit_names() {
+        let interfaces = parse_interfaces(&weight_document("kilogram")).unwrap();
+
+        assert_eq!(interfaces[0].contents[0].unit(), Ok(Some(Unit::Kilogram)));
+    }
+
+    #[test]
+    fn parse_interfaces_rejects_unit_symbols() {
+        assert!(parse_interfaces(&weight_document("kg")).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Digital Twin Model Identifiers (DTMI), as defined by DTDL v3:
+//!
+//! ```text
+//! dtmi:<segment>[:<segment>]*[;<major>[.<minor>]]
+//! ```
+//!
+//! Each path segment starts with a letter, contains only letters, digits and underscores, and
+//! does not end with an underscore. The major version is in the range 1 to 999999999 and the
+//! minor version in the range 1 to 999999, both without leading zeros.
+//!
+//! This module is shared with the crate's build script, so it must only depend on `serde`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::Error as _;
+use serde::{Deserializer, Serializer};
+
+const SCHEME: &str = "dtmi:";
+const MAX_LENGTH: usize = 4096;
+const MAX_MAJOR_VERSION: u32 = 999_999_999;
+const MAX_MINOR_VERSION: u32 = 999_999;
+
+/// The version of a DTMI.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DtmiVersion {
+    pub major: u32,
+    pub minor: Option<u32>,
+}
+
+impl fmt::Display for DtmiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.minor {
+            Some(minor) => write!(f, "{}.{minor}", self.major),
+            None => write!(f, "{}", self.major),
+        }
+    }
+}
+
+/// A validated Digital Twin Model Identifier.
+///
+/// DTMIs are ordered by path and then by version, so the versions of the same model sort
+/// next to each other.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Dtmi {
+    segments: Vec<String>,
+    version: Option<DtmiVersion>,
+}
+
+impl Dtmi {
+    /// Parse and validate a DTMI.
+    ///
+    /// # Arguments
+    /// * `value` - The DTMI string, for example `dtmi:sdv:Trailer:Weight;1`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value.len() > MAX_LENGTH {
+            return Err(format!(
+                "'{value}' is not a valid DTMI: it is longer than {MAX_LENGTH} characters"
+            ));
+        }
+
+        let rest = value.strip_prefix(SCHEME).ok_or_else(|| {
+            format!("'{value}' is not a valid DTMI: it must start with '{SCHEME}'")
+        })?;
+
+        let (path, version) = match rest.split_once(';') {
+            Some((path, version)) => (path, Some(version)),
+            None => (rest, None),
+        };
+
+        let segments = path
+            .split(':')
+            .map(|segment| {
+                if is_valid_segment(segment) {
+                    Ok(segment.to_string())
+                } else {
+                    Err(format!(
+                        "'{value}' is not a valid DTMI: '{segment}' is not a valid path segment"
+                    ))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let version = version
+            .map(|version| {
+                parse_version(version).ok_or_else(|| {
+                    format!("'{value}' is not a valid DTMI: '{version}' is not a valid version")
+                })
+            })
+            .transpose()?;
+
+        Ok(Dtmi { segments, version })
+    }
+
+    /// Create a DTMI that has the same version as this one, with a segment appended to its path.
+    /// For example `dtmi:sdv:Trailer;1` with `Weight` becomes `dtmi:sdv:Trailer:Weight;1`.
+    ///
+    /// # Arguments
+    /// * `segment` - The segment to append.
+    pub fn join(&self, segment: &str) -> Result<Self, String> {
+        if !is_valid_segment(segment) {
+            return Err(format!("'{segment}' is not a valid DTMI path segment"));
+        }
+
+        let mut segments = self.segments.clone();
+        segments.push(segment.to_string());
+
+        Ok(Dtmi {
+            segments,
+            version: self.version,
+        })
+    }
+
+    /// The path segments, for example `["sdv", "Trailer", "Weight"]`.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// The last path segment, for example `Weight`.
+    pub fn name(&self) -> &str {
+        // A parsed DTMI always has at least one segment.
+        self.segments.last().map(String::as_str).unwrap_or_default()
+    }
+
+    /// The path without scheme and version, for example `sdv:Trailer:Weight`.
+    pub fn path(&self) -> String {
+        self.segments.join(":")
+    }
+
+    /// The version, if the DTMI has one.
+    pub fn version(&self) -> Option<DtmiVersion> {
+        self.version
+    }
+
+    /// The major version, if the DTMI has one.
+    pub fn major_version(&self) -> Option<u32> {
+        self.version.map(|version| version.major)
+    }
+
+    /// Does this DTMI identify the same model as another one, ignoring the version?
+    ///
+    /// # Arguments
+    /// * `other` - The other DTMI.
+    pub fn same_path(&self, other: &Dtmi) -> bool {
+        self.segments == other.segments
+    }
+
+    /// Can this DTMI be used where `required` is expected? This is the case when both identify
+    /// the same model with the same major version, and this DTMI's minor version is at least the
+    /// required one. A DTMI without a version is only compatible with another unversioned DTMI.
+    ///
+    /// # Arguments
+    /// * `required` - The required DTMI.
+    pub fn is_compatible_with(&self, required: &Dtmi) -> bool {
+        if !self.same_path(required) {
+            return false;
+        }
+
+        match (self.version, required.version) {
+            (Some(provided), Some(required)) => {
+                provided.major == required.major
+                    && provided.minor.unwrap_or(0) >= required.minor.unwrap_or(0)
+            }
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Is the provided string a valid DTMI path segment?
+///
+/// # Arguments
+/// * `segment` - The segment.
+fn is_valid_segment(segment: &str) -> bool {
+    let mut chars = segment.chars();
+
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !segment.ends_with('_')
+}
+
+/// Parse a DTMI version of the form `<major>[.<minor>]`.
+///
+/// # Arguments
+/// * `version` - The version string.
+fn parse_version(version: &str) -> Option<DtmiVersion> {
+    let (major, minor) = match version.split_once('.') {
+        Some((major, minor)) => (major, Some(minor)),
+        None => (version, None),
+    };
+
+    let major = parse_version_number(major, MAX_MAJOR_VERSION)?;
+    let minor = match minor {
+        Some(minor) => Some(parse_version_number(minor, MAX_MINOR_VERSION)?),
+        None => None,
+    };
+
+    Some(DtmiVersion { major, minor })
+}
+
+/// Parse a version number without a leading zero and in the range 1 to `max`.
+///
+/// # Arguments
+/// * `number` - The version number string.
+/// * `max` - The maximum value.
+fn parse_version_number(number: &str, max: u32) -> Option<u32> {
+    if number.is_empty() || number.starts_with('0') || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    number
+        .parse()
+        .ok()
+        .filter(|value| (1..=max).contains(value))
+}
+
+impl FromStr for Dtmi {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Dtmi::parse(value)
+    }
+}
+
+impl TryFrom<&str> for Dtmi {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Dtmi::parse(value)
+    }
+}
+
+impl fmt::Display for Dtmi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{SCHEME}{}", self.path())?;
+        if let Some(version) = self.version {
+            write!(f, ";{version}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl serde::Serialize for Dtmi {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Dtmi {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+        Dtmi::parse(&value).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_valid_dtmis() {
+        for value in [
+            "dtmi:sdv:Trailer",
+            "dtmi:sdv:Trailer;1",
+            "dtmi:sdv:Trailer:Weight;2",
+            "dtmi:sdv:Trailer:Front_Axle2;1.5",
+            "dtmi:a;999999999.999999",
+        ] {
+            assert!(Dtmi::parse(value).is_ok(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_dtmis() {
+        for value in [
+            "",
+            "dtmi:",
+            "sdv:Trailer;1",
+            "DTMI:sdv:Trailer;1",
+            "dtmi:sdv::Trailer;1",
+            "dtmi:sdv:2Trailer;1",
+            "dtmi:sdv:Trailer_;1",
+            "dtmi:sdv:Trai-ler;1",
+            "dtmi:sdv:Trailer;",
+            "dtmi:sdv:Trailer;0",
+            "dtmi:sdv:Trailer;01",
+            "dtmi:sdv:Trailer;1.0",
+            "dtmi:sdv:Trailer;1.2.3",
+            "dtmi:sdv:Trailer;1000000000",
+            "dtmi:sdv:Trailer;1.1000000",
+            "dtmi:sdv:Trailer;+1",
+        ] {
+            assert!(Dtmi::parse(value).is_err(), "{value}");
+        }
+
+        assert!(Dtmi::parse(&format!("dtmi:{};1", "a".repeat(MAX_LENGTH))).is_err());
+    }
+
+    #[test]
+    fn parse_splits_path_and_version() {
+        let dtmi = Dtmi::parse("dtmi:sdv:Trailer:Weight;2.3").unwrap();
+
+        assert_eq!(dtmi.segments(), ["sdv", "Trailer", "Weight"]);
+        assert_eq!(dtmi.name(), "Weight");
+        assert_eq!(dtmi.path(), "sdv:Trailer:Weight");
+        assert_eq!(
+            dtmi.version(),
+            Some(DtmiVersion {
+                major: 2,
+                minor: Some(3)
+            })
+        );
+        assert_eq!(dtmi.major_version(), Some(2));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for value in [
+            "dtmi:sdv:Trailer",
+            "dtmi:sdv:Trailer:Weight;2",
+            "dtmi:sdv:Trailer:Weight;2.3",
+        ] {
+            let dtmi = Dtmi::parse(value).unwrap();
+
+            assert_eq!(dtmi.to_string(), value);
+            assert_eq!(Dtmi::parse(&dtmi.to_string()), Ok(dtmi));
+        }
+    }
+
+    #[test]
+    fn serde_round_trips_and_validates() {
+        let dtmi = Dtmi::parse("dtmi:sdv:Trailer:Weight;2").unwrap();
+        let json = serde_json::to_string(&dtmi).unwrap();
+
+        assert_eq!(json, r#""dtmi:sdv:Trailer:Weight;2""#);
+        assert_eq!(serde_json::from_str::<Dtmi>(&json).unwrap(), dtmi);
+        assert!(serde_json::from_str::<Dtmi>(r#""dtmi:sdv:Trailer;0""#).is_err());
+    }
+
+    #[test]
+    fn join_keeps_the_version() {
+        let dtmi = Dtmi::parse("dtmi:sdv:Trailer;2").unwrap();
+
+        assert_eq!(
+            dtmi.join("Weight").unwrap().to_string(),
+            "dtmi:sdv:Trailer:Weight;2"
+        );
+        assert!(dtmi.join("Weight;3").is_err());
+    }
+
+    #[test]
+    fn compatibility_requires_the_same_major_and_at_least_the_minor_version() {
+        let compatible = |provided: &str, required: &str| {
+            Dtmi::parse(provided)
+                .unwrap()
+                .is_compatible_with(&Dtmi::parse(required).unwrap())
+        };
+
+        assert!(compatible("dtmi:sdv:Trailer;2", "dtmi:sdv:Trailer;2"));
+        assert!(compatible("dtmi:sdv:Trailer;2.1", "dtmi:sdv:Trailer;2"));
+        assert!(compatible("dtmi:sdv:Trailer;2.3", "dtmi:sdv:Trailer;2.2"));
+        assert!(compatible("dtmi:sdv:Trailer", "dtmi:sdv:Trailer"));
+        assert!(!compatible("dtmi:sdv:Trailer;2", "dtmi:sdv:Trailer;2.1"));
+        assert!(!compatible("dtmi:sdv:Trailer;3", "dtmi:sdv:Trailer;2"));
+        assert!(!compatible("dtmi:sdv:Trailer;1", "dtmi:sdv:Trailer;2"));
+        assert!(!compatible("dtmi:sdv:Trailer", "dtmi:sdv:Trailer;1"));
+        assert!(!compatible("dtmi:sdv:Trailer;1", "dtmi:sdv:Trailer"));
+        assert!(!compatible("dtmi:sdv:Vehicle;1", "dtmi:sdv:Trailer;1"));
+    }
+
+    #[test]
+    fn dtmis_sort_by_path_then_version() {
+        let mut dtmis: Vec<Dtmi> = [
+            "dtmi:sdv:Trailer;2",
+            "dtmi:sdv:Axle;1",
+            "dtmi:sdv:Trailer;1",
+        ]
+        .into_iter()
+        .map(|value| Dtmi::parse(value).unwrap())
+        .collect();
+        dtmis.sort();
+
+        let sorted: Vec<String> = dtmis.iter().map(Dtmi::to_string).collect();
+        assert_eq!(
+            sorted,
+            [
+                "dtmi:sdv:Axle;1",
+                "dtmi:sdv:Trailer;1",
+                "dtmi:sdv:Trailer;2"
+            ]
+        );
+    }
+}
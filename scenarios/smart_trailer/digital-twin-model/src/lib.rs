@@ -2,12 +2,24 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod dtdl;
+pub mod dtmi;
+pub mod model_mapping;
+pub mod model_registry;
+pub mod property_envelope;
 pub mod trailer_v1;
+pub mod trailer_v2;
+pub mod units;
+pub mod vehicle_v1;
 
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::units::Unit;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Metadata {
     #[serde(rename = "$model")]
     pub model: String,
+    #[serde(rename = "$unit", default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<Unit>,
 }
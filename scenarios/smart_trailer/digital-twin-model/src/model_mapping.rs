@@ -0,0 +1,253 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Declared mappings between the entities of different model versions.
+//!
+//! A mapping converts a value of one entity, for example the v2 trailer weight in grams, to a
+//! value of another entity, for example the v1 trailer weight in kilograms. This lets a consumer
+//! of one model version keep working against a provider of another version.
+
+use serde_json::Value;
+
+use crate::property_envelope::PropertyEnvelope;
+use crate::units::Unit;
+use crate::{trailer_v1, trailer_v2};
+
+/// A declared mapping from the value of one entity to the value of another entity.
+#[derive(Debug)]
+pub struct PropertyMapping {
+    /// The DTMI of the source entity.
+    pub from_id: &'static str,
+    /// The DTMI of the target entity.
+    pub to_id: &'static str,
+    /// The name of the target entity.
+    pub to_name: &'static str,
+    /// The unit of measure of the target entity.
+    pub to_unit: Option<Unit>,
+    /// Converts a source value to a target value.
+    pub convert: fn(&Value) -> Result<Value, String>,
+}
+
+/// The mappings between the trailer model versions.
+pub const PROPERTY_MAPPINGS: &[PropertyMapping] = &[
+    PropertyMapping {
+        from_id: trailer_v2::trailer::weight::ID,
+        to_id: trailer_v1::trailer::trailer_weight::ID,
+        to_name: trailer_v1::trailer::trailer_weight::NAME,
+        to_unit: trailer_v1::trailer::trailer_weight::UNIT,
+        convert: grams_to_kilograms,
+    },
+    PropertyMapping {
+        from_id: trailer_v1::trailer::trailer_weight::ID,
+        to_id: trailer_v2::trailer::weight::ID,
+        to_name: trailer_v2::trailer::weight::NAME,
+        to_unit: trailer_v2::trailer::weight::UNIT,
+        convert: kilograms_to_grams,
+    },
+    PropertyMapping {
+        from_id: trailer_v2::trailer::is_trailer_connected::ID,
+        to_id: trailer_v1::trailer::is_trailer_connected::ID,
+        to_name: trailer_v1::trailer::is_trailer_connected::NAME,
+        to_unit: trailer_v1::trailer::is_trailer_connected::UNIT,
+        convert: identity,
+    },
+    PropertyMapping {
+        from_id: trailer_v1::trailer::is_trailer_connected::ID,
+        to_id: trailer_v2::trailer::is_trailer_connected::ID,
+        to_name: trailer_v2::trailer::is_trailer_connected::NAME,
+        to_unit: trailer_v2::trailer::is_trailer_connected::UNIT,
+        convert: identity,
+    },
+];
+
+/// Find the mapping from one entity to another.
+///
+/// # Arguments
+/// * `from_id` - The DTMI of the source entity.
+/// * `to_id` - The DTMI of the target entity.
+pub fn find_mapping(from_id: &str, to_id: &str) -> Option<&'static PropertyMapping> {
+    PROPERTY_MAPPINGS
+        .iter()
+        .find(|mapping| mapping.from_id == from_id && mapping.to_id == to_id)
+}
+
+/// Get the DTMIs of the entities whose values can be mapped to the provided entity.
+///
+/// # Arguments
+/// * `to_id` - The DTMI of the target entity.
+pub fn compatible_ids(to_id: &str) -> impl Iterator<Item = &'static str> + '_ {
+    PROPERTY_MAPPINGS
+        .iter()
+        .filter(move |mapping| mapping.to_id == to_id)
+        .map(|mapping| mapping.from_id)
+}
+
+/// Convert a value of one entity to a value of another entity.
+///
+/// # Arguments
+/// * `from_id` - The DTMI of the source entity.
+/// * `to_id` - The DTMI of the target entity.
+/// * `value` - The source value.
+pub fn convert_value(from_id: &str, to_id: &str, value: &Value) -> Result<Value, String> {
+    if from_id == to_id {
+        return Ok(value.clone());
+    }
+
+    let mapping = find_mapping(from_id, to_id)
+        .ok_or_else(|| format!("There is no mapping from '{from_id}' to '{to_id}'"))?;
+
+    (mapping.convert)(value).map_err(|err| format!("Failed to map '{from_id}' to '{to_id}': {err}"))
+}
+
+/// Adapt a property envelope to another entity. An envelope that already carries the target
+/// entity is returned unchanged.
+///
+/// # Arguments
+/// * `envelope` - The envelope.
+/// * `to_id` - The DTMI of the target entity.
+pub fn adapt_envelope(
+    envelope: PropertyEnvelope<Value>,
+    to_id: &str,
+) -> Result<PropertyEnvelope<Value>, String> {
+    let from_id = envelope.metadata.model.as_str();
+    if from_id == to_id {
+        return Ok(envelope);
+    }
+
+    let value = convert_value(from_id, to_id, &envelope.value)?;
+    let mapping = find_mapping(from_id, to_id)
+        .expect("the value was converted, so there is a mapping to the target entity");
+
+    Ok(PropertyEnvelope::new(mapping.to_name, mapping.to_id, value).with_unit(mapping.to_unit))
+}
+
+/// Pass the value through unchanged.
+///
+/// # Arguments
+/// * `value` - The value.
+fn identity(value: &Value) -> Result<Value, String> {
+    Ok(value.clone())
+}
+
+/// Convert a weight in grams to a whole number of kilograms.
+///
+/// # Arguments
+/// * `value` - The weight in grams.
+fn grams_to_kilograms(value: &Value) -> Result<Value, String> {
+    let grams = value
+        .as_i64()
+        .ok_or_else(|| format!("expected a whole number of grams, got '{value}'"))?;
+    let kilograms = Unit::Gram.convert(grams as f64, Unit::Kilogram)?.round();
+    let kilograms = i32::try_from(kilograms as i64)
+        .map_err(|_| format!("{grams} g is out of range for a weight in kilograms"))?;
+
+    Ok(Value::from(kilograms))
+}
+
+/// Convert a whole number of kilograms to grams.
+///
+/// # Arguments
+/// * `value` - The weight in kilograms.
+fn kilograms_to_grams(value: &Value) -> Result<Value, String> {
+    let kilograms = value
+        .as_i64()
+        .ok_or_else(|| format!("expected a whole number of kilograms, got '{value}'"))?;
+    let grams = kilograms
+        .checked_mul(1000)
+        .ok_or_else(|| format!("{kilograms} kg is out of range for a weight in grams"))?;
+
+    Ok(Value::from(grams))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn grams_to_kilograms_rounds_to_the_nearest_kilogram() {
+        assert_eq!(grams_to_kilograms(&json!(1499)), Ok(json!(1)));
+        assert_eq!(grams_to_kilograms(&json!(1500)), Ok(json!(2)));
+        assert_eq!(grams_to_kilograms(&json!(-1500)), Ok(json!(-2)));
+        assert_eq!(grams_to_kilograms(&json!(0)), Ok(json!(0)));
+        assert!(grams_to_kilograms(&json!(1.5)).is_err());
+    }
+
+    #[test]
+    fn grams_to_kilograms_rejects_weights_beyond_i32() {
+        let max_grams = i64::from(i32::MAX) * 1000;
+
+        assert_eq!(grams_to_kilograms(&json!(max_grams)), Ok(json!(i32::MAX)));
+        assert!(grams_to_kilograms(&json!(max_grams + 1000)).is_err());
+        assert!(grams_to_kilograms(&json!(i64::MIN)).is_err());
+    }
+
+    #[test]
+    fn kilograms_to_grams_rejects_overflow() {
+        assert_eq!(kilograms_to_grams(&json!(1500)), Ok(json!(1_500_000)));
+        assert!(kilograms_to_grams(&json!(i64::MAX / 1000 + 1)).is_err());
+        assert!(kilograms_to_grams(&json!("1500")).is_err());
+    }
+
+    #[test]
+    fn compatible_ids_lists_the_mapped_sources() {
+        let ids: Vec<&str> = compatible_ids(trailer_v1::trailer::trailer_weight::ID).collect();
+
+        assert_eq!(ids, [trailer_v2::trailer::weight::ID]);
+        assert_eq!(compatible_ids("dtmi:sdv:Trailer:Unknown;1").count(), 0);
+    }
+
+    #[test]
+    fn convert_value_requires_a_mapping() {
+        let value = json!(true);
+
+        assert_eq!(
+            convert_value(
+                trailer_v2::trailer::weight::ID,
+                trailer_v2::trailer::weight::ID,
+                &value
+            ),
+            Ok(value.clone())
+        );
+        assert_eq!(
+            convert_value(
+                trailer_v2::trailer::weight::ID,
+                trailer_v1::trailer::is_trailer_connected::ID,
+                &value
+            ),
+            Err(format!(
+                "There is no mapping from '{}' to '{}'",
+                trailer_v2::trailer::weight::ID,
+                trailer_v1::trailer::is_trailer_connected::ID
+            ))
+        );
+    }
+
+    #[test]
+    fn adapt_envelope_converts_to_the_target_entity() {
+        let envelope = PropertyEnvelope::new(
+            trailer_v2::trailer::weight::NAME,
+            trailer_v2::trailer::weight::ID,
+            json!(1_500_000),
+        )
+        .with_unit(trailer_v2::trailer::weight::UNIT);
+
+        let adapted =
+            adapt_envelope(envelope.clone(), trailer_v1::trailer::trailer_weight::ID).unwrap();
+        assert_eq!(
+            adapted,
+            PropertyEnvelope::new(
+                trailer_v1::trailer::trailer_weight::NAME,
+                trailer_v1::trailer::trailer_weight::ID,
+                json!(1500)
+            )
+            .with_unit(trailer_v1::trailer::trailer_weight::UNIT)
+        );
+        assert_eq!(
+            adapt_envelope(envelope.clone(), trailer_v2::trailer::weight::ID),
+            Ok(envelope)
+        );
+    }
+}
@@ -0,0 +1,693 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A runtime registry of DTDL Interfaces, used to look up model entities by DTMI and to check
+//! that property values conform to their declared schema.
+//!
+//! The contents of a Component's Interface are registered with composite DTMIs, so that every
+//! part of a model, for example each tire of each trailer axle, is a separate entity. Walking
+//! the model from an Interface also follows its Relationships, so a consumer can go from the
+//! vehicle to each part of the trailer that it is towing.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::dtdl::{
+    self, element_type, primitive_schema, ComplexSchema, Content, Interface, Schema,
+};
+use crate::property_envelope::PropertyEnvelope;
+use crate::units::Unit;
+
+include!(concat!(env!("OUT_DIR"), "/dtdl_documents.rs"));
+
+/// A Property, Telemetry, Command, Relationship or Component of a registered Interface.
+#[derive(Clone, Debug)]
+pub struct Entity {
+    /// The entity's DTMI. Entities inside a Component have a composite DTMI.
+    pub id: String,
+    /// The DTMI of the Interface that declares the entity.
+    pub interface_id: String,
+    /// The DTMI of the Interface that the entity is part of. This differs from `interface_id`
+    /// for entities inside a Component.
+    pub root_interface_id: String,
+    /// The names of the Components that lead from the root Interface to the entity, followed by
+    /// the entity's name. For example `["FrontAxle", "LeftTire", "Pressure"]`.
+    pub path: Vec<String>,
+    /// The entity's DTDL definition.
+    pub content: Content,
+}
+
+impl Entity {
+    /// The schema that values of this entity must conform to, if it has one.
+    /// For a Command this is the response schema.
+    pub fn schema(&self) -> Option<&Schema> {
+        if self.content.is(element_type::COMMAND) {
+            self.content
+                .response
+                .as_ref()
+                .map(|response| &response.schema)
+        } else {
+            self.content.schema.as_ref()
+        }
+    }
+
+    /// The unit of measure of this entity's values, if it declares one.
+    pub fn unit(&self) -> Option<Unit> {
+        // The unit was validated when the Interface was parsed.
+        self.content.unit().ok().flatten()
+    }
+}
+
+/// An entity reached by walking the model from an Interface.
+#[derive(Clone, Debug)]
+pub struct EntityRoute<'a> {
+    /// The names of the Relationships and Components that lead from the Interface that the walk
+    /// started at to the entity, followed by the entity's name. For example
+    /// `["hasTrailer", "FrontAxle", "LeftTire", "Pressure"]`.
+    pub path: Vec<String>,
+    /// The entity.
+    pub entity: &'a Entity,
+}
+
+/// Registry of DTDL Interfaces and the entities that they declare.
+#[derive(Clone, Debug, Default)]
+pub struct ModelRegistry {
+    interfaces: HashMap<String, Interface>,
+    entities: HashMap<String, Entity>,
+}
+
+impl ModelRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry with the DTDL documents that are embedded in this crate.
+    pub fn with_builtin_models() -> Result<Self, String> {
+        let mut interfaces = Vec::new();
+        for (name, json) in DTDL_DOCUMENTS {
+            interfaces.extend(
+                dtdl::parse_interfaces(json)
+                    .map_err(|err| format!("Failed to load the '{name}' model: {err}"))?,
+            );
+        }
+
+        let mut registry = Self::new();
+        registry.add_interfaces(interfaces)?;
+
+        Ok(registry)
+    }
+
+    /// Load the Interfaces in a DTDL document.
+    ///
+    /// # Arguments
+    /// * `json` - The contents of the DTDL document.
+    pub fn load_str(&mut self, json: &str) -> Result<(), String> {
+        self.add_interfaces(dtdl::parse_interfaces(json)?)
+    }
+
+    /// Load the Interfaces in a DTDL file.
+    ///
+    /// # Arguments
+    /// * `path` - The path to the DTDL file.
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        self.add_interfaces(read_interfaces(path)?)
+            .map_err(|err| format!("Failed to load '{}': {err}", path.display()))
+    }
+
+    /// Load every DTDL file (`*.json`) in a directory.
+    ///
+    /// # Arguments
+    /// * `path` - The path to the directory.
+    pub fn load_dir(&mut self, path: &Path) -> Result<(), String> {
+        let entries = fs::read_dir(path)
+            .map_err(|err| format!("Failed to read '{}' due to '{err}'", path.display()))?;
+
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .collect();
+        files.sort();
+
+        // The entities are collected once for all files, rather than once per Interface.
+        let mut interfaces = Vec::new();
+        for file in files {
+            interfaces.extend(read_interfaces(&file)?);
+        }
+
+        self.add_interfaces(interfaces)
+            .map_err(|err| format!("Failed to load '{}': {err}", path.display()))
+    }
+
+    /// Add an Interface and its entities to the registry. The Interfaces of its Components may
+    /// be added before or after it.
+    ///
+    /// # Arguments
+    /// * `interface` - The Interface.
+    pub fn add_interface(&mut self, interface: Interface) -> Result<(), String> {
+        self.add_interfaces([interface])
+    }
+
+    /// Add Interfaces and their entities to the registry. Either all of them are added, or, if
+    /// any of them is invalid, none.
+    ///
+    /// # Arguments
+    /// * `interfaces` - The Interfaces.
+    pub fn add_interfaces(
+        &mut self,
+        interfaces: impl IntoIterator<Item = Interface>,
+    ) -> Result<(), String> {
+        let mut all_interfaces = self.interfaces.clone();
+        for interface in interfaces {
+            if all_interfaces.contains_key(&interface.id) {
+                return Err(format!(
+                    "Interface '{}' is already registered",
+                    interface.id
+                ));
+            }
+            all_interfaces.insert(interface.id.clone(), interface);
+        }
+
+        // Adding an Interface can complete the Components of other Interfaces, so the entities
+        // are collected again for all of them.
+        self.entities = collect_entities(&all_interfaces)?;
+        self.interfaces = all_interfaces;
+
+        Ok(())
+    }
+
+    /// Get an Interface by its DTMI.
+    ///
+    /// # Arguments
+    /// * `id` - The Interface's DTMI.
+    pub fn get_interface(&self, id: &str) -> Option<&Interface> {
+        self.interfaces.get(id)
+    }
+
+    /// Get an entity by its DTMI.
+    ///
+    /// # Arguments
+    /// * `id` - The entity's DTMI.
+    pub fn get_entity(&self, id: &str) -> Option<&Entity> {
+        self.entities.get(id)
+    }
+
+    /// Iterate over the registered Interfaces.
+    pub fn interfaces(&self) -> impl Iterator<Item = &Interface> {
+        self.interfaces.values()
+    }
+
+    /// Iterate over the registered entities.
+    pub fn entities(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
+    /// Walk the model from an Interface. This visits the Interface's entities, the entities
+    /// inside its Components, and the entities of the Interfaces that its Relationships target.
+    /// Each Interface is only visited once per route, so cyclic Relationships end the walk.
+    ///
+    /// # Arguments
+    /// * `interface_id` - The DTMI of the Interface to start at.
+    pub fn walk(&self, interface_id: &str) -> Result<Vec<EntityRoute<'_>>, String> {
+        let (interface_id, _) = self
+            .interfaces
+            .get_key_value(interface_id)
+            .ok_or_else(|| format!("Unknown Interface '{interface_id}'"))?;
+
+        let mut routes = Vec::new();
+        self.walk_from(interface_id, &[], &mut vec![interface_id], &mut routes);
+
+        Ok(routes)
+    }
+
+    /// Add the routes to the entities that are part of an Interface to `routes`, and walk on
+    /// through its Relationships.
+    ///
+    /// # Arguments
+    /// * `interface_id` - The DTMI of the Interface.
+    /// * `prefix` - The route to the Interface.
+    /// * `visited` - The Interfaces on the route to the Interface.
+    /// * `routes` - Collects the routes.
+    fn walk_from<'a>(
+        &'a self,
+        interface_id: &str,
+        prefix: &[String],
+        visited: &mut Vec<&'a str>,
+        routes: &mut Vec<EntityRoute<'a>>,
+    ) {
+        let mut entities: Vec<&Entity> = self
+            .entities
+            .values()
+            .filter(|entity| entity.root_interface_id == interface_id)
+            .collect();
+        entities.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for entity in entities {
+            let path: Vec<String> = prefix.iter().chain(&entity.path).cloned().collect();
+
+            let target = entity
+                .content
+                .target
+                .as_deref()
+                .filter(|_| entity.content.is(element_type::RELATIONSHIP))
+                .and_then(|target| self.interfaces.get_key_value(target))
+                .map(|(target, _)| target.as_str());
+
+            routes.push(EntityRoute {
+                path: path.clone(),
+                entity,
+            });
+
+            if let Some(target) = target {
+                if !visited.contains(&target) {
+                    visited.push(target);
+                    self.walk_from(target, &path, visited, routes);
+                    visited.pop();
+                }
+            }
+        }
+    }
+
+    /// Check that a value conforms to the schema of an entity.
+    ///
+    /// # Arguments
+    /// * `id` - The entity's DTMI.
+    /// * `value` - The value.
+    pub fn validate_value(&self, id: &str, value: &Value) -> Result<(), String> {
+        let entity = self
+            .get_entity(id)
+            .ok_or_else(|| format!("Unknown entity '{id}'"))?;
+        let schema = entity
+            .schema()
+            .ok_or_else(|| format!("Entity '{id}' does not have a schema"))?;
+
+        validate_schema(schema, value).map_err(|err| format!("Invalid value for '{id}': {err}"))
+    }
+
+    /// Check that a property envelope carries a registered entity, in that entity's unit of
+    /// measure, and that its value conforms to the entity's schema. An envelope without a unit
+    /// is taken to be in the entity's unit.
+    ///
+    /// # Arguments
+    /// * `envelope` - The envelope.
+    pub fn validate_envelope(&self, envelope: &PropertyEnvelope<Value>) -> Result<(), String> {
+        let id = envelope.metadata.model.as_str();
+        let entity = self
+            .get_entity(id)
+            .ok_or_else(|| format!("Unknown entity '{id}'"))?;
+        envelope.expect_entity(&entity.content.name, id)?;
+
+        if envelope.metadata.unit.is_some() && envelope.metadata.unit != entity.unit() {
+            return Err(format!(
+                "Expected '{id}' in {}, got {}",
+                unit_name(entity.unit()),
+                unit_name(envelope.metadata.unit)
+            ));
+        }
+
+        self.validate_value(id, &envelope.value)
+    }
+}
+
+/// Read the Interfaces in a DTDL file.
+///
+/// # Arguments
+/// * `path` - The path to the DTDL file.
+fn read_interfaces(path: &Path) -> Result<Vec<Interface>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read '{}' due to '{err}'", path.display()))?;
+
+    dtdl::parse_interfaces(&json)
+        .map_err(|err| format!("Failed to load '{}': {err}", path.display()))
+}
+
+/// Collect the entities of all Interfaces. The contents of a Component's Interface are collected
+/// with composite DTMIs, once for every Component. Components whose Interface is not
+/// registered yet are skipped.
+///
+/// # Arguments
+/// * `interfaces` - The Interfaces, by DTMI.
+fn collect_entities(
+    interfaces: &HashMap<String, Interface>,
+) -> Result<HashMap<String, Entity>, String> {
+    let mut entities = HashMap::new();
+    for interface in interfaces.values() {
+        collect_interface_entities(
+            interfaces,
+            interface,
+            interface,
+            None,
+            &[],
+            &mut HashSet::new(),
+            &mut entities,
+        )?;
+    }
+
+    Ok(entities)
+}
+
+/// Collect the entities of an Interface, and of the Interfaces of its Components.
+///
+/// # Arguments
+/// * `interfaces` - All Interfaces, by DTMI.
+/// * `root` - The Interface that the entities are part of.
+/// * `interface` - The Interface that declares the entities.
+/// * `component_id` - The DTMI of the Component that `interface` is the schema of, if any.
+/// * `prefix` - The names of the Components that lead from `root` to `interface`.
+/// * `components` - The Interfaces of the Components on the way to `interface`, to detect cycles.
+/// * `entities` - Collects the entities.
+fn collect_interface_entities(
+    interfaces: &HashMap<String, Interface>,
+    root: &Interface,
+    interface: &Interface,
+    component_id: Option<&str>,
+    prefix: &[String],
+    components: &mut HashSet<String>,
+    entities: &mut HashMap<String, Entity>,
+) -> Result<(), String> {
+    for content in &interface.contents {
+        let id = match component_id {
+            Some(component_id) => dtdl::composite_entity_id(component_id, &content.name)?,
+            None => content.entity_id(interface),
+        };
+        let path: Vec<String> = prefix
+            .iter()
+            .cloned()
+            .chain([content.name.clone()])
+            .collect();
+
+        if entities.contains_key(&id) {
+            return Err(format!("Entity '{id}' is already registered"));
+        }
+        entities.insert(
+            id.clone(),
+            Entity {
+                id: id.clone(),
+                interface_id: interface.id.clone(),
+                root_interface_id: root.id.clone(),
+                path: path.clone(),
+                content: content.clone(),
+            },
+        );
+
+        let component_interface = content
+            .component_schema()
+            .and_then(|schema| interfaces.get(schema));
+        if let Some(component_interface) = component_interface {
+            if !components.insert(component_interface.id.clone()) {
+                return Err(format!(
+                    "The Components of '{}' form a cycle through '{}'",
+                    root.id, component_interface.id
+                ));
+            }
+            collect_interface_entities(
+                interfaces,
+                root,
+                component_interface,
+                Some(&id),
+                &path,
+                components,
+                entities,
+            )?;
+            components.remove(&component_interface.id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the name of an optional unit for messages.
+///
+/// # Arguments
+/// * `unit` - The unit.
+fn unit_name(unit: Option<Unit>) -> &'static str {
+    unit.map_or("no unit", |unit| unit.as_str())
+}
+
+/// Check that a value conforms to a schema.
+///
+/// # Arguments
+/// * `schema` - The schema.
+/// * `value` - The value.
+pub fn validate_schema(schema: &Schema, value: &Value) -> Result<(), String> {
+    match schema {
+        Schema::Named(name) => validate_primitive(name, value),
+        Schema::Complex(complex) => validate_complex(complex, value),
+    }
+}
+
+/// Check that a value conforms to a primitive schema.
+///
+/// # Arguments
+/// * `schema` - The primitive schema's name.
+/// * `value` - The value.
+fn validate_primitive(schema: &str, value: &Value) -> Result<(), String> {
+    let is_valid = match schema {
+        primitive_schema::BOOLEAN => value.is_boolean(),
+        primitive_schema::INTEGER => value.as_i64().is_some_and(|v| i32::try_from(v).is_ok()),
+        primitive_schema::LONG => value.is_i64(),
+        primitive_schema::DOUBLE | primitive_schema::FLOAT => value.is_number(),
+        primitive_schema::STRING
+        | primitive_schema::DATE
+        | primitive_schema::DATE_TIME
+        | primitive_schema::DURATION
+        | primitive_schema::TIME => value.is_string(),
+        other => return Err(format!("unsupported schema '{other}'")),
+    };
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "expected a value of schema '{schema}', got '{value}'"
+        ))
+    }
+}
+
+/// Check that a value conforms to a complex schema.
+///
+/// # Arguments
+/// * `schema` - The complex schema.
+/// * `value` - The value.
+fn validate_complex(schema: &ComplexSchema, value: &Value) -> Result<(), String> {
+    match schema.r#type.as_str() {
+        element_type::ENUM => {
+            if let Some(value_schema) = &schema.value_schema {
+                validate_primitive(value_schema, value)?;
+            }
+
+            if schema.enum_values.iter().any(|v| &v.enum_value == value) {
+                Ok(())
+            } else {
+                let allowed: Vec<String> = schema
+                    .enum_values
+                    .iter()
+                    .map(|v| v.enum_value.to_string())
+                    .collect();
+                Err(format!(
+                    "'{value}' is not one of the enum values [{}]",
+                    allowed.join(", ")
+                ))
+            }
+        }
+        element_type::OBJECT => {
+            let object = value
+                .as_object()
+                .ok_or_else(|| format!("expected an object, got '{value}'"))?;
+
+            for (key, field_value) in object {
+                let field = schema
+                    .fields
+                    .iter()
+                    .find(|field| &field.name == key)
+                    .ok_or_else(|| format!("unknown field '{key}'"))?;
+                validate_schema(&field.schema, field_value)
+                    .map_err(|err| format!("field '{key}': {err}"))?;
+            }
+
+            Ok(())
+        }
+        element_type::ARRAY => {
+            let element_schema = schema
+                .element_schema
+                .as_ref()
+                .ok_or_else(|| "the Array schema has no elementSchema".to_string())?;
+            let elements = value
+                .as_array()
+                .ok_or_else(|| format!("expected an array, got '{value}'"))?;
+
+            for (index, element) in elements.iter().enumerate() {
+                validate_schema(element_schema, element)
+                    .map_err(|err| format!("element {index}: {err}"))?;
+            }
+
+            Ok(())
+        }
+        element_type::MAP => {
+            let map_value = schema
+                .map_value
+                .as_ref()
+                .ok_or_else(|| "the Map schema has no mapValue".to_string())?;
+            let map = value
+                .as_object()
+                .ok_or_else(|| format!("expected a map, got '{value}'"))?;
+
+            for (key, entry) in map {
+                validate_schema(&map_value.schema, entry)
+                    .map_err(|err| format!("key '{key}': {err}"))?;
+            }
+
+            Ok(())
+        }
+        other => Err(format!("unsupported complex schema '{other}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::trailer_v2::trailer;
+
+    /// A document with an Interface that has an integer and a long Property.
+    const COUNTER_DOCUMENT: &str = r#"{
+        "@context": ["dtmi:dtdl:context;3"],
+        "@type": "Interface",
+        "@id": "dtmi:test:Counter;1",
+        "contents": [
+            { "@type": "Property", "name": "Small", "schema": "integer" },
+            { "@type": "Property", "name": "Large", "schema": "long" }
+        ]
+    }"#;
+
+    fn builtin_registry() -> ModelRegistry {
+        ModelRegistry::with_builtin_models().unwrap()
+    }
+
+    #[test]
+    fn integers_must_fit_in_32_bits() {
+        let mut registry = ModelRegistry::new();
+        registry.load_str(COUNTER_DOCUMENT).unwrap();
+
+        let id = "dtmi:test:Counter:Small;1";
+        assert!(registry.validate_value(id, &json!(i32::MAX)).is_ok());
+        assert!(registry.validate_value(id, &json!(i32::MIN)).is_ok());
+        assert!(registry
+            .validate_value(id, &json!(i64::from(i32::MAX) + 1))
+            .is_err());
+        assert!(registry.validate_value(id, &json!(1.5)).is_err());
+    }
+
+    #[test]
+    fn longs_must_fit_in_64_bits() {
+        let mut registry = ModelRegistry::new();
+        registry.load_str(COUNTER_DOCUMENT).unwrap();
+
+        let id = "dtmi:test:Counter:Large;1";
+        assert!(registry.validate_value(id, &json!(i64::MAX)).is_ok());
+        assert!(registry.validate_value(id, &json!(u64::MAX)).is_err());
+        assert!(registry.validate_value(id, &json!("1")).is_err());
+    }
+
+    #[test]
+    fn enum_values_must_be_members() {
+        let registry = builtin_registry();
+        let id = trailer::lights::lighting_mode::ID;
+
+        assert!(registry.validate_value(id, &json!("hazard")).is_ok());
+        let err = registry.validate_value(id, &json!("strobe")).unwrap_err();
+        assert!(err.contains("is not one of the enum values"), "{err}");
+        assert!(registry.validate_value(id, &json!(1)).is_err());
+    }
+
+    #[test]
+    fn envelopes_must_use_the_entity_unit() {
+        let registry = builtin_registry();
+        let envelope = |unit| {
+            PropertyEnvelope::new(trailer::weight::NAME, trailer::weight::ID, json!(1500))
+                .with_unit(unit)
+        };
+
+        assert!(registry
+            .validate_envelope(&envelope(Some(Unit::Gram)))
+            .is_ok());
+        assert!(registry.validate_envelope(&envelope(None)).is_ok());
+        let err = registry
+            .validate_envelope(&envelope(Some(Unit::Kilogram)))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            format!("Expected '{}' in gram, got kilogram", trailer::weight::ID)
+        );
+    }
+
+    #[test]
+    fn envelopes_must_carry_a_registered_entity() {
+        let registry = builtin_registry();
+
+        let unknown = PropertyEnvelope::new("Weight", "dtmi:sdv:Trailer:Weight;9", json!(1));
+        assert_eq!(
+            registry.validate_envelope(&unknown),
+            Err("Unknown entity 'dtmi:sdv:Trailer:Weight;9'".to_string())
+        );
+
+        let misnamed = PropertyEnvelope::new("Mass", trailer::weight::ID, json!(1));
+        assert!(registry.validate_envelope(&misnamed).is_err());
+    }
+
+    #[test]
+    fn load_dir_loads_every_document() {
+        let mut registry = ModelRegistry::new();
+        registry
+            .load_dir(&Path::new(env!("CARGO_MANIFEST_DIR")).join("dtdl"))
+            .unwrap();
+
+        assert_eq!(
+            registry.entities().count(),
+            builtin_registry().entities().count()
+        );
+    }
+
+    #[test]
+    fn interfaces_cannot_be_registered_twice() {
+        let mut registry = ModelRegistry::new();
+        registry.load_str(COUNTER_DOCUMENT).unwrap();
+
+        assert!(registry.load_str(COUNTER_DOCUMENT).is_err());
+        assert_eq!(registry.interfaces().count(), 1);
+    }
+
+    #[test]
+    fn components_get_composite_ids() {
+        let registry = builtin_registry();
+        let pressure = registry
+            .get_entity(trailer::front_axle::left_tire::pressure::ID)
+            .unwrap();
+
+        assert_eq!(pressure.interface_id, "dtmi:sdv:Tire;1");
+        assert_eq!(pressure.root_interface_id, trailer::ID);
+        assert_eq!(pressure.path, ["FrontAxle", "LeftTire", "Pressure"]);
+        assert_eq!(pressure.unit(), Some(Unit::Kilopascal));
+    }
+
+    #[test]
+    fn walk_follows_relationships() {
+        let registry = builtin_registry();
+        let routes = registry.walk("dtmi:sdv:Vehicle;1").unwrap();
+
+        let weight = routes
+            .iter()
+            .find(|route| route.entity.id == trailer::weight::ID)
+            .unwrap();
+        assert_eq!(weight.path, ["hasTrailer", "Weight"]);
+        assert!(registry.walk("dtmi:sdv:Unknown;1").is_err());
+    }
+}
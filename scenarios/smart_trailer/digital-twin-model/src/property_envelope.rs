@@ -0,0 +1,300 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The JSON envelope that carries a property value, shared by providers and consumers.
+//!
+//! A property is encoded as an object with one member named after the model entity and a
+//! `$metadata` member, for example:
+//!
+//! ```json
+//! { "TrailerWeight": 1500, "$metadata": { "$model": "dtmi:sdv:Trailer:Weight;1", "$unit": "kilogram" } }
+//! ```
+//!
+//! The `$unit` member is only present for entities that declare a unit of measure.
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::SerializeMap;
+use serde::{Deserializer, Serializer};
+use serde_json::{Map, Value};
+
+use crate::units::Unit;
+use crate::Metadata;
+
+const METADATA_KEY: &str = "$metadata";
+
+/// A property value of a model entity, together with its metadata.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertyEnvelope<T> {
+    /// The entity's name, which is the name of the member that holds the value.
+    pub name: String,
+    /// The property value.
+    pub value: T,
+    /// The property's metadata.
+    pub metadata: Metadata,
+}
+
+impl<T> PropertyEnvelope<T> {
+    /// Create an envelope for a model entity's value.
+    ///
+    /// # Arguments
+    /// * `name` - The entity's name, for example `trailer_weight::NAME`.
+    /// * `model_id` - The entity's DTMI, for example `trailer_weight::ID`.
+    /// * `value` - The property value.
+    pub fn new(name: &str, model_id: &str, value: T) -> Self {
+        PropertyEnvelope {
+            name: name.to_string(),
+            value,
+            metadata: Metadata {
+                model: model_id.to_string(),
+                unit: None,
+            },
+        }
+    }
+
+    /// Set the unit of measure of the value, for example `trailer_weight::UNIT`.
+    ///
+    /// # Arguments
+    /// * `unit` - The unit, or `None` if the value does not have one.
+    pub fn with_unit(mut self, unit: Option<Unit>) -> Self {
+        self.metadata.unit = unit;
+        self
+    }
+
+    /// Check that this envelope carries the expected model entity.
+    ///
+    /// # Arguments
+    /// * `name` - The expected entity name.
+    /// * `model_id` - The expected entity DTMI.
+    pub fn expect_entity(&self, name: &str, model_id: &str) -> Result<(), String> {
+        if self.name != name {
+            return Err(format!("Expected a '{name}' property, got '{}'", self.name));
+        }
+
+        if self.metadata.model != model_id {
+            return Err(format!(
+                "Expected the '{model_id}' model, got '{}'",
+                self.metadata.model
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: serde::Serialize> PropertyEnvelope<T> {
+    /// Encode the envelope as JSON.
+    pub fn encode(&self) -> Result<String, String> {
+        serde_json::to_string(self).map_err(|err| {
+            format!(
+                "Failed to encode the '{}' property due to '{err}'",
+                self.name
+            )
+        })
+    }
+}
+
+impl<T: DeserializeOwned> PropertyEnvelope<T> {
+    /// Decode an envelope from JSON.
+    ///
+    /// # Arguments
+    /// * `payload` - The JSON payload.
+    pub fn decode(payload: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(payload)
+            .map_err(|err| format!("Failed to decode the property due to '{err}'"))
+    }
+
+    /// Decode an envelope from JSON and check that it carries the expected model entity.
+    ///
+    /// # Arguments
+    /// * `payload` - The JSON payload.
+    /// * `name` - The expected entity name.
+    /// * `model_id` - The expected entity DTMI.
+    pub fn decode_for(payload: &[u8], name: &str, model_id: &str) -> Result<Self, String> {
+        let envelope = Self::decode(payload)?;
+        envelope.expect_entity(name, model_id)?;
+
+        Ok(envelope)
+    }
+}
+
+impl PropertyEnvelope<Value> {
+    /// Get the value in the provided unit of measure.
+    ///
+    /// # Arguments
+    /// * `unit` - The unit to get the value in.
+    pub fn value_in(&self, unit: Unit) -> Result<f64, String> {
+        let from = self
+            .metadata
+            .unit
+            .ok_or_else(|| format!("The '{}' property does not have a unit", self.name))?;
+        let value = self.value.as_f64().ok_or_else(|| {
+            format!(
+                "The '{}' property is not numeric: '{}'",
+                self.name, self.value
+            )
+        })?;
+
+        from.convert(value, unit)
+    }
+
+    /// Create a copy of this envelope with the value converted to the provided unit of measure.
+    /// The converted value is a floating point number, so it may no longer conform to the
+    /// entity's schema.
+    ///
+    /// # Arguments
+    /// * `unit` - The unit to convert to.
+    pub fn convert_to(&self, unit: Unit) -> Result<Self, String> {
+        let value = self.value_in(unit)?;
+
+        Ok(
+            PropertyEnvelope::new(&self.name, &self.metadata.model, Value::from(value))
+                .with_unit(Some(unit)),
+        )
+    }
+}
+
+impl<T: serde::Serialize> serde::Serialize for PropertyEnvelope<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(&self.name, &self.value)?;
+        map.serialize_entry(METADATA_KEY, &self.metadata)?;
+        map.end()
+    }
+}
+
+impl<'de, T: DeserializeOwned> serde::Deserialize<'de> for PropertyEnvelope<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut object = <Map<String, Value> as serde::Deserialize>::deserialize(deserializer)?;
+
+        let metadata = object
+            .remove(METADATA_KEY)
+            .ok_or_else(|| D::Error::missing_field(METADATA_KEY))?;
+        let metadata: Metadata = serde_json::from_value(metadata).map_err(D::Error::custom)?;
+
+        let mut members = object.into_iter();
+        let (name, value) = match (members.next(), members.next()) {
+            (Some(member), None) => member,
+            _ => {
+                return Err(D::Error::custom(
+                    "expected exactly one property member next to '$metadata'",
+                ))
+            }
+        };
+        let value: T = serde_json::from_value(value).map_err(D::Error::custom)?;
+
+        Ok(PropertyEnvelope {
+            name,
+            value,
+            metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::trailer_v2::trailer::{is_trailer_connected, weight};
+
+    fn weight_envelope() -> PropertyEnvelope<i64> {
+        PropertyEnvelope::new(weight::NAME, weight::ID, 1500).with_unit(weight::UNIT)
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let envelope = weight_envelope();
+        let json = envelope.encode().unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<Value>(&json).unwrap(),
+            json!({
+                "Weight": 1500,
+                "$metadata": { "$model": weight::ID, "$unit": "gram" }
+            })
+        );
+        assert_eq!(
+            PropertyEnvelope::<i64>::decode(json.as_bytes()).unwrap(),
+            envelope
+        );
+    }
+
+    #[test]
+    fn the_unit_is_omitted_when_there_is_none() {
+        let json =
+            PropertyEnvelope::new(is_trailer_connected::NAME, is_trailer_connected::ID, true)
+                .encode()
+                .unwrap();
+
+        assert!(!json.contains("$unit"), "{json}");
+        assert_eq!(
+            PropertyEnvelope::<bool>::decode(json.as_bytes())
+                .unwrap()
+                .metadata
+                .unit,
+            None
+        );
+    }
+
+    #[test]
+    fn decode_requires_metadata() {
+        let err = PropertyEnvelope::<i64>::decode(br#"{ "Weight": 1500 }"#).unwrap_err();
+
+        assert!(err.contains("$metadata"), "{err}");
+    }
+
+    #[test]
+    fn decode_requires_exactly_one_property() {
+        let payload = json!({
+            "Weight": 1500,
+            "Mass": 1500,
+            "$metadata": { "$model": weight::ID }
+        });
+
+        assert!(PropertyEnvelope::<i64>::decode(payload.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn decode_for_rejects_other_entities() {
+        let json = weight_envelope().encode().unwrap();
+
+        assert!(
+            PropertyEnvelope::<i64>::decode_for(json.as_bytes(), weight::NAME, weight::ID).is_ok()
+        );
+        assert_eq!(
+            PropertyEnvelope::<i64>::decode_for(json.as_bytes(), "IsTrailerConnected", weight::ID),
+            Err("Expected a 'IsTrailerConnected' property, got 'Weight'".to_string())
+        );
+        assert_eq!(
+            weight_envelope().expect_entity(weight::NAME, "dtmi:sdv:Trailer:Weight;1"),
+            Err(format!(
+                "Expected the 'dtmi:sdv:Trailer:Weight;1' model, got '{}'",
+                weight::ID
+            ))
+        );
+    }
+
+    #[test]
+    fn value_in_converts_between_units() {
+        let envelope = PropertyEnvelope::new(weight::NAME, weight::ID, json!(1500))
+            .with_unit(Some(Unit::Gram));
+
+        assert_eq!(envelope.value_in(Unit::Kilogram), Ok(1.5));
+        assert_eq!(
+            envelope.convert_to(Unit::Kilogram).unwrap().metadata.unit,
+            Some(Unit::Kilogram)
+        );
+        assert!(envelope.value_in(Unit::Kilopascal).is_err());
+    }
+
+    #[test]
+    fn value_in_requires_a_numeric_value_with_a_unit() {
+        let without_unit = PropertyEnvelope::new(weight::NAME, weight::ID, json!(1500));
+        assert!(without_unit.value_in(Unit::Kilogram).is_err());
+
+        let not_numeric = PropertyEnvelope::new(weight::NAME, weight::ID, json!("heavy"))
+            .with_unit(Some(Unit::Gram));
+        assert!(not_numeric.value_in(Unit::Kilogram).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Units of measure from the DTDL QuantitativeTypes extension, and conversions between them.
+//!
+//! A Property or Telemetry declares its unit with a semantic type and a `unit`, for example:
+//!
+//! ```json
+//! { "@type": ["Property", "Mass"], "name": "Weight", "schema": "long", "unit": "gram" }
+//! ```
+//!
+//! Units are converted through the base unit of their semantic type: kilograms for mass,
+//! degrees Celsius for temperature and kilopascals for pressure.
+//!
+//! This module is shared with the crate's build script, so it must only depend on `serde` and
+//! `serde_derive`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// The context of the DTDL QuantitativeTypes extension, which defines the semantic types and
+/// units.
+pub const QUANTITATIVE_TYPES_CONTEXT: &str = "dtmi:dtdl:extension:quantitativeTypes;1";
+
+/// The DTDL semantic types that have a supported unit.
+pub mod semantic_type {
+    pub const MASS: &str = "Mass";
+    pub const TEMPERATURE: &str = "Temperature";
+    pub const PRESSURE: &str = "Pressure";
+
+    /// All supported semantic types.
+    pub const ALL: &[&str] = &[MASS, TEMPERATURE, PRESSURE];
+}
+
+/// A unit of measure. The serialized form is the DTDL unit name, for example `kilogram`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Unit {
+    Gram,
+    Kilogram,
+    Pound,
+    DegreeCelsius,
+    DegreeFahrenheit,
+    Kilopascal,
+    PoundPerSquareInch,
+}
+
+impl Unit {
+    /// All supported units.
+    pub const ALL: &'static [Unit] = &[
+        Unit::Gram,
+        Unit::Kilogram,
+        Unit::Pound,
+        Unit::DegreeCelsius,
+        Unit::DegreeFahrenheit,
+        Unit::Kilopascal,
+        Unit::PoundPerSquareInch,
+    ];
+
+    /// The DTDL name of the unit, for example `kilogram`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Unit::Gram => "gram",
+            Unit::Kilogram => "kilogram",
+            Unit::Pound => "pound",
+            Unit::DegreeCelsius => "degreeCelsius",
+            Unit::DegreeFahrenheit => "degreeFahrenheit",
+            Unit::Kilopascal => "kilopascal",
+            Unit::PoundPerSquareInch => "poundPerSquareInch",
+        }
+    }
+
+    /// The unit's symbol, for example `kg`.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Gram => "g",
+            Unit::Kilogram => "kg",
+            Unit::Pound => "lb",
+            Unit::DegreeCelsius => "°C",
+            Unit::DegreeFahrenheit => "°F",
+            Unit::Kilopascal => "kPa",
+            Unit::PoundPerSquareInch => "psi",
+        }
+    }
+
+    /// The DTDL semantic type that this unit measures, for example `Mass`.
+    pub fn semantic_type(&self) -> &'static str {
+        match self {
+            Unit::Gram | Unit::Kilogram | Unit::Pound => semantic_type::MASS,
+            Unit::DegreeCelsius | Unit::DegreeFahrenheit => semantic_type::TEMPERATURE,
+            Unit::Kilopascal | Unit::PoundPerSquareInch => semantic_type::PRESSURE,
+        }
+    }
+
+    /// Parse a unit from its DTDL name, as it is written in a DTDL document.
+    ///
+    /// # Arguments
+    /// * `value` - The unit's DTDL name, for example `kilogram`.
+    pub fn from_dtdl_name(value: &str) -> Result<Self, String> {
+        Unit::ALL
+            .iter()
+            .find(|unit| unit.as_str() == value)
+            .copied()
+            .ok_or_else(|| format!("'{value}' is not a supported DTDL unit"))
+    }
+
+    /// Parse a unit from its DTDL name or its symbol, for example in user-facing configuration.
+    ///
+    /// # Arguments
+    /// * `value` - The unit's name or symbol, for example `kilogram` or `kg`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        Unit::ALL
+            .iter()
+            .find(|unit| unit.as_str() == value || unit.symbol() == value)
+            .copied()
+            .ok_or_else(|| format!("'{value}' is not a supported unit"))
+    }
+
+    /// Convert a value in this unit to another unit of the same semantic type.
+    ///
+    /// # Arguments
+    /// * `value` - The value in this unit.
+    /// * `to` - The unit to convert to.
+    pub fn convert(&self, value: f64, to: Unit) -> Result<f64, String> {
+        if self.semantic_type() != to.semantic_type() {
+            return Err(format!(
+                "Cannot convert from {} ({}) to {} ({})",
+                self.as_str(),
+                self.semantic_type(),
+                to.as_str(),
+                to.semantic_type()
+            ));
+        }
+
+        Ok(to.base_unit_to(self.to_base_unit(value)))
+    }
+
+    /// Convert a value in this unit to the base unit of its semantic type.
+    ///
+    /// # Arguments
+    /// * `value` - The value in this unit.
+    fn to_base_unit(self, value: f64) -> f64 {
+        match self {
+            Unit::Gram => value / 1000.0,
+            Unit::Kilogram | Unit::DegreeCelsius | Unit::Kilopascal => value,
+            Unit::Pound => value * KILOGRAMS_PER_POUND,
+            Unit::DegreeFahrenheit => (value - 32.0) * 5.0 / 9.0,
+            Unit::PoundPerSquareInch => value * KILOPASCALS_PER_PSI,
+        }
+    }
+
+    /// Convert a value in the base unit of this unit's semantic type to this unit.
+    ///
+    /// # Arguments
+    /// * `value` - The value in the base unit.
+    fn base_unit_to(self, value: f64) -> f64 {
+        match self {
+            Unit::Gram => value * 1000.0,
+            Unit::Kilogram | Unit::DegreeCelsius | Unit::Kilopascal => value,
+            Unit::Pound => value / KILOGRAMS_PER_POUND,
+            Unit::DegreeFahrenheit => value * 9.0 / 5.0 + 32.0,
+            Unit::PoundPerSquareInch => value / KILOPASCALS_PER_PSI,
+        }
+    }
+}
+
+// Exact by definition of the international avoirdupois pound.
+const KILOGRAMS_PER_POUND: f64 = 0.453_592_37;
+// Derived from the pound-force and the inch.
+const KILOPASCALS_PER_PSI: f64 = 6.894_757_293_168_361;
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Unit::parse(value)
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// Convert a value between two units of the same semantic type.
+///
+/// # Arguments
+/// * `value` - The value.
+/// * `from` - The value's unit.
+/// * `to` - The unit to convert to.
+pub fn convert(value: f64, from: Unit, to: Unit) -> Result<f64, String> {
+    from.convert(value, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_dtdl_name_accepts_only_dtdl_names() {
+        assert_eq!(Unit::from_dtdl_name("kilogram"), Ok(Unit::Kilogram));
+        assert_eq!(
+            Unit::from_dtdl_name("poundPerSquareInch"),
+            Ok(Unit::PoundPerSquareInch)
+        );
+        assert!(Unit::from_dtdl_name("kg").is_err());
+        assert!(Unit::from_dtdl_name("Kilogram").is_err());
+    }
+
+    #[test]
+    fn parse_accepts_names_and_symbols() {
+        assert_eq!(Unit::parse("kilogram"), Ok(Unit::Kilogram));
+        assert_eq!(Unit::parse("kg"), Ok(Unit::Kilogram));
+        assert_eq!(Unit::parse("°F"), Ok(Unit::DegreeFahrenheit));
+        assert!(Unit::parse("stone").is_err());
+    }
+}
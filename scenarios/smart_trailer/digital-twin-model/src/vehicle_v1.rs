@@ -0,0 +1,8 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+// Note: This code is generated at build time from the structure of the
+// vehicle model in "../dtdl/vehicle_v1.json"
+
+include!(concat!(env!("OUT_DIR"), "/vehicle_v1.rs"));
@@ -0,0 +1,153 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Registration of the trailer services with Chariott Service Discovery, so that tools and
+//! orchestrators can list the live trailer services with `DiscoverByNamespace`.
+
+use invehicle_stack_interfaces::service_discovery::core::v1::service_registry_client::ServiceRegistryClient;
+use invehicle_stack_interfaces::service_discovery::core::v1::{
+    RegisterRequest, ServiceMetadata, UnregisterRequest,
+};
+use log::{info, warn};
+use tonic::Request;
+
+use crate::constants::chariott::{
+    TRAILER_SERVICE_COMMUNICATION_KIND, TRAILER_SERVICE_NAMESPACE, TRAILER_SERVICE_VERSION,
+};
+use crate::errors::DiscoveryError;
+use crate::tls::connect;
+use crate::utils::{connection_error, RetryPolicy};
+
+/// Describe a trailer service in the `sdv.trailer` namespace.
+///
+/// # Arguments
+/// * `name` - The service's name.
+/// * `uri` - The service's URI.
+/// * `communication_reference` - The service's communication reference, such as its proto.
+pub fn trailer_service_metadata(
+    name: &str,
+    uri: &str,
+    communication_reference: &str,
+) -> ServiceMetadata {
+    ServiceMetadata {
+        namespace: TRAILER_SERVICE_NAMESPACE.to_string(),
+        name: name.to_string(),
+        version: TRAILER_SERVICE_VERSION.to_string(),
+        uri: uri.to_string(),
+        communication_kind: TRAILER_SERVICE_COMMUNICATION_KIND.to_string(),
+        communication_reference: communication_reference.to_string(),
+    }
+}
+
+/// A service that is registered with Chariott. It stays registered until it is unregistered.
+#[derive(Clone, Debug)]
+pub struct ChariottRegistration {
+    chariott_uri: String,
+    service: ServiceMetadata,
+    retry_policy: RetryPolicy,
+}
+
+impl ChariottRegistration {
+    /// Register a service with Chariott.
+    ///
+    /// # Arguments
+    /// * `chariott_uri` - Chariott's URI.
+    /// * `service` - The service's metadata.
+    /// * `retry_policy` - How the calls to Chariott are retried.
+    pub async fn register(
+        chariott_uri: &str,
+        service: ServiceMetadata,
+        retry_policy: RetryPolicy,
+    ) -> Result<Self, DiscoveryError> {
+        let registration = ChariottRegistration {
+            chariott_uri: chariott_uri.to_string(),
+            service,
+            retry_policy,
+        };
+
+        registration
+            .retry_policy
+            .run("Register with Chariott", || async {
+                let mut client = ServiceRegistryClient::new(
+                    connect(chariott_uri).await.map_err(connection_error)?,
+                );
+                let request = Request::new(RegisterRequest {
+                    service: Some(registration.service.clone()),
+                });
+                client.register(request).await
+            })
+            .await
+            .map_err(|status| DiscoveryError::from(status).with_context(registration.context()))?;
+
+        info!(
+            "Registered the service '{}' with Chariott at {chariott_uri}",
+            registration.service.name
+        );
+
+        Ok(registration)
+    }
+
+    /// Register a service with Chariott, and log a warning if that fails. The service remains
+    /// usable through the In-Vehicle Digital Twin Service without the registration.
+    ///
+    /// # Arguments
+    /// * `chariott_uri` - Chariott's URI.
+    /// * `service` - The service's metadata.
+    /// * `retry_policy` - How the calls to Chariott are retried.
+    pub async fn try_register(
+        chariott_uri: &str,
+        service: ServiceMetadata,
+        retry_policy: RetryPolicy,
+    ) -> Option<Self> {
+        match ChariottRegistration::register(chariott_uri, service, retry_policy).await {
+            Ok(registration) => Some(registration),
+            Err(err) => {
+                warn!("Failed to register with Chariott: {err}");
+                None
+            }
+        }
+    }
+
+    /// The registered service's metadata.
+    pub fn service(&self) -> &ServiceMetadata {
+        &self.service
+    }
+
+    /// Unregister the service from Chariott. A single attempt is made, so that a missing Chariott
+    /// does not delay a shutdown.
+    pub async fn unregister(self) -> Result<(), DiscoveryError> {
+        self.retry_policy
+            .single_attempt()
+            .run("Unregister from Chariott", || async {
+                let mut client = ServiceRegistryClient::new(
+                    connect(&self.chariott_uri)
+                        .await
+                        .map_err(connection_error)?,
+                );
+                let request = Request::new(UnregisterRequest {
+                    namespace: self.service.namespace.clone(),
+                    name: self.service.name.clone(),
+                    version: self.service.version.clone(),
+                });
+                client.unregister(request).await
+            })
+            .await
+            .map_err(|status| DiscoveryError::from(status).with_context(self.context()))?;
+
+        info!(
+            "Unregistered the service '{}' from Chariott at {}",
+            self.service.name, self.chariott_uri
+        );
+
+        Ok(())
+    }
+
+    /// Describe the registration, for error messages.
+    fn context(&self) -> String {
+        format!(
+            "Chariott at {}, service namespace '{}', name '{}' and version {}",
+            self.chariott_uri, self.service.namespace, self.service.name, self.service.version
+        )
+    }
+}
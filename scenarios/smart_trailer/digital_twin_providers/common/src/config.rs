@@ -0,0 +1,814 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Layered configuration for the smart trailer binaries.
+//!
+//! A binary's configuration is a typed struct that implements [`Config`]. It is loaded in layers,
+//! where each layer overrides the values of the previous ones:
+//!
+//! 1. The struct's defaults.
+//! 2. A TOML or YAML file. The file is provided with the `config=<path>` flag or the
+//!    `SMART_TRAILER_CONFIG` environment variable. Otherwise `<binary>.toml`, `<binary>.yaml` or
+//!    `<binary>.yml` in the working directory is used if it exists.
+//! 3. Environment variables, named `SMART_TRAILER__<SECTION>__<KEY>`, for example
+//!    `SMART_TRAILER__CHARIOTT__URI`.
+//! 4. Command line flags of the form `<section>.<key>=<value>`, for example
+//!    `chariott.uri=http://10.0.0.1:50000`.
+//!
+//! Values from environment variables and flags are converted to the type of the value that they
+//! override. An array is overridden by a comma separated environment variable, and extended by
+//! repeated flags. The merged configuration is then deserialized and validated, so unknown keys
+//! and invalid values are reported at startup.
+
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::time::Duration;
+
+use crate::discovery_cache::DiscoveryCache;
+use crate::service_matching::parse_version_requirement;
+use crate::service_resolver::{
+    ChainResolver, ChariottResolver, ServiceDescriptor, ServiceResolver, StaticResolver,
+};
+use crate::utils::RetryPolicy;
+
+/// The flag that selects the configuration file.
+pub const CONFIG_FILE_FLAG: &str = "config=";
+
+/// The environment variable that selects the configuration file.
+pub const CONFIG_FILE_ENV_VAR: &str = "SMART_TRAILER_CONFIG";
+
+/// The prefix of the environment variables that override configuration values.
+pub const ENV_VAR_PREFIX: &str = "SMART_TRAILER__";
+
+/// The separator between the sections and keys in an environment variable name.
+const ENV_VAR_SEPARATOR: &str = "__";
+
+const DEFAULT_DISCOVERY_CACHE_TTL_MS: u64 = 60000; // 1 minute
+const DEFAULT_REGISTRATION_CHECK_INTERVAL_MS: u64 = 30000; // 30 seconds
+
+// We ignore the DevSkim warning because this is a sample application. In production, TLS should be
+// configured and an https URI used.
+const DEFAULT_CHARIOTT_URI: &str = "http://0.0.0.0:50000"; // DevSkim: ignore DS137138
+
+/// A binary's configuration.
+pub trait Config: Default + serde::Serialize + DeserializeOwned {
+    /// Check that the configuration values are valid.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Loads a configuration from its layers.
+#[derive(Clone, Debug)]
+pub struct ConfigLoader {
+    name: String,
+    args: Vec<String>,
+    env_vars: Vec<(String, String)>,
+    aliases: Vec<(String, String)>,
+}
+
+impl ConfigLoader {
+    /// Create a loader that reads the process's command line arguments and environment.
+    ///
+    /// # Arguments
+    /// * `name` - The binary's name, used to find its default configuration file.
+    pub fn new(name: &str) -> Self {
+        ConfigLoader {
+            name: name.to_string(),
+            args: env::args().skip(1).collect(),
+            env_vars: env::vars().collect(),
+            aliases: Vec::new(),
+        }
+    }
+
+    /// Use the provided command line arguments instead of the process's.
+    ///
+    /// # Arguments
+    /// * `args` - The arguments, without the binary's path.
+    pub fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.args = args.into_iter().collect();
+        self
+    }
+
+    /// Use the provided environment variables instead of the process's.
+    ///
+    /// # Arguments
+    /// * `env_vars` - The environment variables, as (name, value) pairs.
+    pub fn with_env_vars(mut self, env_vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.env_vars = env_vars.into_iter().collect();
+        self
+    }
+
+    /// Accept a short flag as an alias for a configuration key, for example `freq_ms` for
+    /// `subscription.frequency_ms`.
+    ///
+    /// # Arguments
+    /// * `flag` - The flag, without the `=`.
+    /// * `key` - The dotted configuration key.
+    pub fn with_alias(mut self, flag: &str, key: &str) -> Self {
+        self.aliases.push((flag.to_string(), key.to_string()));
+        self
+    }
+
+    /// Load and validate the configuration.
+    pub fn load<T: Config>(&self) -> Result<T, String> {
+        let mut config = serde_json::to_value(T::default())
+            .map_err(|err| format!("Failed to serialize the default configuration: {err}"))?;
+
+        if let Some(path) = self.config_file() {
+            merge(&mut config, read_file(&path)?);
+        }
+
+        for (name, value) in &self.env_vars {
+            if let Some(key) = name.strip_prefix(ENV_VAR_PREFIX) {
+                let path: Vec<String> = key
+                    .split(ENV_VAR_SEPARATOR)
+                    .map(str::to_lowercase)
+                    .collect();
+                set_value(&mut config, &path, value, false)
+                    .map_err(|err| format!("Invalid environment variable '{name}': {err}"))?;
+            }
+        }
+
+        for arg in &self.args {
+            let Some((flag, value)) = arg.split_once('=') else {
+                return Err(format!("Unexpected argument '{arg}'. {}", usage(&config)));
+            };
+            if arg.starts_with(CONFIG_FILE_FLAG) {
+                continue;
+            }
+
+            let key = self
+                .aliases
+                .iter()
+                .find(|(alias, _)| alias == flag)
+                .map_or(flag, |(_, key)| key.as_str());
+            let path: Vec<String> = key.split('.').map(str::to_string).collect();
+            set_value(&mut config, &path, value, true)
+                .map_err(|err| format!("Invalid flag '{arg}': {err}"))?;
+        }
+
+        let config: T = serde_json::from_value(config)
+            .map_err(|err| format!("Invalid configuration: {err}"))?;
+        config
+            .validate()
+            .map_err(|err| format!("Invalid configuration: {err}"))?;
+
+        Ok(config)
+    }
+
+    /// Get the path of the configuration file, if there is one.
+    fn config_file(&self) -> Option<PathBuf> {
+        let flag = self
+            .args
+            .iter()
+            .find_map(|arg| arg.strip_prefix(CONFIG_FILE_FLAG));
+        let env_var = self
+            .env_vars
+            .iter()
+            .find(|(name, _)| name == CONFIG_FILE_ENV_VAR)
+            .map(|(_, value)| value.as_str());
+
+        match flag.or(env_var) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => ["toml", "yaml", "yml"]
+                .iter()
+                .map(|extension| PathBuf::from(format!("{}.{extension}", self.name)))
+                .find(|path| path.is_file()),
+        }
+    }
+}
+
+/// Describe the flags that a binary accepts, for arguments that are not flags, such as `--help`.
+///
+/// # Arguments
+/// * `config` - The binary's default configuration, whose tables are its sections.
+fn usage(config: &Value) -> String {
+    let sections: Vec<&str> = config
+        .as_object()
+        .map(|sections| sections.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+
+    format!(
+        "The arguments must have the form '<section>.<key>=<value>' or '{CONFIG_FILE_FLAG}<path>', where the sections are: {}",
+        sections.join(", ")
+    )
+}
+
+/// Read a TOML or YAML configuration file, depending on its extension.
+///
+/// # Arguments
+/// * `path` - The path to the file.
+fn read_file(path: &Path) -> Result<Value, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read '{}' due to '{err}'", path.display()))?;
+
+    let value = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str::<Value>(&contents).map_err(|err| err.to_string()),
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str::<Value>(&contents).map_err(|err| err.to_string())
+        }
+        _ => Err("the file must have a .toml, .yaml or .yml extension".to_string()),
+    }
+    .map_err(|err| format!("Failed to parse '{}': {err}", path.display()))?;
+
+    match value {
+        // An empty YAML file is null.
+        Value::Null => Ok(Value::Object(Map::new())),
+        Value::Object(_) => Ok(value),
+        _ => Err(format!(
+            "Failed to parse '{}': expected a table of sections",
+            path.display()
+        )),
+    }
+}
+
+/// Merge a layer into a configuration. Tables are merged key by key, other values are replaced.
+///
+/// # Arguments
+/// * `config` - The configuration.
+/// * `layer` - The layer to merge into it.
+fn merge(config: &mut Value, layer: Value) {
+    match (config, layer) {
+        (Value::Object(config), Value::Object(layer)) => {
+            for (key, value) in layer {
+                match config.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        config.insert(key, value);
+                    }
+                }
+            }
+        }
+        (config, layer) => *config = layer,
+    }
+}
+
+/// Set a configuration value from a string, converted to the type of the value it overrides.
+///
+/// # Arguments
+/// * `config` - The configuration.
+/// * `path` - The sections and key of the value.
+/// * `raw` - The value as a string.
+/// * `append` - Append to an array instead of replacing it.
+fn set_value(config: &mut Value, path: &[String], raw: &str, append: bool) -> Result<(), String> {
+    let (key, sections) = path
+        .split_last()
+        .ok_or_else(|| "the key is empty".to_string())?;
+
+    let mut table = config;
+    for section in sections {
+        table = table
+            .as_object_mut()
+            .ok_or_else(|| format!("'{section}' is not in a section"))?
+            .entry(section.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    let table = table
+        .as_object_mut()
+        .ok_or_else(|| format!("'{key}' is not in a section"))?;
+
+    let value = match table.get_mut(key) {
+        Some(Value::Array(items)) if append => {
+            items.push(parse_scalar(raw, None));
+            return Ok(());
+        }
+        Some(Value::Array(items)) => Value::Array(
+            raw.split(',')
+                .map(|item| parse_scalar(item.trim(), items.first()))
+                .collect(),
+        ),
+        Some(Value::Object(_)) => return Err(format!("'{key}' is a section, not a key")),
+        existing => parse_scalar(raw, existing.as_deref()),
+    };
+    table.insert(key.clone(), value);
+
+    Ok(())
+}
+
+/// Convert a string to a value of the same type as an existing value. Without an existing value,
+/// booleans and numbers are recognized and anything else is a string.
+///
+/// # Arguments
+/// * `raw` - The value as a string.
+/// * `existing` - The value that is overridden.
+fn parse_scalar(raw: &str, existing: Option<&Value>) -> Value {
+    if let Some(Value::String(_)) = existing {
+        return Value::String(raw.to_string());
+    }
+
+    match serde_json::from_str::<Value>(raw) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_))) => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+/// Chariott's configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChariottConfig {
+    /// The URI of Chariott's service discovery.
+    pub uri: String,
+}
+
+impl Default for ChariottConfig {
+    fn default() -> Self {
+        ChariottConfig {
+            uri: DEFAULT_CHARIOTT_URI.to_string(),
+        }
+    }
+}
+
+impl ChariottConfig {
+    /// Check that the configuration values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_uri("chariott.uri", &self.uri)
+    }
+}
+
+/// A provider's server configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderConfig {
+    /// The address that the provider listens on, for example `0.0.0.0:4030`.
+    pub authority: String,
+    /// Whether the provider registers itself with Chariott, in the `sdv.trailer` namespace.
+    pub register_with_chariott: bool,
+    /// How often the provider checks that its entities are still registered with the
+    /// In-Vehicle Digital Twin Service, and registers them again if they are not. 0 disables the
+    /// checks.
+    pub registration_check_interval_ms: u64,
+}
+
+impl ProviderConfig {
+    /// Create a provider configuration.
+    ///
+    /// # Arguments
+    /// * `authority` - The address that the provider listens on.
+    pub fn new(authority: &str) -> Self {
+        ProviderConfig {
+            authority: authority.to_string(),
+            register_with_chariott: true,
+            registration_check_interval_ms: DEFAULT_REGISTRATION_CHECK_INTERVAL_MS,
+        }
+    }
+
+    /// Check that the configuration values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        self.socket_addr().map(|_| ())
+    }
+
+    /// The address that the provider listens on.
+    pub fn socket_addr(&self) -> Result<SocketAddr, String> {
+        self.authority.parse().map_err(|err| {
+            format!(
+                "provider.authority '{}' is not a socket address: {err}",
+                self.authority
+            )
+        })
+    }
+
+    /// How often the registration with the In-Vehicle Digital Twin Service is checked.
+    pub fn registration_check_interval(&self) -> Duration {
+        Duration::from_millis(self.registration_check_interval_ms)
+    }
+
+    /// The provider's URI, which is registered with the In-Vehicle Digital Twin Service.
+    ///
+    /// # Arguments
+    /// * `tls` - Whether the provider serves with TLS.
+    pub fn uri(&self, tls: bool) -> String {
+        if tls {
+            return format!("https://{}", self.authority);
+        }
+
+        // We ignore the DevSkim warning because this is a sample application. In production, TLS
+        // should be configured.
+        format!("http://{}", self.authority) // DevSkim: ignore DS137138
+    }
+}
+
+/// The TLS configuration of a binary's gRPC servers and clients. Empty paths are not set.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// The PEM file of the CA that the servers, and the clients when client certificates are
+    /// required, are verified with.
+    pub ca_cert_path: String,
+    /// The PEM file of the certificate that identifies a provider's server, and the clients for
+    /// mutual TLS. A provider serves with TLS when it is set.
+    pub cert_path: String,
+    /// The PEM file of the certificate's private key.
+    pub key_path: String,
+    /// Whether a provider's server requires the clients to present a certificate that is signed
+    /// by the CA.
+    pub require_client_cert: bool,
+    /// The name that the servers' certificates are verified against, instead of the URI's host.
+    pub domain_name: String,
+}
+
+impl TlsConfig {
+    /// Check that the configuration values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cert_path.is_empty() != self.key_path.is_empty() {
+            return Err("tls.cert_path and tls.key_path must be set together".to_string());
+        }
+        if self.require_client_cert && (self.ca_cert_path.is_empty() || self.cert_path.is_empty()) {
+            return Err(
+                "tls.require_client_cert requires tls.ca_cert_path, tls.cert_path and tls.key_path"
+                    .to_string(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Does a provider's server use TLS?
+    pub fn is_server_enabled(&self) -> bool {
+        !self.cert_path.is_empty()
+    }
+}
+
+/// An MQTT client's configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// The client id, which is made unique per connection where needed.
+    pub client_id: String,
+}
+
+impl MqttConfig {
+    /// Create an MQTT client configuration.
+    ///
+    /// # Arguments
+    /// * `client_id` - The client id.
+    pub fn new(client_id: &str) -> Self {
+        MqttConfig {
+            client_id: client_id.to_string(),
+        }
+    }
+
+    /// Check that the configuration values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.client_id.is_empty() {
+            return Err("mqtt.client_id must not be empty".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// The configuration of the retries of gRPC calls. A timeout of 0 means that there is no timeout.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry.
+    pub initial_backoff_ms: u64,
+    /// The maximum delay between two attempts.
+    pub max_backoff_ms: u64,
+    /// The factor that the delay grows by after each retry.
+    pub multiplier: f64,
+    /// The fraction of the delay that is randomly added or removed, between 0 and 1.
+    pub jitter: f64,
+    /// The deadline of each attempt.
+    pub attempt_timeout_ms: u64,
+    /// The deadline of all attempts together.
+    pub overall_timeout_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        let millis =
+            |duration: Option<Duration>| duration.map_or(0, |duration| duration.as_millis() as u64);
+
+        RetryConfig {
+            max_attempts: policy.max_attempts,
+            initial_backoff_ms: policy.initial_backoff.as_millis() as u64,
+            max_backoff_ms: policy.max_backoff.as_millis() as u64,
+            multiplier: policy.multiplier,
+            jitter: policy.jitter,
+            attempt_timeout_ms: millis(policy.attempt_timeout),
+            overall_timeout_ms: millis(policy.overall_timeout),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Check that the configuration values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("retry.max_attempts must be greater than 0".to_string());
+        }
+        if self.initial_backoff_ms > self.max_backoff_ms {
+            return Err(format!(
+                "retry.initial_backoff_ms ({}) must not be greater than retry.max_backoff_ms ({})",
+                self.initial_backoff_ms, self.max_backoff_ms
+            ));
+        }
+        if !(self.multiplier >= 1.0 && self.multiplier.is_finite()) {
+            return Err(format!(
+                "retry.multiplier ({}) must be at least 1",
+                self.multiplier
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!(
+                "retry.jitter ({}) must be between 0 and 1",
+                self.jitter
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The retry policy.
+    pub fn policy(&self) -> RetryPolicy {
+        let timeout = |millis: u64| (millis > 0).then(|| Duration::from_millis(millis));
+
+        RetryPolicy::default()
+            .with_max_attempts(self.max_attempts)
+            .with_backoff(
+                Duration::from_millis(self.initial_backoff_ms),
+                Duration::from_millis(self.max_backoff_ms),
+                self.multiplier,
+            )
+            .with_jitter(self.jitter)
+            .with_attempt_timeout(timeout(self.attempt_timeout_ms))
+            .with_overall_timeout(timeout(self.overall_timeout_ms))
+    }
+}
+
+/// How services such as the In-Vehicle Digital Twin Service are resolved.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolverKind {
+    /// Discover services with Chariott.
+    #[default]
+    Chariott,
+    /// Look services up in `discovery.static_services`.
+    Static,
+    /// Discover services with Chariott, and fall back to `discovery.static_services`.
+    Chain,
+}
+
+/// Which registrations of a service in Chariott are compatible.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceRequirementsConfig {
+    /// A semver requirement on the service's version, for example `^1`.
+    pub version: String,
+    /// The accepted communication kinds, the preferred one first.
+    pub communication_kinds: Vec<String>,
+    /// Patterns of the accepted communication references, where `*` matches any sequence of
+    /// characters.
+    pub communication_references: Vec<String>,
+}
+
+impl ServiceRequirementsConfig {
+    /// Use the requirements of a service descriptor as the defaults.
+    ///
+    /// # Arguments
+    /// * `service` - The service descriptor.
+    pub fn new(service: &ServiceDescriptor) -> Self {
+        ServiceRequirementsConfig {
+            version: service.version.clone(),
+            communication_kinds: service.communication_kinds.clone(),
+            communication_references: service.communication_references.clone(),
+        }
+    }
+
+    /// Check that the configuration values are valid.
+    ///
+    /// # Arguments
+    /// * `key` - The configuration key of the section, for messages.
+    pub fn validate(&self, key: &str) -> Result<(), String> {
+        parse_version_requirement(&self.version).map_err(|err| format!("{key}.version: {err}"))?;
+        if self.communication_kinds.is_empty() {
+            return Err(format!("{key}.communication_kinds must not be empty"));
+        }
+        if self.communication_references.is_empty() {
+            return Err(format!("{key}.communication_references must not be empty"));
+        }
+
+        Ok(())
+    }
+
+    /// Apply the requirements to a service descriptor.
+    ///
+    /// # Arguments
+    /// * `service` - The service descriptor.
+    pub fn apply(&self, service: ServiceDescriptor) -> ServiceDescriptor {
+        ServiceDescriptor {
+            version: self.version.clone(),
+            communication_kinds: self.communication_kinds.clone(),
+            communication_references: self.communication_references.clone(),
+            ..service
+        }
+    }
+}
+
+/// The configuration of service resolution and of the discovery cache.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// How services are resolved.
+    pub resolver: ResolverKind,
+    /// The static service map, as entries of the form `<namespace>/<name>=<uri>`.
+    pub static_services: Vec<String>,
+    /// How long a discovery result is used. 0 disables the cache.
+    pub cache_ttl_ms: u64,
+    /// Which registrations of the In-Vehicle Digital Twin Service are compatible.
+    pub invehicle_digital_twin: ServiceRequirementsConfig,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            resolver: ResolverKind::default(),
+            static_services: Vec::new(),
+            cache_ttl_ms: DEFAULT_DISCOVERY_CACHE_TTL_MS,
+            invehicle_digital_twin: ServiceRequirementsConfig::new(
+                &ServiceDescriptor::invehicle_digital_twin(),
+            ),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// Check that the configuration values are valid.
+    pub fn validate(&self) -> Result<(), String> {
+        let static_resolver = StaticResolver::parse(&self.static_services)
+            .map_err(|err| format!("discovery.static_services: {err}"))?;
+        if self.resolver == ResolverKind::Static && static_resolver.is_empty() {
+            return Err(
+                "discovery.static_services must not be empty with the static resolver".to_string(),
+            );
+        }
+
+        self.invehicle_digital_twin
+            .validate("discovery.invehicle_digital_twin")
+    }
+
+    /// Describe the compatible In-Vehicle Digital Twin Services.
+    pub fn invehicle_digital_twin_service(&self) -> ServiceDescriptor {
+        self.invehicle_digital_twin
+            .apply(ServiceDescriptor::invehicle_digital_twin())
+    }
+
+    /// Create the service resolver.
+    ///
+    /// # Arguments
+    /// * `chariott` - Chariott's configuration.
+    /// * `retry_policy` - How the calls to Chariott are retried.
+    pub fn resolver(
+        &self,
+        chariott: &ChariottConfig,
+        retry_policy: RetryPolicy,
+    ) -> Result<Box<dyn ServiceResolver>, String> {
+        let chariott_resolver = || ChariottResolver::new(&chariott.uri, retry_policy.clone());
+        let static_resolver = || StaticResolver::parse(&self.static_services);
+
+        Ok(match self.resolver {
+            ResolverKind::Chariott => Box::new(chariott_resolver()),
+            ResolverKind::Static => Box::new(static_resolver()?),
+            // Chariott is asked once, so that the static map is used right away when Chariott
+            // cannot be reached, instead of after all of Chariott's retries.
+            ResolverKind::Chain => Box::new(
+                ChainResolver::new()
+                    .with(ChariottResolver::new(
+                        &chariott.uri,
+                        retry_policy.single_attempt(),
+                    ))
+                    .with(static_resolver()?),
+            ),
+        })
+    }
+
+    /// Create the discovery cache.
+    ///
+    /// # Arguments
+    /// * `chariott` - Chariott's configuration.
+    /// * `retry_policy` - How the discovery calls are retried.
+    pub fn cache(
+        &self,
+        chariott: &ChariottConfig,
+        retry_policy: RetryPolicy,
+    ) -> Result<DiscoveryCache, String> {
+        Ok(DiscoveryCache::new(
+            Duration::from_millis(self.cache_ttl_ms),
+            self.resolver(chariott, retry_policy.clone())?,
+            retry_policy,
+        )
+        .with_invehicle_digital_twin_service(self.invehicle_digital_twin_service()))
+    }
+}
+
+/// Check that a URI has a scheme and an authority.
+///
+/// # Arguments
+/// * `key` - The configuration key, for messages.
+/// * `uri` - The URI.
+pub fn validate_uri(key: &str, uri: &str) -> Result<(), String> {
+    match uri.split_once("://") {
+        Some((scheme, authority)) if !scheme.is_empty() && !authority.is_empty() => Ok(()),
+        _ => Err(format!("{key} '{uri}' is not a URI")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct TestConfig {
+        chariott: ChariottConfig,
+        retry: RetryConfig,
+    }
+
+    impl Config for TestConfig {}
+
+    /// A loader that does not read the process's arguments or environment.
+    fn loader(args: &[&str], env_vars: &[(&str, &str)]) -> ConfigLoader {
+        ConfigLoader::new("config_test")
+            .with_args(args.iter().map(|arg| arg.to_string()))
+            .with_env_vars(
+                env_vars
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            )
+    }
+
+    #[test]
+    fn load_uses_the_defaults() {
+        let config: TestConfig = loader(&[], &[]).load().unwrap();
+
+        assert_eq!(config.chariott.uri, DEFAULT_CHARIOTT_URI);
+    }
+
+    #[test]
+    fn flags_override_environment_variables() {
+        let env_vars = [
+            ("SMART_TRAILER__CHARIOTT__URI", "http://10.0.0.1:50000"),
+            ("SMART_TRAILER__RETRY__MAX_ATTEMPTS", "7"),
+        ];
+        let config: TestConfig = loader(&["chariott.uri=http://10.0.0.2:50000"], &env_vars)
+            .load()
+            .unwrap();
+
+        assert_eq!(config.chariott.uri, "http://10.0.0.2:50000");
+        assert_eq!(config.retry.max_attempts, 7);
+    }
+
+    #[test]
+    fn aliases_set_their_key() {
+        let config: TestConfig = loader(&["chariott_uri=http://10.0.0.3:50000"], &[])
+            .with_alias("chariott_uri", "chariott.uri")
+            .load()
+            .unwrap();
+
+        assert_eq!(config.chariott.uri, "http://10.0.0.3:50000");
+    }
+
+    #[test]
+    fn arguments_that_are_not_flags_are_rejected_with_the_usage() {
+        let err = loader(&["--help"], &[]).load::<TestConfig>().unwrap_err();
+
+        assert!(err.starts_with("Unexpected argument '--help'."), "{err}");
+        assert!(err.contains("'<section>.<key>=<value>'"), "{err}");
+        assert!(err.contains("chariott, retry"), "{err}");
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(loader(&["chariott.url=http://10.0.0.1:50000"], &[])
+            .load::<TestConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn static_service_uris_are_validated() {
+        let config = DiscoveryConfig {
+            resolver: ResolverKind::Static,
+            static_services: vec!["sdv/invehicle_digital_twin=0.0.0.0:5010".to_string()],
+            ..Default::default()
+        };
+
+        let err = config.validate().unwrap_err();
+
+        assert!(
+            err.starts_with("discovery.static_services: Invalid URI"),
+            "{err}"
+        );
+    }
+}
@@ -25,6 +25,12 @@ pub mod chariott {
     pub const INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION: &str = "1.0";
     pub const INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND: &str = "grpc+proto";
     pub const INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE: &str = "https://github.com/eclipse-ibeji/ibeji/blob/main/interfaces/digital_twin/v1/digital_twin.proto";
+    pub const INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION_REQUIREMENT: &str = "^1";
+    pub const INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE_PATTERN: &str = "https://github.com/eclipse-ibeji/ibeji/blob/*/interfaces/digital_twin/v1/digital_twin.proto";
+
+    pub const TRAILER_SERVICE_NAMESPACE: &str = "sdv.trailer";
+    pub const TRAILER_SERVICE_VERSION: &str = "1.0";
+    pub const TRAILER_SERVICE_COMMUNICATION_KIND: &str = "grpc+proto";
 }
 
 /// Recognized constraint types for subscribe requests.
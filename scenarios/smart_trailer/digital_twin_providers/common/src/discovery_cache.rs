@@ -0,0 +1,395 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A cache of the services resolved with a [`ServiceResolver`] and the providers discovered with
+//! the In-Vehicle Digital Twin Service.
+//!
+//! Entries expire after a time to live, so that a service or provider that moved is eventually
+//! rediscovered. A caller that fails to connect to a discovered URI should invalidate it with
+//! [`DiscoveryCache::invalidate_uri`], so that the next lookup rediscovers it right away.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::EndpointInfo;
+use log::debug;
+use parking_lot::Mutex;
+use tokio::time::{Duration, Instant};
+
+use crate::errors::DiscoveryError;
+use crate::service_resolver::{ServiceDescriptor, ServiceResolver};
+use crate::utils::{discover_digital_twin_provider_using_ibeji, RetryPolicy};
+
+/// The key of a provider discovered with the In-Vehicle Digital Twin Service.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct ProviderKey {
+    invehicle_digital_twin_uri: String,
+    entity_id: String,
+    protocol: String,
+    operations: Vec<String>,
+}
+
+/// A cached discovery result.
+#[derive(Clone, Debug)]
+struct CacheEntry<T> {
+    value: T,
+    expires_at: Instant,
+}
+
+/// Memoizes discovery results for a time to live.
+#[derive(Debug)]
+pub struct DiscoveryCache {
+    ttl: Duration,
+    resolver: Box<dyn ServiceResolver>,
+    retry_policy: RetryPolicy,
+    invehicle_digital_twin_service: ServiceDescriptor,
+    services: Mutex<HashMap<ServiceDescriptor, CacheEntry<String>>>,
+    providers: Mutex<HashMap<ProviderKey, CacheEntry<EndpointInfo>>>,
+}
+
+impl DiscoveryCache {
+    /// Create a cache.
+    ///
+    /// # Arguments
+    /// * `ttl` - How long a discovery result is used. A zero duration disables the cache.
+    /// * `resolver` - Resolves the URIs of services.
+    /// * `retry_policy` - How the calls to the In-Vehicle Digital Twin Service are retried.
+    pub fn new(
+        ttl: Duration,
+        resolver: Box<dyn ServiceResolver>,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        DiscoveryCache {
+            ttl,
+            resolver,
+            retry_policy,
+            invehicle_digital_twin_service: ServiceDescriptor::invehicle_digital_twin(),
+            services: Mutex::new(HashMap::new()),
+            providers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set which In-Vehicle Digital Twin Services are compatible. The default is
+    /// [`ServiceDescriptor::invehicle_digital_twin`].
+    ///
+    /// # Arguments
+    /// * `service` - The In-Vehicle Digital Twin Service's descriptor.
+    pub fn with_invehicle_digital_twin_service(mut self, service: ServiceDescriptor) -> Self {
+        self.invehicle_digital_twin_service = service;
+        self
+    }
+
+    /// The retry policy of the discovery calls.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Resolve the URI of a service, or use the result of an earlier resolution.
+    ///
+    /// # Arguments
+    /// * `service` - The service.
+    pub async fn discover_service(
+        &self,
+        service: &ServiceDescriptor,
+    ) -> Result<String, DiscoveryError> {
+        if let Some(uri) = self.get(&self.services, service) {
+            debug!("Using the cached URI {uri} of {service}");
+            return Ok(uri);
+        }
+
+        let uri = self.resolver.resolve(service).await?;
+        self.insert(&self.services, service.clone(), uri.clone());
+
+        Ok(uri)
+    }
+
+    /// Resolve the URI of the In-Vehicle Digital Twin Service, or use the result of an earlier
+    /// resolution.
+    pub async fn discover_invehicle_digital_twin_service(&self) -> Result<String, DiscoveryError> {
+        self.discover_service(&self.invehicle_digital_twin_service)
+            .await
+    }
+
+    /// Discover a provider of an entity with the In-Vehicle Digital Twin Service, or use the
+    /// result of an earlier discovery. Returns the provider's endpoint.
+    ///
+    /// # Arguments
+    /// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin Service's URI.
+    /// * `entity_id` - The entity's id.
+    /// * `protocol` - The required protocol.
+    /// * `operations` - The required operations.
+    pub async fn discover_provider(
+        &self,
+        invehicle_digital_twin_uri: &str,
+        entity_id: &str,
+        protocol: &str,
+        operations: &[String],
+    ) -> Result<EndpointInfo, DiscoveryError> {
+        let key = ProviderKey {
+            invehicle_digital_twin_uri: invehicle_digital_twin_uri.to_string(),
+            entity_id: entity_id.to_string(),
+            protocol: protocol.to_string(),
+            operations: operations.to_vec(),
+        };
+        if let Some(endpoint_info) = self.get(&self.providers, &key) {
+            debug!(
+                "Using the cached endpoint {} of the provider of {entity_id}",
+                endpoint_info.uri
+            );
+            return Ok(endpoint_info);
+        }
+
+        let endpoint_info = discover_digital_twin_provider_using_ibeji(
+            invehicle_digital_twin_uri,
+            entity_id,
+            protocol,
+            operations,
+            &self.retry_policy,
+        )
+        .await?;
+        self.insert(&self.providers, key, endpoint_info.clone());
+
+        Ok(endpoint_info)
+    }
+
+    /// Forget every discovery result that resolved to a URI, for example after failing to
+    /// connect to it. Providers that were discovered through a service at that URI are forgotten
+    /// as well. Returns the number of forgotten results.
+    ///
+    /// # Arguments
+    /// * `uri` - The URI.
+    pub fn invalidate_uri(&self, uri: &str) -> usize {
+        let mut count = 0;
+
+        self.services.lock().retain(|_, entry| {
+            let keep = entry.value != uri;
+            count += usize::from(!keep);
+            keep
+        });
+        self.providers.lock().retain(|key, entry| {
+            let keep = entry.value.uri != uri && key.invehicle_digital_twin_uri != uri;
+            count += usize::from(!keep);
+            keep
+        });
+
+        if count > 0 {
+            debug!("Invalidated {count} cached discovery results for {uri}");
+        }
+
+        count
+    }
+
+    /// Forget all discovery results.
+    pub fn clear(&self) {
+        self.services.lock().clear();
+        self.providers.lock().clear();
+    }
+
+    /// Get a discovery result that has not expired.
+    ///
+    /// # Arguments
+    /// * `entries` - The cached results.
+    /// * `key` - The result's key.
+    fn get<K: Eq + Hash, T: Clone>(
+        &self,
+        entries: &Mutex<HashMap<K, CacheEntry<T>>>,
+        key: &K,
+    ) -> Option<T> {
+        let mut entries = entries.lock();
+        match entries.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache a discovery result.
+    ///
+    /// # Arguments
+    /// * `entries` - The cached results.
+    /// * `key` - The result's key.
+    /// * `value` - The result.
+    fn insert<K: Eq + Hash, T>(
+        &self,
+        entries: &Mutex<HashMap<K, CacheEntry<T>>>,
+        key: K,
+        value: T,
+    ) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        entries.lock().insert(
+            key,
+            CacheEntry {
+                value,
+                expires_at: Instant::now() + self.ttl,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    const URI: &str = "http://0.0.0.0:5010";
+    const TTL: Duration = Duration::from_secs(30);
+
+    /// Resolves every service to the same URI, and counts the resolutions.
+    #[derive(Debug, Default)]
+    struct CountingResolver {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[tonic::async_trait]
+    impl ServiceResolver for CountingResolver {
+        async fn resolve(&self, _service: &ServiceDescriptor) -> Result<String, DiscoveryError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(URI.to_string())
+        }
+    }
+
+    /// Create a cache and the counter of its resolver's resolutions.
+    fn cache(ttl: Duration) -> (DiscoveryCache, Arc<AtomicUsize>) {
+        let resolver = CountingResolver::default();
+        let calls = resolver.calls.clone();
+
+        (
+            DiscoveryCache::new(ttl, Box::new(resolver), RetryPolicy::no_retry()),
+            calls,
+        )
+    }
+
+    fn provider_key(invehicle_digital_twin_uri: &str) -> ProviderKey {
+        ProviderKey {
+            invehicle_digital_twin_uri: invehicle_digital_twin_uri.to_string(),
+            entity_id: "dtmi:sdv:Trailer:Weight;2".to_string(),
+            protocol: "grpc".to_string(),
+            operations: vec!["Get".to_string()],
+        }
+    }
+
+    fn endpoint(uri: &str) -> EndpointInfo {
+        EndpointInfo {
+            protocol: "grpc".to_string(),
+            operations: vec!["Get".to_string()],
+            uri: uri.to_string(),
+            context: String::new(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn results_are_reused_until_they_expire() {
+        let (cache, calls) = cache(TTL);
+
+        assert_eq!(
+            cache
+                .discover_invehicle_digital_twin_service()
+                .await
+                .unwrap(),
+            URI
+        );
+        tokio::time::advance(TTL - Duration::from_secs(1)).await;
+        assert_eq!(
+            cache
+                .discover_invehicle_digital_twin_service()
+                .await
+                .unwrap(),
+            URI
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(
+            cache
+                .discover_invehicle_digital_twin_service()
+                .await
+                .unwrap(),
+            URI
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_zero_ttl_disables_the_cache() {
+        let (cache, calls) = cache(Duration::ZERO);
+
+        cache
+            .discover_invehicle_digital_twin_service()
+            .await
+            .unwrap();
+        cache
+            .discover_invehicle_digital_twin_service()
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invalidate_uri_forgets_the_results_that_use_it() {
+        let (cache, calls) = cache(TTL);
+        cache
+            .discover_invehicle_digital_twin_service()
+            .await
+            .unwrap();
+        // A provider discovered through the service, and a provider at the service's URI.
+        cache.insert(
+            &cache.providers,
+            provider_key(URI),
+            endpoint("http://0.0.0.0:4010"),
+        );
+        cache.insert(
+            &cache.providers,
+            provider_key("http://0.0.0.0:5011"),
+            endpoint(URI),
+        );
+        let other = provider_key("http://0.0.0.0:5012");
+        cache.insert(
+            &cache.providers,
+            other.clone(),
+            endpoint("http://0.0.0.0:4020"),
+        );
+
+        assert_eq!(cache.invalidate_uri(URI), 3);
+        assert_eq!(cache.invalidate_uri(URI), 0);
+        assert!(cache.get(&cache.providers, &other).is_some());
+
+        cache
+            .discover_invehicle_digital_twin_service()
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clear_forgets_everything() {
+        let (cache, calls) = cache(TTL);
+        cache
+            .discover_invehicle_digital_twin_service()
+            .await
+            .unwrap();
+        let key = provider_key(URI);
+        cache.insert(
+            &cache.providers,
+            key.clone(),
+            endpoint("http://0.0.0.0:4010"),
+        );
+
+        cache.clear();
+
+        assert!(cache.get(&cache.providers, &key).is_none());
+        cache
+            .discover_invehicle_digital_twin_service()
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,409 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Errors of service discovery and of digital twin providers.
+//!
+//! Both errors convert to and from [`tonic::Status`], so they can cross gRPC boundaries and be
+//! retried with a [`crate::utils::RetryPolicy`].
+
+use std::error::Error;
+use std::fmt;
+
+use tonic::{Code, Status};
+
+/// Why discovering a service or a digital twin provider failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiscoveryError {
+    /// The request is malformed, for example it has an invalid entity id.
+    InvalidRequest { context: String, message: String },
+    /// The service could not be reached or did not answer in time.
+    Connection { context: String, message: String },
+    /// Nothing matched the request.
+    NotFound { context: String, message: String },
+    /// A match was found, but not with the required protocol, operations or communication kind.
+    ProtocolMismatch { context: String, message: String },
+    /// The response did not have the expected content.
+    MalformedResponse { context: String, message: String },
+    /// The call failed with another status.
+    Rpc {
+        context: String,
+        code: Code,
+        message: String,
+    },
+}
+
+impl DiscoveryError {
+    /// Create an invalid request error.
+    ///
+    /// # Arguments
+    /// * `message` - What is wrong with the request.
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        DiscoveryError::InvalidRequest {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a connection error.
+    ///
+    /// # Arguments
+    /// * `message` - What failed.
+    pub fn connection(message: impl Into<String>) -> Self {
+        DiscoveryError::Connection {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a not found error.
+    ///
+    /// # Arguments
+    /// * `message` - What was not found.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        DiscoveryError::NotFound {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a protocol mismatch error.
+    ///
+    /// # Arguments
+    /// * `message` - What was required and what was found.
+    pub fn protocol_mismatch(message: impl Into<String>) -> Self {
+        DiscoveryError::ProtocolMismatch {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a malformed response error.
+    ///
+    /// # Arguments
+    /// * `message` - What is wrong with the response.
+    pub fn malformed_response(message: impl Into<String>) -> Self {
+        DiscoveryError::MalformedResponse {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Add context to the error, for example the service that was called. Context that is added
+    /// later is shown first.
+    ///
+    /// # Arguments
+    /// * `context` - The context.
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        let existing = match &mut self {
+            DiscoveryError::InvalidRequest { context, .. }
+            | DiscoveryError::Connection { context, .. }
+            | DiscoveryError::NotFound { context, .. }
+            | DiscoveryError::ProtocolMismatch { context, .. }
+            | DiscoveryError::MalformedResponse { context, .. }
+            | DiscoveryError::Rpc { context, .. } => context,
+        };
+        *existing = join_context(context.into(), existing);
+        self
+    }
+
+    /// The context of the error.
+    pub fn context(&self) -> &str {
+        match self {
+            DiscoveryError::InvalidRequest { context, .. }
+            | DiscoveryError::Connection { context, .. }
+            | DiscoveryError::NotFound { context, .. }
+            | DiscoveryError::ProtocolMismatch { context, .. }
+            | DiscoveryError::MalformedResponse { context, .. }
+            | DiscoveryError::Rpc { context, .. } => context,
+        }
+    }
+
+    /// The error's message, without its context.
+    pub fn message(&self) -> &str {
+        match self {
+            DiscoveryError::InvalidRequest { message, .. }
+            | DiscoveryError::Connection { message, .. }
+            | DiscoveryError::NotFound { message, .. }
+            | DiscoveryError::ProtocolMismatch { message, .. }
+            | DiscoveryError::MalformedResponse { message, .. }
+            | DiscoveryError::Rpc { message, .. } => message,
+        }
+    }
+
+    /// The gRPC status code that the error converts to.
+    pub fn code(&self) -> Code {
+        match self {
+            DiscoveryError::InvalidRequest { .. } => Code::InvalidArgument,
+            DiscoveryError::Connection { .. } => Code::Unavailable,
+            DiscoveryError::NotFound { .. } => Code::NotFound,
+            DiscoveryError::ProtocolMismatch { .. } => Code::FailedPrecondition,
+            DiscoveryError::MalformedResponse { .. } => Code::Internal,
+            DiscoveryError::Rpc { code, .. } => *code,
+        }
+    }
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_with_context(f, self.context(), self.message())
+    }
+}
+
+impl Error for DiscoveryError {}
+
+/// Whether a call failed because the service could not be reached or did not answer in time, in
+/// which case the URI that was called may be stale.
+///
+/// # Arguments
+/// * `status` - The status that the call failed with.
+pub fn is_connection_error(status: &Status) -> bool {
+    match status.code() {
+        Code::Unavailable | Code::DeadlineExceeded => true,
+        // tonic reports transport failures as unknown, with the transport error as the source.
+        Code::Unknown => status.source().is_some(),
+        _ => false,
+    }
+}
+
+impl From<Status> for DiscoveryError {
+    fn from(status: Status) -> Self {
+        let context = String::new();
+        let message = status.message().to_string();
+
+        match status.code() {
+            Code::InvalidArgument => DiscoveryError::InvalidRequest { context, message },
+            _ if is_connection_error(&status) => DiscoveryError::Connection { context, message },
+            Code::NotFound => DiscoveryError::NotFound { context, message },
+            Code::FailedPrecondition => DiscoveryError::ProtocolMismatch { context, message },
+            code => DiscoveryError::Rpc {
+                context,
+                code,
+                message,
+            },
+        }
+    }
+}
+
+impl From<DiscoveryError> for Status {
+    fn from(error: DiscoveryError) -> Self {
+        Status::new(error.code(), error.to_string())
+    }
+}
+
+/// Why a digital twin provider could not handle a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProviderError {
+    /// The request is malformed, for example it has an invalid entity id.
+    InvalidArgument { context: String, message: String },
+    /// The request refers to an entity or topic that the provider does not know.
+    NotFound { context: String, message: String },
+    /// A service that the provider depends on, such as the MQTT broker, could not be reached.
+    Unavailable { context: String, message: String },
+    /// The provider failed for another reason.
+    Internal { context: String, message: String },
+}
+
+impl ProviderError {
+    /// Create an invalid argument error.
+    ///
+    /// # Arguments
+    /// * `message` - What is wrong with the request.
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        ProviderError::InvalidArgument {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a not found error.
+    ///
+    /// # Arguments
+    /// * `message` - What was not found.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ProviderError::NotFound {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create an unavailable error.
+    ///
+    /// # Arguments
+    /// * `message` - What could not be reached.
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        ProviderError::Unavailable {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Create an internal error.
+    ///
+    /// # Arguments
+    /// * `message` - What failed.
+    pub fn internal(message: impl Into<String>) -> Self {
+        ProviderError::Internal {
+            context: String::new(),
+            message: message.into(),
+        }
+    }
+
+    /// Add context to the error, for example the entity that was requested. Context that is added
+    /// later is shown first.
+    ///
+    /// # Arguments
+    /// * `context` - The context.
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        let existing = match &mut self {
+            ProviderError::InvalidArgument { context, .. }
+            | ProviderError::NotFound { context, .. }
+            | ProviderError::Unavailable { context, .. }
+            | ProviderError::Internal { context, .. } => context,
+        };
+        *existing = join_context(context.into(), existing);
+        self
+    }
+
+    /// The context of the error.
+    pub fn context(&self) -> &str {
+        match self {
+            ProviderError::InvalidArgument { context, .. }
+            | ProviderError::NotFound { context, .. }
+            | ProviderError::Unavailable { context, .. }
+            | ProviderError::Internal { context, .. } => context,
+        }
+    }
+
+    /// The error's message, without its context.
+    pub fn message(&self) -> &str {
+        match self {
+            ProviderError::InvalidArgument { message, .. }
+            | ProviderError::NotFound { message, .. }
+            | ProviderError::Unavailable { message, .. }
+            | ProviderError::Internal { message, .. } => message,
+        }
+    }
+
+    /// The gRPC status code that the error converts to.
+    pub fn code(&self) -> Code {
+        match self {
+            ProviderError::InvalidArgument { .. } => Code::InvalidArgument,
+            ProviderError::NotFound { .. } => Code::NotFound,
+            ProviderError::Unavailable { .. } => Code::Unavailable,
+            ProviderError::Internal { .. } => Code::Internal,
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_with_context(f, self.context(), self.message())
+    }
+}
+
+impl Error for ProviderError {}
+
+impl From<Status> for ProviderError {
+    fn from(status: Status) -> Self {
+        let context = String::new();
+        let message = status.message().to_string();
+
+        match status.code() {
+            Code::InvalidArgument => ProviderError::InvalidArgument { context, message },
+            Code::NotFound => ProviderError::NotFound { context, message },
+            Code::Unavailable => ProviderError::Unavailable { context, message },
+            _ => ProviderError::Internal { context, message },
+        }
+    }
+}
+
+impl From<ProviderError> for Status {
+    fn from(error: ProviderError) -> Self {
+        Status::new(error.code(), error.to_string())
+    }
+}
+
+/// Put new context in front of existing context.
+///
+/// # Arguments
+/// * `context` - The new context.
+/// * `existing` - The existing context, which may be empty.
+fn join_context(context: String, existing: &str) -> String {
+    if existing.is_empty() {
+        context
+    } else {
+        format!("{context}: {existing}")
+    }
+}
+
+/// Write a message, preceded by its context if there is any.
+///
+/// # Arguments
+/// * `f` - The formatter.
+/// * `context` - The context, which may be empty.
+/// * `message` - The message.
+fn write_with_context(f: &mut fmt::Formatter<'_>, context: &str, message: &str) -> fmt::Result {
+    if context.is_empty() {
+        f.write_str(message)
+    } else {
+        write!(f, "{context}: {message}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreachable_services_are_connection_errors() {
+        for status in [
+            Status::unavailable("down"),
+            Status::deadline_exceeded("too slow"),
+            Status::from_error("transport error".into()),
+        ] {
+            assert!(
+                matches!(
+                    DiscoveryError::from(status.clone()),
+                    DiscoveryError::Connection { .. }
+                ),
+                "{status:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_from_the_service_are_rpc_errors() {
+        let error = DiscoveryError::from(Status::unknown("failed"));
+
+        assert_eq!(error.code(), Code::Unknown);
+        assert!(matches!(error, DiscoveryError::Rpc { .. }));
+    }
+
+    #[test]
+    fn statuses_map_to_their_discovery_errors() {
+        assert!(matches!(
+            DiscoveryError::from(Status::invalid_argument("bad id")),
+            DiscoveryError::InvalidRequest { .. }
+        ));
+        assert!(matches!(
+            DiscoveryError::from(Status::not_found("none")),
+            DiscoveryError::NotFound { .. }
+        ));
+        assert!(matches!(
+            DiscoveryError::from(Status::failed_precondition("no grpc")),
+            DiscoveryError::ProtocolMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn context_is_prepended_to_the_message() {
+        let error = DiscoveryError::from(Status::not_found("no provider"))
+            .with_context("FindById")
+            .with_context("trailer weight");
+
+        assert_eq!(error.message(), "no provider");
+        assert_eq!(error.to_string(), "trailer weight: FindById: no provider");
+        assert_eq!(Status::from(error).code(), Code::NotFound);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The serving status that a provider reports through the standard `grpc.health.v1.Health`
+//! service.
+//!
+//! A provider is serving when its entities are registered with the In-Vehicle Digital Twin
+//! Service and all of its readiness checks pass, for example when its MQTT broker can be reached.
+//! The status is reported for the whole server, that is the empty service name, and for each of
+//! the provider's services. The [`REGISTRATION_SERVICE`] reports the registration alone, so that
+//! a provider that is not serving can be told apart from one whose entities are not registered.
+
+use std::sync::Arc;
+
+use log::{info, warn};
+use tokio::sync::{watch, Notify};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::registration_supervisor::{RegistrationState, RegistrationStatus};
+use crate::shutdown::CancellationToken;
+
+/// The name under which the health service reports whether the provider's entities are
+/// registered with the In-Vehicle Digital Twin Service.
+pub const REGISTRATION_SERVICE: &str = "registration";
+
+/// A condition that must hold for a provider to serve.
+#[derive(Clone, Debug)]
+pub struct ReadinessCheck {
+    name: String,
+    ready: watch::Receiver<bool>,
+}
+
+impl ReadinessCheck {
+    /// Create a readiness check.
+    ///
+    /// # Arguments
+    /// * `name` - The check's name, which is logged when it fails.
+    /// * `ready` - Whether the condition holds. It is watched for changes.
+    pub fn new(name: &str, ready: watch::Receiver<bool>) -> Self {
+        ReadinessCheck {
+            name: name.to_string(),
+            ready,
+        }
+    }
+}
+
+/// Report a provider's serving status until the token is cancelled. The services are then
+/// reported as not serving, so that the clients stop using the provider while it shuts down.
+///
+/// # Arguments
+/// * `reporter` - Sets the status of the health service.
+/// * `services` - The names of the provider's services.
+/// * `registration` - The registration status of the provider's entities.
+/// * `checks` - The provider's readiness checks.
+/// * `shutdown` - Stops the reporting when it is cancelled.
+pub async fn report_health(
+    mut reporter: HealthReporter,
+    services: Vec<String>,
+    registration: watch::Receiver<RegistrationStatus>,
+    checks: Vec<ReadinessCheck>,
+    shutdown: CancellationToken,
+) {
+    // Wake up the reporter whenever the registration or a readiness check changes.
+    let changed = Arc::new(Notify::new());
+    let mut watchers = vec![tokio::spawn(notify_on_change(
+        registration.clone(),
+        changed.clone(),
+    ))];
+    for check in &checks {
+        watchers.push(tokio::spawn(notify_on_change(
+            check.ready.clone(),
+            changed.clone(),
+        )));
+    }
+
+    let mut serving = None;
+    let mut registered = None;
+    loop {
+        let state = registration.borrow().state;
+        if registered != Some(state == RegistrationState::Registered) {
+            registered = Some(state == RegistrationState::Registered);
+            reporter
+                .set_service_status(REGISTRATION_SERVICE, registration_serving_status(state))
+                .await;
+        }
+
+        let failures = failures(&registration, &checks);
+        if serving != Some(failures.is_empty()) {
+            serving = Some(failures.is_empty());
+            if failures.is_empty() {
+                info!("The Provider is serving.");
+                set_status(&mut reporter, &services, ServingStatus::Serving).await;
+            } else {
+                warn!("The Provider is not serving: {}", failures.join("; "));
+                set_status(&mut reporter, &services, ServingStatus::NotServing).await;
+            }
+        }
+
+        tokio::select! {
+            _ = changed.notified() => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+
+    set_status(&mut reporter, &services, ServingStatus::NotServing).await;
+    reporter
+        .set_service_status(REGISTRATION_SERVICE, ServingStatus::NotServing)
+        .await;
+    for watcher in watchers {
+        watcher.abort();
+    }
+}
+
+/// Describe why a provider cannot serve. Returns an empty list if it can.
+///
+/// # Arguments
+/// * `registration` - The registration status of the provider's entities.
+/// * `checks` - The provider's readiness checks.
+fn failures(
+    registration: &watch::Receiver<RegistrationStatus>,
+    checks: &[ReadinessCheck],
+) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    let state = registration.borrow().state;
+    if state != RegistrationState::Registered {
+        failures.push(format!("the registration is {state}"));
+    }
+
+    for check in checks {
+        if !*check.ready.borrow() {
+            failures.push(format!("{} is not ready", check.name));
+        }
+    }
+
+    failures
+}
+
+/// The status that the [`REGISTRATION_SERVICE`] reports for a registration state.
+///
+/// # Arguments
+/// * `state` - The registration state.
+fn registration_serving_status(state: RegistrationState) -> ServingStatus {
+    if state == RegistrationState::Registered {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    }
+}
+
+/// Set the status of the whole server and of each service.
+///
+/// # Arguments
+/// * `reporter` - Sets the status of the health service.
+/// * `services` - The names of the provider's services.
+/// * `status` - The status.
+async fn set_status(reporter: &mut HealthReporter, services: &[String], status: ServingStatus) {
+    reporter.set_service_status("", status).await;
+    for service in services {
+        reporter.set_service_status(service, status).await;
+    }
+}
+
+/// Notify a waiter each time a watched value changes, until its sender is dropped.
+///
+/// # Arguments
+/// * `receiver` - The watched value.
+/// * `changed` - Notified of the changes.
+async fn notify_on_change<T>(mut receiver: watch::Receiver<T>, changed: Arc<Notify>) {
+    while receiver.changed().await.is_ok() {
+        changed.notify_one();
+    }
+}
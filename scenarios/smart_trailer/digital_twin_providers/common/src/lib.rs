@@ -2,5 +2,18 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod chariott_registration;
+pub mod config;
 pub mod constants;
+pub mod discovery_cache;
+pub mod errors;
+pub mod health;
+pub mod managed_subscribe;
+pub mod property_value;
+pub mod provider_runtime;
+pub mod registration_supervisor;
+pub mod service_matching;
+pub mod service_resolver;
+pub mod shutdown;
+pub mod tls;
 pub mod utils;
@@ -0,0 +1,396 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shared pieces of the providers that publish through Ibeji's Managed Subscribe module.
+//!
+//! The module calls a provider's `ManagedSubscribeCallback` service to start or stop publishing an
+//! entity to a topic, and the provider publishes the values to the MQTT broker of the topic's
+//! subscription info. A [`TopicManager`] records the topics of a provider's entities, handles
+//! the actions of the module and stops the publish tasks, so that a provider only implements how
+//! an entity is published to a topic. Publishing requires the `mqtt` feature.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::str::FromStr;
+
+use digital_twin_model::dtmi::Dtmi;
+use invehicle_stack_interfaces::module::managed_subscribe::v1::{
+    CallbackPayload, TopicManagementRequest, TopicManagementResponse,
+};
+use log::{debug, warn};
+use parking_lot::RwLock;
+use strum_macros::{Display, EnumString};
+use tokio::task::JoinHandle;
+
+use crate::errors::ProviderError;
+use crate::shutdown::CancellationToken;
+
+/// Actions that are returned from the Pub Sub Service.
+#[derive(Clone, EnumString, Eq, Display, Debug, PartialEq)]
+pub enum ProviderAction {
+    #[strum(serialize = "PUBLISH")]
+    Publish,
+
+    #[strum(serialize = "STOP_PUBLISH")]
+    StopPublish,
+}
+
+/// A topic that an entity is published to.
+#[derive(Debug)]
+struct TopicInfo {
+    topic: String,
+    /// Stops the publish task when it is cancelled.
+    stop: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
+/// Records the topics that a provider's entities are published to.
+#[derive(Debug)]
+pub struct TopicManager {
+    entity_map: RwLock<HashMap<String, Vec<TopicInfo>>>,
+    shutdown: CancellationToken,
+}
+
+impl TopicManager {
+    /// Create a topic manager.
+    ///
+    /// # Arguments
+    /// * `entity_ids` - The ids of the entities that the provider publishes.
+    /// * `shutdown` - Stops all publish tasks when it is cancelled.
+    pub fn new<'a>(
+        entity_ids: impl IntoIterator<Item = &'a str>,
+        shutdown: CancellationToken,
+    ) -> Self {
+        let entity_map = entity_ids
+            .into_iter()
+            .map(|entity_id| (entity_id.to_string(), Vec::new()))
+            .collect();
+
+        TopicManager {
+            entity_map: RwLock::new(entity_map),
+            shutdown,
+        }
+    }
+
+    /// Handle an action of the Managed Subscribe module, for a provider's
+    /// `ManagedSubscribeCallback::topic_management_cb`.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the action and associated payload.
+    /// * `handle_publish_action` - Handles the 'PUBLISH' action, usually by calling
+    ///   [`TopicManager::start_publishing`].
+    pub fn handle_topic_management(
+        &self,
+        request: TopicManagementRequest,
+        handle_publish_action: impl FnOnce(CallbackPayload) -> Result<(), ProviderError>,
+    ) -> Result<TopicManagementResponse, ProviderError> {
+        let action = request.action;
+        let payload = request
+            .payload
+            .ok_or_else(|| ProviderError::invalid_argument("Failed to get payload"))?;
+
+        let provider_action = ProviderAction::from_str(&action).map_err(|err| {
+            ProviderError::invalid_argument(format!(
+                "Failed to parse action '{action}' due to '{err}'"
+            ))
+        })?;
+
+        match provider_action {
+            ProviderAction::Publish => handle_publish_action(payload)?,
+            ProviderAction::StopPublish => self.handle_stop_publish_action(payload)?,
+        }
+
+        Ok(TopicManagementResponse {})
+    }
+
+    /// Start a task that publishes an entity to a topic, and record the topic.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The entity's id.
+    /// * `topic` - The topic.
+    /// * `publish` - Creates the publish task from a token that is cancelled when publishing to
+    ///   the topic stops. The task must finish once the token is cancelled.
+    pub fn start_publishing<F>(
+        &self,
+        entity_id: &Dtmi,
+        topic: &str,
+        publish: impl FnOnce(CancellationToken) -> F,
+    ) -> Result<(), ProviderError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut entity_lock = self.entity_map.write();
+        let topics = entity_lock.get_mut(&entity_id.to_string()).ok_or_else(|| {
+            ProviderError::not_found("This provider does not provide the entity")
+                .with_context(format!("Publish {entity_id} to {topic}"))
+        })?;
+
+        let stop = self.shutdown.child_token();
+        let handle = tokio::spawn(publish(stop.clone()));
+        topics.push(TopicInfo {
+            topic: topic.to_string(),
+            stop,
+            handle,
+        });
+
+        Ok(())
+    }
+
+    /// Handles the 'STOP_PUBLISH' action from the callback.
+    ///
+    /// # Arguments
+    /// `payload` - Payload sent with the 'STOP_PUBLISH' action.
+    pub fn handle_stop_publish_action(
+        &self,
+        payload: CallbackPayload,
+    ) -> Result<(), ProviderError> {
+        let entity_id = Dtmi::parse(&payload.entity_id).map_err(ProviderError::invalid_argument)?;
+        let context = || format!("Stop publishing {entity_id} to {}", payload.topic);
+
+        let mut entity_lock = self.entity_map.write();
+        let topics = entity_lock.get_mut(&entity_id.to_string()).ok_or_else(|| {
+            ProviderError::not_found("This provider does not provide the entity")
+                .with_context(context())
+        })?;
+
+        // Check to see if topic exists.
+        if let Some(index) = topics.iter().position(|t| t.topic == payload.topic) {
+            // Remove topic, and stop publishing to it.
+            topics.swap_remove(index).stop.cancel();
+            Ok(())
+        } else {
+            let error = ProviderError::not_found("No topic found").with_context(context());
+            warn!("{error}");
+            Err(error)
+        }
+    }
+
+    /// Stop every publish task and wait for them to finish.
+    pub async fn stop_publishing(&self) {
+        let topics: Vec<TopicInfo> = self
+            .entity_map
+            .write()
+            .values_mut()
+            .flat_map(|topics| topics.drain(..))
+            .collect();
+
+        for topic_info in topics {
+            topic_info.stop.cancel();
+            match topic_info.handle.await {
+                Ok(()) => debug!("Stopped publishing to {}.", topic_info.topic),
+                Err(err) => warn!("The publish task for {} failed: {err}", topic_info.topic),
+            }
+        }
+    }
+}
+
+/// Publish a message to a MQTT broker. Each message is published with its own MQTT connection,
+/// which is disconnected after the message. The blocking MQTT client runs on the blocking thread
+/// pool.
+///
+/// # Arguments
+/// `broker_uri` - The MQTT broker's URI.
+/// `client_id` - The MQTT client id.
+/// `topic` - The topic to publish to.
+/// `content` - The message to publish.
+#[cfg(feature = "mqtt")]
+pub async fn publish_message(
+    broker_uri: &str,
+    client_id: &str,
+    topic: &str,
+    content: &str,
+) -> Result<(), String> {
+    let (broker_uri, client_id, topic, content) = (
+        broker_uri.to_string(),
+        client_id.to_string(),
+        topic.to_string(),
+        content.to_string(),
+    );
+
+    tokio::task::spawn_blocking(move || {
+        publish_message_blocking(&broker_uri, &client_id, &topic, &content)
+    })
+    .await
+    .map_err(|err| format!("The publish task failed due to '{err}'"))?
+}
+
+/// Publish a message to a MQTT broker, blocking the thread.
+///
+/// # Arguments
+/// `broker_uri` - The MQTT broker's URI.
+/// `client_id` - The MQTT client id.
+/// `topic` - The topic to publish to.
+/// `content` - The message to publish.
+#[cfg(feature = "mqtt")]
+fn publish_message_blocking(
+    broker_uri: &str,
+    client_id: &str,
+    topic: &str,
+    content: &str,
+) -> Result<(), String> {
+    use paho_mqtt as mqtt;
+    use tokio::time::Duration;
+
+    let create_opts = mqtt::CreateOptionsBuilder::new()
+        .server_uri(broker_uri)
+        .client_id(client_id.to_string())
+        .finalize();
+
+    let client = mqtt::Client::new(create_opts)
+        .map_err(|err| format!("Failed to create the client due to '{err:?}'"))?;
+
+    let conn_opts = mqtt::ConnectOptionsBuilder::new()
+        .keep_alive_interval(Duration::from_secs(30))
+        .clean_session(true)
+        .finalize();
+
+    client
+        .connect(conn_opts)
+        .map_err(|err| format!("Failed to connect to broker {broker_uri} due to '{err:?}'"))?;
+
+    let msg = mqtt::Message::new(topic, content, mqtt::types::QOS_1);
+    client
+        .publish(msg)
+        .map_err(|err| format!("Failed to publish message due to '{err:?}"))?;
+
+    client.disconnect(None).map_err(|err| {
+        format!("Failed to disconnect from topic '{topic}' on broker {broker_uri} due to {err:?}")
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use tonic::Code;
+
+    use super::*;
+
+    const ENTITY_ID: &str = "dtmi:sdv:Trailer:IsTrailerConnected;1";
+    const TOPIC: &str = "trailer/connected";
+
+    fn request(action: &str, entity_id: &str, topic: &str) -> TopicManagementRequest {
+        TopicManagementRequest {
+            action: action.to_string(),
+            payload: Some(CallbackPayload {
+                entity_id: entity_id.to_string(),
+                topic: topic.to_string(),
+                constraints: Vec::new(),
+                subscription_info: None,
+            }),
+        }
+    }
+
+    /// Start a publish task that records when it finishes.
+    fn start(manager: &TopicManager, topic: &str) -> Arc<AtomicBool> {
+        let finished = Arc::new(AtomicBool::new(false));
+        let task_finished = finished.clone();
+        manager
+            .start_publishing(&Dtmi::parse(ENTITY_ID).unwrap(), topic, |stop| async move {
+                stop.cancelled().await;
+                task_finished.store(true, Ordering::SeqCst);
+            })
+            .unwrap();
+
+        finished
+    }
+
+    #[tokio::test]
+    async fn actions_are_dispatched() {
+        let manager = TopicManager::new([ENTITY_ID], CancellationToken::new());
+        let finished = start(&manager, TOPIC);
+
+        let mut published = None;
+        manager
+            .handle_topic_management(request("PUBLISH", ENTITY_ID, "other"), |payload| {
+                published = Some(payload.topic);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(published.as_deref(), Some("other"));
+
+        manager
+            .handle_topic_management(request("STOP_PUBLISH", ENTITY_ID, TOPIC), |_| {
+                panic!("not a publish action")
+            })
+            .unwrap();
+        tokio::task::yield_now().await;
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let manager = TopicManager::new([ENTITY_ID], CancellationToken::new());
+
+        let err = manager
+            .handle_topic_management(request("SUBSCRIBE", ENTITY_ID, TOPIC), |_| Ok(()))
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{err}");
+
+        let err = manager
+            .handle_topic_management(
+                TopicManagementRequest {
+                    action: "PUBLISH".to_string(),
+                    payload: None,
+                },
+                |_| Ok(()),
+            )
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{err}");
+
+        let err = manager
+            .handle_topic_management(request("STOP_PUBLISH", "not a dtmi", TOPIC), |_| Ok(()))
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{err}");
+    }
+
+    #[tokio::test]
+    async fn stopping_a_topic_stops_only_its_task() {
+        let manager = TopicManager::new([ENTITY_ID], CancellationToken::new());
+        let stopped = start(&manager, TOPIC);
+        let kept = start(&manager, "other");
+
+        let payload = request("STOP_PUBLISH", ENTITY_ID, TOPIC).payload.unwrap();
+        manager.handle_stop_publish_action(payload.clone()).unwrap();
+        tokio::task::yield_now().await;
+
+        assert!(stopped.load(Ordering::SeqCst));
+        assert!(!kept.load(Ordering::SeqCst));
+        let err = manager.handle_stop_publish_action(payload).unwrap_err();
+        assert_eq!(err.code(), Code::NotFound, "{err}");
+
+        manager.stop_publishing().await;
+        assert!(kept.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn unknown_entities_are_not_found() {
+        let manager = TopicManager::new([ENTITY_ID], CancellationToken::new());
+        let other_id = "dtmi:sdv:Trailer:Weight;2";
+
+        let err = manager
+            .start_publishing(&Dtmi::parse(other_id).unwrap(), TOPIC, |_| async {})
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound, "{err}");
+
+        let payload = request("STOP_PUBLISH", other_id, TOPIC).payload.unwrap();
+        let err = manager.handle_stop_publish_action(payload).unwrap_err();
+        assert_eq!(err.code(), Code::NotFound, "{err}");
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_every_task() {
+        let shutdown = CancellationToken::new();
+        let manager = TopicManager::new([ENTITY_ID], shutdown.clone());
+        let finished = start(&manager, TOPIC);
+
+        shutdown.cancel();
+        manager.stop_publishing().await;
+
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
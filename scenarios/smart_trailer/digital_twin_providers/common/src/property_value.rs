@@ -0,0 +1,662 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Typed property values of version 2 of the Get provider protocol.
+//!
+//! The providers keep their property values as JSON [`PropertyEnvelope`]s. [`to_property_value`]
+//! converts an envelope to the member of the `PropertyValue` oneof that the entity's DTDL schema
+//! calls for, so that a provider can serve properties of any schema: an Enum is served as its
+//! integer or string value, and an Object, Array or Map as JSON. [`from_property_value`] converts
+//! the value of a Set request back to an envelope, after checking it against the model.
+//!
+//! The Get helpers turn these conversions into the statuses of the Get provider protocol, and
+//! [`batch_get`] answers a BatchGet request of either version with one result per entity.
+
+use std::time::SystemTime;
+
+use digital_twin_model::dtdl::{element_type, primitive_schema, Schema};
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::property_envelope::PropertyEnvelope;
+use digital_twin_model::units::Unit;
+use serde_json::{Number, Value};
+use smart_trailer_interfaces::digital_twin_get_provider::v2::property_value;
+use smart_trailer_interfaces::digital_twin_get_provider::v2::{PropertyMetadata, PropertyValue};
+use smart_trailer_interfaces::digital_twin_get_provider::{v1, v2};
+use tonic::Code;
+
+use crate::errors::ProviderError;
+
+/// Convert a property envelope to a typed property value. The envelope is validated against the
+/// model first.
+///
+/// # Arguments
+/// * `model_registry` - The model that declares the envelope's entity.
+/// * `envelope` - The envelope.
+/// * `source_timestamp` - When the provider's source produced the value.
+pub fn to_property_value(
+    model_registry: &ModelRegistry,
+    envelope: &PropertyEnvelope<Value>,
+    source_timestamp: SystemTime,
+) -> Result<PropertyValue, String> {
+    model_registry.validate_envelope(envelope)?;
+
+    let id = envelope.metadata.model.as_str();
+    let entity = model_registry
+        .get_entity(id)
+        .ok_or_else(|| format!("Unknown entity '{id}'"))?;
+    let schema = entity
+        .schema()
+        .ok_or_else(|| format!("Entity '{id}' does not have a schema"))?;
+
+    Ok(PropertyValue {
+        value: Some(typed_value(schema, &envelope.value)?),
+        metadata: Some(PropertyMetadata {
+            model_id: id.to_string(),
+            unit: entity
+                .unit()
+                .map(|unit| unit.as_str().to_string())
+                .unwrap_or_default(),
+            source_timestamp: Some(source_timestamp.into()),
+        }),
+    })
+}
+
+/// Convert a typed property value to a property envelope of a writable Property. The value must
+/// conform to the Property's schema, and its metadata, which is optional, must name the Property
+/// and its unit.
+///
+/// # Arguments
+/// * `model_registry` - The model that declares the Property.
+/// * `id` - The Property's DTMI.
+/// * `property_value` - The typed value.
+pub fn from_property_value(
+    model_registry: &ModelRegistry,
+    id: &str,
+    property_value: &PropertyValue,
+) -> Result<PropertyEnvelope<Value>, String> {
+    let entity = model_registry
+        .get_entity(id)
+        .ok_or_else(|| format!("Unknown entity '{id}'"))?;
+    if !entity.content.is(element_type::PROPERTY) || !entity.content.writable {
+        return Err(format!("'{id}' is not a writable Property"));
+    }
+
+    let mut unit = None;
+    if let Some(metadata) = &property_value.metadata {
+        if !metadata.model_id.is_empty() && metadata.model_id != id {
+            return Err(format!(
+                "The value is of '{}', not of '{id}'",
+                metadata.model_id
+            ));
+        }
+        if !metadata.unit.is_empty() {
+            unit = Some(Unit::parse(&metadata.unit)?);
+        }
+    }
+
+    let value = property_value
+        .value
+        .as_ref()
+        .ok_or_else(|| format!("The value of '{id}' is not set"))?;
+    let envelope = PropertyEnvelope::new(&entity.content.name, id, json_value(value)?)
+        .with_unit(unit.or(entity.unit()));
+    model_registry.validate_envelope(&envelope)?;
+
+    Ok(envelope)
+}
+
+/// Get the value of a boolean property, for version 1 of the Get provider protocol, which can
+/// only return booleans. Any other value is rejected with the advice to use version 2.
+///
+/// # Arguments
+/// * `entity_id` - The property's entity id, as it was requested.
+/// * `envelope` - The property's envelope.
+pub fn get_bool(
+    entity_id: &str,
+    envelope: &PropertyEnvelope<Value>,
+) -> Result<bool, ProviderError> {
+    envelope.value.as_bool().ok_or_else(|| {
+        ProviderError::invalid_argument(
+            "The property is not a boolean, get it with version 2 of the Get provider protocol",
+        )
+        .with_context(format!("Get {entity_id}"))
+    })
+}
+
+/// Get the typed value of a property, for version 2 of the Get provider protocol. A value that
+/// does not conform to the model is the provider's fault, so it is an internal error.
+///
+/// # Arguments
+/// * `model_registry` - The model that declares the property.
+/// * `entity_id` - The property's entity id, as it was requested.
+/// * `envelope` - The property's envelope.
+/// * `source_timestamp` - When the provider's source produced the value.
+pub fn get_typed(
+    model_registry: &ModelRegistry,
+    entity_id: &str,
+    envelope: &PropertyEnvelope<Value>,
+    source_timestamp: SystemTime,
+) -> Result<PropertyValue, ProviderError> {
+    to_property_value(model_registry, envelope, source_timestamp)
+        .map_err(|err| ProviderError::internal(err).with_context(format!("Get {entity_id}")))
+}
+
+/// The result for one entity of a BatchGet request.
+pub trait BatchGetResult {
+    /// The value that the version of the Get provider protocol returns.
+    type Value;
+
+    /// Create the result for an entity from its value, or from the error that prevented getting
+    /// it.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The entity id, as it was requested.
+    /// * `result` - The entity's value, or the error.
+    fn new(entity_id: String, result: Result<Self::Value, ProviderError>) -> Self;
+}
+
+impl BatchGetResult for v1::BatchGetResult {
+    type Value = bool;
+
+    fn new(entity_id: String, result: Result<bool, ProviderError>) -> Self {
+        match result {
+            Ok(property_value) => v1::BatchGetResult {
+                entity_id,
+                status_code: Code::Ok as i32,
+                status_message: String::new(),
+                property_value,
+            },
+            Err(err) => v1::BatchGetResult {
+                entity_id,
+                status_code: err.code() as i32,
+                status_message: err.to_string(),
+                property_value: false,
+            },
+        }
+    }
+}
+
+impl BatchGetResult for v2::BatchGetResult {
+    type Value = PropertyValue;
+
+    fn new(entity_id: String, result: Result<PropertyValue, ProviderError>) -> Self {
+        match result {
+            Ok(value) => v2::BatchGetResult {
+                entity_id,
+                status_code: Code::Ok as i32,
+                status_message: String::new(),
+                value: Some(value),
+            },
+            Err(err) => v2::BatchGetResult {
+                entity_id,
+                status_code: err.code() as i32,
+                status_message: err.to_string(),
+                value: None,
+            },
+        }
+    }
+}
+
+/// Get the values of several entities for a BatchGet request. The results are in the order of
+/// the entity ids, and an entity that cannot be got has its own status instead of failing the
+/// request.
+///
+/// # Arguments
+/// * `entity_ids` - The requested entity ids.
+/// * `get` - Gets the value of one entity.
+pub fn batch_get<T: BatchGetResult>(
+    entity_ids: Vec<String>,
+    get: impl Fn(&str) -> Result<T::Value, ProviderError>,
+) -> Vec<T> {
+    entity_ids
+        .into_iter()
+        .map(|entity_id| {
+            let result = get(&entity_id);
+            T::new(entity_id, result)
+        })
+        .collect()
+}
+
+/// Convert a typed value to JSON.
+///
+/// # Arguments
+/// * `value` - The typed value.
+fn json_value(value: &property_value::Value) -> Result<Value, String> {
+    Ok(match value {
+        property_value::Value::BoolValue(value) => Value::Bool(*value),
+        property_value::Value::Int32Value(value) => Value::from(*value),
+        property_value::Value::Int64Value(value) => Value::from(*value),
+        property_value::Value::DoubleValue(value) => Number::from_f64(*value)
+            .map(Value::Number)
+            .ok_or_else(|| format!("'{value}' is not a finite number"))?,
+        property_value::Value::StringValue(value) => Value::String(value.clone()),
+        property_value::Value::BytesValue(_) => {
+            return Err("binary values do not have a DTDL schema".to_string())
+        }
+        property_value::Value::JsonValue(json) => serde_json::from_str(json)
+            .map_err(|err| format!("Invalid JSON value '{json}': {err}"))?,
+    })
+}
+
+/// Convert a JSON value to the typed value of its schema.
+///
+/// # Arguments
+/// * `schema` - The value's schema.
+/// * `value` - The value, which conforms to the schema.
+fn typed_value(schema: &Schema, value: &Value) -> Result<property_value::Value, String> {
+    match schema {
+        Schema::Named(name) => primitive_value(name, value),
+        Schema::Complex(complex) if complex.r#type == element_type::ENUM => match value {
+            Value::Number(_) => primitive_value(primitive_schema::INTEGER, value),
+            _ => primitive_value(primitive_schema::STRING, value),
+        },
+        Schema::Complex(_) => Ok(property_value::Value::JsonValue(value.to_string())),
+    }
+}
+
+/// Convert a JSON value to the typed value of a primitive schema.
+///
+/// # Arguments
+/// * `schema` - The primitive schema's name.
+/// * `value` - The value.
+fn primitive_value(schema: &str, value: &Value) -> Result<property_value::Value, String> {
+    let mismatch = || format!("expected a value of schema '{schema}', got '{value}'");
+
+    Ok(match schema {
+        primitive_schema::BOOLEAN => {
+            property_value::Value::BoolValue(value.as_bool().ok_or_else(mismatch)?)
+        }
+        primitive_schema::INTEGER => property_value::Value::Int32Value(
+            value
+                .as_i64()
+                .and_then(|v| i32::try_from(v).ok())
+                .ok_or_else(mismatch)?,
+        ),
+        primitive_schema::LONG => {
+            property_value::Value::Int64Value(value.as_i64().ok_or_else(mismatch)?)
+        }
+        primitive_schema::DOUBLE | primitive_schema::FLOAT => {
+            property_value::Value::DoubleValue(value.as_f64().ok_or_else(mismatch)?)
+        }
+        primitive_schema::STRING
+        | primitive_schema::DATE
+        | primitive_schema::DATE_TIME
+        | primitive_schema::DURATION
+        | primitive_schema::TIME => {
+            property_value::Value::StringValue(value.as_str().ok_or_else(mismatch)?.to_string())
+        }
+        other => return Err(format!("unsupported schema '{other}'")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+
+    const SENSOR_DOCUMENT: &str = r#"{
+        "@context": ["dtmi:dtdl:context;3", "dtmi:dtdl:extension:quantitativeTypes;1"],
+        "@type": "Interface",
+        "@id": "dtmi:test:Sensor;1",
+        "contents": [
+            { "@type": "Property", "name": "Flag", "schema": "boolean", "writable": true },
+            { "@type": "Property", "name": "Count", "schema": "integer", "writable": true },
+            { "@type": "Property", "name": "Total", "schema": "long", "writable": true },
+            { "@type": "Property", "name": "Level", "schema": "double", "writable": true },
+            { "@type": "Property", "name": "Ratio", "schema": "float" },
+            { "@type": "Property", "name": "Label", "schema": "string", "writable": true },
+            { "@type": "Property", "name": "Day", "schema": "date" },
+            {
+                "@type": ["Property", "Mass"], "name": "Load", "schema": "long", "unit": "kilogram",
+                "writable": true
+            },
+            {
+                "@type": "Property", "name": "Gear", "writable": true,
+                "schema": {
+                    "@type": "Enum", "valueSchema": "integer",
+                    "enumValues": [{ "name": "park", "enumValue": 0 }, { "name": "drive", "enumValue": 1 }]
+                }
+            },
+            {
+                "@type": "Property", "name": "Color",
+                "schema": {
+                    "@type": "Enum", "valueSchema": "string",
+                    "enumValues": [{ "name": "red", "enumValue": "red" }]
+                }
+            },
+            {
+                "@type": "Property", "name": "Position", "writable": true,
+                "schema": { "@type": "Object", "fields": [{ "name": "x", "schema": "double" }] }
+            },
+            {
+                "@type": "Property", "name": "History",
+                "schema": { "@type": "Array", "elementSchema": "integer" }
+            }
+        ]
+    }"#;
+
+    const LOAD: &str = "dtmi:test:Sensor:Load;1";
+    const LABEL: &str = "dtmi:test:Sensor:Label;1";
+
+    fn registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::new();
+        registry.load_str(SENSOR_DOCUMENT).unwrap();
+        registry
+    }
+
+    fn id(name: &str) -> String {
+        format!("dtmi:test:Sensor:{name};1")
+    }
+
+    /// An envelope of a property of the sensor, in the property's unit.
+    fn envelope(registry: &ModelRegistry, name: &str, value: Value) -> PropertyEnvelope<Value> {
+        let id = id(name);
+        let unit = registry.get_entity(&id).unwrap().unit();
+        PropertyEnvelope::new(name, &id, value).with_unit(unit)
+    }
+
+    fn typed(value: property_value::Value, model_id: &str, unit: &str) -> PropertyValue {
+        PropertyValue {
+            value: Some(value),
+            metadata: Some(PropertyMetadata {
+                model_id: model_id.to_string(),
+                unit: unit.to_string(),
+                source_timestamp: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn each_schema_is_converted_to_its_typed_value() {
+        use property_value::Value::*;
+
+        let registry = registry();
+        let cases = [
+            ("Flag", json!(true), BoolValue(true)),
+            ("Count", json!(-3), Int32Value(-3)),
+            ("Total", json!(5_000_000_000i64), Int64Value(5_000_000_000)),
+            ("Level", json!(0.25), DoubleValue(0.25)),
+            ("Ratio", json!(2), DoubleValue(2.0)),
+            ("Label", json!("front"), StringValue("front".to_string())),
+            (
+                "Day",
+                json!("2024-01-31"),
+                StringValue("2024-01-31".to_string()),
+            ),
+            ("Load", json!(1500), Int64Value(1500)),
+            ("Gear", json!(1), Int32Value(1)),
+            ("Color", json!("red"), StringValue("red".to_string())),
+            (
+                "Position",
+                json!({ "x": 1.5 }),
+                JsonValue(r#"{"x":1.5}"#.to_string()),
+            ),
+            ("History", json!([1, 2]), JsonValue("[1,2]".to_string())),
+        ];
+
+        for (name, value, expected) in cases {
+            let envelope = envelope(&registry, name, value);
+
+            let property_value = to_property_value(&registry, &envelope, SystemTime::now());
+
+            assert_eq!(
+                property_value.map(|property_value| property_value.value),
+                Ok(Some(expected)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn values_that_do_not_conform_are_rejected() {
+        let registry = registry();
+        let cases = [
+            ("Flag", json!("true")),
+            ("Count", json!(i64::from(i32::MAX) + 1)),
+            ("Total", json!(1.5)),
+            ("Gear", json!(2)),
+            ("Position", json!({ "x": "left" })),
+        ];
+
+        for (name, value) in cases {
+            let envelope = envelope(&registry, name, value);
+
+            assert!(
+                to_property_value(&registry, &envelope, SystemTime::now()).is_err(),
+                "{name}"
+            );
+        }
+
+        let unknown = PropertyEnvelope::new("Unknown", &id("Unknown"), json!(1));
+        assert!(to_property_value(&registry, &unknown, SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn the_metadata_names_the_entity_its_unit_and_the_source_timestamp() {
+        let registry = registry();
+        let source_timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+
+        let property_value = to_property_value(
+            &registry,
+            &envelope(&registry, "Load", json!(1500)),
+            source_timestamp,
+        )
+        .unwrap();
+
+        let metadata = property_value.metadata.unwrap();
+        assert_eq!(metadata.model_id, LOAD);
+        assert_eq!(metadata.unit, Unit::Kilogram.as_str());
+        let timestamp = metadata.source_timestamp.unwrap();
+        assert_eq!(
+            (timestamp.seconds, timestamp.nanos),
+            (1_700_000_000, 250_000_000)
+        );
+
+        let property_value = to_property_value(
+            &registry,
+            &envelope(&registry, "Flag", json!(false)),
+            source_timestamp,
+        )
+        .unwrap();
+        assert_eq!(property_value.metadata.unwrap().unit, "");
+    }
+
+    #[test]
+    fn typed_values_are_converted_back_to_envelopes() {
+        use property_value::Value::*;
+
+        let registry = registry();
+        let cases = [
+            ("Flag", BoolValue(true), json!(true)),
+            ("Count", Int32Value(7), json!(7)),
+            ("Total", Int64Value(-7), json!(-7)),
+            ("Level", DoubleValue(0.5), json!(0.5)),
+            ("Label", StringValue("rear".to_string()), json!("rear")),
+            ("Gear", Int32Value(0), json!(0)),
+            (
+                "Position",
+                JsonValue(r#"{"x":2.5}"#.to_string()),
+                json!({ "x": 2.5 }),
+            ),
+        ];
+
+        for (name, value, expected) in cases {
+            let property_value = PropertyValue {
+                value: Some(value),
+                metadata: None,
+            };
+
+            let envelope = from_property_value(&registry, &id(name), &property_value);
+
+            assert_eq!(
+                envelope,
+                Ok(PropertyEnvelope::new(name, &id(name), expected)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn matching_metadata_is_accepted() {
+        let registry = registry();
+
+        let envelope = from_property_value(
+            &registry,
+            LOAD,
+            &typed(property_value::Value::Int64Value(1500), LOAD, "kilogram"),
+        )
+        .unwrap();
+        assert_eq!(envelope.metadata.unit, Some(Unit::Kilogram));
+
+        // The metadata is optional, and so are its fields.
+        let envelope = from_property_value(
+            &registry,
+            LOAD,
+            &typed(property_value::Value::Int64Value(1500), "", ""),
+        )
+        .unwrap();
+        assert_eq!(envelope.metadata.unit, Some(Unit::Kilogram));
+    }
+
+    #[test]
+    fn a_model_id_or_unit_mismatch_is_rejected() {
+        let registry = registry();
+        let value = property_value::Value::Int64Value(1500);
+
+        let err =
+            from_property_value(&registry, LOAD, &typed(value.clone(), LABEL, "")).unwrap_err();
+        assert_eq!(err, format!("The value is of '{LABEL}', not of '{LOAD}'"));
+
+        for unit in ["gram", "pound", "furlong"] {
+            assert!(
+                from_property_value(&registry, LOAD, &typed(value.clone(), LOAD, unit)).is_err(),
+                "{unit}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_set_values_are_rejected() {
+        use property_value::Value::*;
+
+        let registry = registry();
+        let cases = [
+            ("Ratio", Some(DoubleValue(0.5))),
+            ("Unknown", Some(DoubleValue(0.5))),
+            ("Flag", None),
+            ("Flag", Some(BytesValue(vec![1]))),
+            ("Flag", Some(Int32Value(1))),
+            ("Level", Some(DoubleValue(f64::NAN))),
+            ("Position", Some(JsonValue("{".to_string()))),
+            ("Gear", Some(Int32Value(5))),
+        ];
+
+        for (name, value) in cases {
+            let property_value = PropertyValue {
+                value: value.clone(),
+                metadata: None,
+            };
+
+            assert!(
+                from_property_value(&registry, &id(name), &property_value).is_err(),
+                "{name}: {value:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn get_bool_rejects_values_that_are_not_booleans() {
+        let registry = registry();
+
+        assert!(get_bool("Flag", &envelope(&registry, "Flag", json!(true))).unwrap());
+
+        let err = get_bool("Count", &envelope(&registry, "Count", json!(1))).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{err}");
+    }
+
+    #[test]
+    fn get_typed_reports_non_conforming_values_as_internal_errors() {
+        let registry = registry();
+        let now = SystemTime::now();
+
+        assert!(get_typed(
+            &registry,
+            "Flag",
+            &envelope(&registry, "Flag", json!(true)),
+            now
+        )
+        .is_ok());
+
+        let err = get_typed(
+            &registry,
+            "Flag",
+            &envelope(&registry, "Flag", json!(1)),
+            now,
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), Code::Internal, "{err}");
+    }
+
+    #[test]
+    fn batch_get_keeps_the_order_and_reports_each_status() {
+        let get = |entity_id: &str| match entity_id {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(ProviderError::not_found("Unknown entity")),
+        };
+        let entity_ids = ["off", "unknown", "on"].map(String::from).to_vec();
+
+        let results: Vec<v1::BatchGetResult> = batch_get(entity_ids, get);
+
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.entity_id.as_str(),
+                    Code::from(result.status_code),
+                    result.status_message.is_empty(),
+                    result.property_value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("off", Code::Ok, true, false),
+                ("unknown", Code::NotFound, false, false),
+                ("on", Code::Ok, true, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_get_returns_typed_values() {
+        let registry = registry();
+        let get = |entity_id: &str| {
+            get_typed(
+                &registry,
+                entity_id,
+                &envelope(&registry, entity_id, json!(true)),
+                SystemTime::now(),
+            )
+        };
+        let entity_ids = ["Flag", "Count"].map(String::from).to_vec();
+
+        let results: Vec<v2::BatchGetResult> = batch_get(entity_ids, get);
+
+        assert_eq!(Code::from(results[0].status_code), Code::Ok);
+        assert_eq!(
+            results[0]
+                .value
+                .as_ref()
+                .and_then(|value| value.value.clone()),
+            Some(property_value::Value::BoolValue(true))
+        );
+        assert_eq!(Code::from(results[1].status_code), Code::Internal);
+        assert_eq!(results[1].value, None);
+    }
+}
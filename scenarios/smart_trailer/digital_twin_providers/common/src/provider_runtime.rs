@@ -0,0 +1,440 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The lifecycle shared by the digital twin providers.
+//!
+//! A [`ProviderRuntime`] serves the provider's gRPC services, registers the provider's entities
+//! with the In-Vehicle Digital Twin Service and registers the provider with Chariott. While it
+//! runs, a [`RegistrationSupervisor`] registers the entities again if the In-Vehicle Digital Twin
+//! Service loses them. The server also serves the standard gRPC health service, whose status
+//! follows the registration and the provider's readiness checks, and the gRPC reflection service,
+//! so that tools such as `grpcurl` can call the provider without its protos, with TLS if it is
+//! configured. On shutdown, which is control-c or SIGTERM unless another token is given, it stops
+//! serving and unregisters from Chariott.
+
+use std::convert::Infallible;
+use std::error::Error;
+use std::sync::Arc;
+
+use env_logger::{Builder, Target};
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::{EndpointInfo, EntityAccessInfo};
+use log::{debug, info, warn, LevelFilter};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Request, Response};
+use tonic::codegen::Service;
+use tonic::server::NamedService;
+use tonic::transport::server::{RoutesBuilder, TcpIncoming};
+use tonic::transport::{Body, Server};
+
+use crate::chariott_registration::{trailer_service_metadata, ChariottRegistration};
+use crate::config::{ChariottConfig, DiscoveryConfig, ProviderConfig, TlsConfig};
+use crate::health::{report_health, ReadinessCheck};
+use crate::registration_supervisor::{RegistrationStatus, RegistrationSupervisor};
+use crate::shutdown::{shutdown_on_signal, CancellationToken};
+use crate::tls::server_config;
+use crate::utils::RetryPolicy;
+
+/// Set up logging to stdout.
+///
+/// # Arguments
+/// * `level` - The most verbose level that is logged.
+pub fn init_logging(level: LevelFilter) {
+    Builder::new()
+        .filter(None, level)
+        .target(Target::Stdout)
+        .init();
+}
+
+/// An endpoint of an entity, without the provider's URI.
+#[derive(Clone, Debug)]
+struct EndpointDescriptor {
+    protocol: String,
+    operations: Vec<String>,
+    context: String,
+}
+
+/// Describes an entity that a provider registers with the In-Vehicle Digital Twin Service.
+#[derive(Clone, Debug)]
+pub struct EntityDescriptor {
+    name: String,
+    id: String,
+    description: String,
+    endpoints: Vec<EndpointDescriptor>,
+}
+
+impl EntityDescriptor {
+    /// Describe an entity.
+    ///
+    /// # Arguments
+    /// * `name` - The entity's name.
+    /// * `id` - The entity's id.
+    /// * `description` - The entity's description.
+    pub fn new(name: &str, id: &str, description: &str) -> Self {
+        EntityDescriptor {
+            name: name.to_string(),
+            id: id.to_string(),
+            description: description.to_string(),
+            endpoints: Vec::new(),
+        }
+    }
+
+    /// Add an endpoint that is served at the provider's URI.
+    ///
+    /// # Arguments
+    /// * `protocol` - The endpoint's protocol.
+    /// * `operations` - The operations that the endpoint supports.
+    /// * `context` - The endpoint's context, for example the entity id or an RPC name.
+    pub fn with_endpoint(mut self, protocol: &str, operations: &[&str], context: &str) -> Self {
+        self.endpoints.push(EndpointDescriptor {
+            protocol: protocol.to_string(),
+            operations: operations.iter().map(ToString::to_string).collect(),
+            context: context.to_string(),
+        });
+        self
+    }
+
+    /// The entity's access information, with endpoints at the provider's URI.
+    ///
+    /// # Arguments
+    /// * `provider_uri` - The provider's URI.
+    fn access_info(&self, provider_uri: &str) -> EntityAccessInfo {
+        EntityAccessInfo {
+            name: self.name.clone(),
+            id: self.id.clone(),
+            description: self.description.clone(),
+            endpoint_info_list: self
+                .endpoints
+                .iter()
+                .map(|endpoint| EndpointInfo {
+                    protocol: endpoint.protocol.clone(),
+                    operations: endpoint.operations.clone(),
+                    uri: provider_uri.to_string(),
+                    context: endpoint.context.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Runs a digital twin provider.
+#[derive(Debug)]
+pub struct ProviderRuntime {
+    name: String,
+    communication_reference: String,
+    chariott: ChariottConfig,
+    provider: ProviderConfig,
+    discovery: DiscoveryConfig,
+    tls: TlsConfig,
+    retry_policy: RetryPolicy,
+    entities: Vec<EntityDescriptor>,
+    routes: RoutesBuilder,
+    service_names: Vec<String>,
+    file_descriptor_sets: Vec<&'static [u8]>,
+    readiness_checks: Vec<ReadinessCheck>,
+    shutdown: Option<CancellationToken>,
+    registration_status: watch::Sender<RegistrationStatus>,
+}
+
+impl ProviderRuntime {
+    /// Create a runtime for a provider.
+    ///
+    /// # Arguments
+    /// * `name` - The provider's name, which it is registered under with Chariott.
+    /// * `provider` - The provider's configuration.
+    pub fn new(name: &str, provider: &ProviderConfig) -> Self {
+        ProviderRuntime {
+            name: name.to_string(),
+            communication_reference: String::new(),
+            chariott: ChariottConfig::default(),
+            provider: provider.clone(),
+            discovery: DiscoveryConfig::default(),
+            tls: TlsConfig::default(),
+            retry_policy: RetryPolicy::default(),
+            entities: Vec::new(),
+            routes: RoutesBuilder::default(),
+            service_names: Vec::new(),
+            file_descriptor_sets: Vec::new(),
+            readiness_checks: Vec::new(),
+            shutdown: None,
+            registration_status: watch::channel(RegistrationStatus::default()).0,
+        }
+    }
+
+    /// Set Chariott's configuration.
+    ///
+    /// # Arguments
+    /// * `chariott` - Chariott's configuration.
+    pub fn with_chariott(mut self, chariott: &ChariottConfig) -> Self {
+        self.chariott = chariott.clone();
+        self
+    }
+
+    /// Set how the In-Vehicle Digital Twin Service is discovered.
+    ///
+    /// # Arguments
+    /// * `discovery` - The discovery configuration.
+    pub fn with_discovery(mut self, discovery: &DiscoveryConfig) -> Self {
+        self.discovery = discovery.clone();
+        self
+    }
+
+    /// Set the TLS configuration of the server. The clients are configured with
+    /// [`crate::tls::configure_clients`].
+    ///
+    /// # Arguments
+    /// * `tls` - The TLS configuration.
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Self {
+        self.tls = tls.clone();
+        self
+    }
+
+    /// Set how the calls to Chariott and the In-Vehicle Digital Twin Service are retried.
+    ///
+    /// # Arguments
+    /// * `retry_policy` - The retry policy.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the communication reference that the provider is registered with in Chariott, such as
+    /// the proto of its services.
+    ///
+    /// # Arguments
+    /// * `communication_reference` - The communication reference.
+    pub fn with_communication_reference(mut self, communication_reference: &str) -> Self {
+        self.communication_reference = communication_reference.to_string();
+        self
+    }
+
+    /// Add an entity to register with the In-Vehicle Digital Twin Service.
+    ///
+    /// # Arguments
+    /// * `entity` - The entity.
+    pub fn with_entity(mut self, entity: EntityDescriptor) -> Self {
+        self.entities.push(entity);
+        self
+    }
+
+    /// Add a gRPC service to serve.
+    ///
+    /// # Arguments
+    /// * `service` - The service, for example a generated `...Server`.
+    pub fn with_service<S>(mut self, service: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.routes.add_service(service);
+        self.service_names.push(S::NAME.to_string());
+        self
+    }
+
+    /// Add the encoded file descriptor set of the provider's protos, which the gRPC reflection
+    /// service serves.
+    ///
+    /// # Arguments
+    /// * `file_descriptor_set` - The file descriptor set, for example a generated
+    ///   `FILE_DESCRIPTOR_SET`.
+    pub fn with_file_descriptor_set(mut self, file_descriptor_set: &'static [u8]) -> Self {
+        self.file_descriptor_sets.push(file_descriptor_set);
+        self
+    }
+
+    /// Add a condition that must hold for the provider to be reported as serving by the gRPC
+    /// health service.
+    ///
+    /// # Arguments
+    /// * `name` - The condition's name, which is logged when it fails.
+    /// * `ready` - Whether the condition holds.
+    pub fn with_readiness_check(mut self, name: &str, ready: watch::Receiver<bool>) -> Self {
+        self.readiness_checks.push(ReadinessCheck::new(name, ready));
+        self
+    }
+
+    /// Stop the provider when a token is cancelled, instead of on control-c or SIGTERM.
+    ///
+    /// # Arguments
+    /// * `shutdown` - The token.
+    pub fn with_shutdown_token(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Watch whether the provider's entities are registered with the In-Vehicle Digital Twin
+    /// Service. The status is updated while the provider runs.
+    pub fn registration_status(&self) -> watch::Receiver<RegistrationStatus> {
+        self.registration_status.subscribe()
+    }
+
+    /// Serve the services and register the provider, until the shutdown token is cancelled.
+    /// The server then stops accepting requests, finishes the requests in progress, and the
+    /// provider is unregistered from Chariott. If the provider fails to register, or the server
+    /// fails, the provider stops and the error is returned.
+    pub async fn run(self) -> Result<(), Box<dyn Error>> {
+        // A child token lets the provider stop itself on errors without cancelling the caller's
+        // token.
+        let shutdown = self
+            .shutdown
+            .clone()
+            .unwrap_or_else(shutdown_on_signal)
+            .child_token();
+        let addr = self.provider.socket_addr()?;
+        let provider_uri = self.provider.uri(self.tls.is_server_enabled());
+        debug!("The Provider URI is {provider_uri}");
+
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+        let mut reflection = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET);
+        for file_descriptor_set in &self.file_descriptor_sets {
+            reflection = reflection.register_encoded_file_descriptor_set(file_descriptor_set);
+        }
+
+        let mut routes = self.routes.clone();
+        routes
+            .add_service(health_service)
+            .add_service(reflection.build()?);
+
+        let mut server_builder = Server::builder();
+        if let Some(server_tls_config) = server_config(&self.tls)? {
+            server_builder = server_builder.tls_config(server_tls_config)?;
+        }
+
+        // Bind before registering, so that the provider is only registered once it can be called.
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let incoming =
+            TcpIncoming::from_listener(listener, true, None).map_err(|err| err.to_string())?;
+        info!("The HTTP server is listening on address '{local_addr}'");
+
+        let health = tokio::spawn(report_health(
+            health_reporter,
+            self.service_names.clone(),
+            self.registration_status(),
+            self.readiness_checks.clone(),
+            shutdown.clone(),
+        ));
+
+        // Serve while the provider registers, so that it can be called as soon as it is found.
+        let mut server = tokio::spawn(
+            server_builder
+                .add_routes(routes.routes())
+                .serve_with_incoming_shutdown(incoming, shutdown.clone().cancelled_owned()),
+        );
+
+        let mut server_result = None;
+        let mut registration_error = None;
+        let (supervisor, chariott_registration) = tokio::select! {
+            result = self.register(&provider_uri) => match result {
+                Ok((supervisor, chariott_registration)) => {
+                    let supervisor = tokio::spawn(supervisor.supervise(shutdown.clone()));
+                    (Some(supervisor), chariott_registration)
+                }
+                Err(err) => {
+                    warn!("The Provider failed to register, stopping it: {err}");
+                    registration_error = Some(err);
+                    (None, None)
+                }
+            },
+            result = &mut server => {
+                server_result = Some(result);
+                (None, None)
+            }
+            _ = shutdown.cancelled() => {
+                info!("The Provider was stopped before it completed its registration.");
+                (None, None)
+            }
+        };
+
+        if registration_error.is_some() {
+            shutdown.cancel();
+        }
+        let server_result = match server_result {
+            Some(result) => result,
+            None => server.await,
+        };
+        // The health reporting and the supervisor stop with the server, even if it failed.
+        shutdown.cancel();
+        info!("The HTTP server has stopped.");
+        health.await?;
+
+        if let Some(supervisor) = supervisor {
+            supervisor.await?;
+        }
+
+        // The In-Vehicle Digital Twin Service does not support unregistering entities, so only
+        // Chariott is told that the provider is gone.
+        if let Some(chariott_registration) = chariott_registration {
+            if let Err(err) = chariott_registration.unregister().await {
+                warn!("Failed to unregister from Chariott: {err}");
+            }
+        }
+
+        if let Some(err) = registration_error {
+            match server_result {
+                Ok(Err(server_err)) => warn!("The HTTP server failed: {server_err}"),
+                Err(server_err) => warn!("The HTTP server failed: {server_err}"),
+                Ok(Ok(())) => {}
+            }
+            return Err(err);
+        }
+        server_result??;
+
+        Ok(())
+    }
+
+    /// Register the entities with the In-Vehicle Digital Twin Service, and the provider with
+    /// Chariott. Returns the supervisor of the entities' registration, and the registration with
+    /// Chariott if there is one.
+    ///
+    /// # Arguments
+    /// * `provider_uri` - The provider's URI.
+    async fn register(
+        &self,
+        provider_uri: &str,
+    ) -> Result<(RegistrationSupervisor, Option<ChariottRegistration>), Box<dyn Error>> {
+        let discovery_cache = self
+            .discovery
+            .cache(&self.chariott, self.retry_policy.clone())?;
+
+        let entity_access_info_list: Vec<EntityAccessInfo> = self
+            .entities
+            .iter()
+            .map(|entity| entity.access_info(provider_uri))
+            .collect();
+
+        let supervisor = RegistrationSupervisor::new(
+            entity_access_info_list,
+            Arc::new(discovery_cache),
+            self.retry_policy.clone(),
+            self.provider.registration_check_interval(),
+        )
+        .with_status_sender(self.registration_status.clone());
+
+        supervisor.register().await?;
+        debug!("The Provider has registered with the In-Vehicle Digital Twin Service.");
+
+        // Make the provider visible to the tools and orchestrators that list the trailer services.
+        if !self.provider.register_with_chariott {
+            return Ok((supervisor, None));
+        }
+
+        let service =
+            trailer_service_metadata(&self.name, provider_uri, &self.communication_reference);
+        let chariott_registration = ChariottRegistration::try_register(
+            &self.chariott.uri,
+            service,
+            self.retry_policy.clone(),
+        )
+        .await;
+
+        Ok((supervisor, chariott_registration))
+    }
+}
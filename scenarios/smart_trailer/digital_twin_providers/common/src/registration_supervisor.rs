@@ -0,0 +1,561 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps a provider's entities registered with the In-Vehicle Digital Twin Service.
+//!
+//! The In-Vehicle Digital Twin Service forgets its registrations when it restarts. The
+//! [`RegistrationSupervisor`] periodically checks with `FindById` that the provider's entities are
+//! still registered, and registers them again when they are missing. The registration state is
+//! logged when it changes, and can be watched through [`RegistrationSupervisor::status`].
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::invehicle_digital_twin_client::InvehicleDigitalTwinClient;
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::{
+    EntityAccessInfo, FindByIdRequest, RegisterRequest,
+};
+use log::{debug, info, warn};
+use tokio::sync::watch;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tonic::{Code, Status};
+
+use crate::discovery_cache::DiscoveryCache;
+use crate::errors::{is_connection_error, DiscoveryError};
+use crate::shutdown::CancellationToken;
+use crate::tls::connect;
+use crate::utils::{connection_error, RetryPolicy};
+
+/// Whether the provider's entities are registered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationState {
+    /// The entities have not been registered yet.
+    Unregistered,
+    /// The entities were registered when they were last checked.
+    Registered,
+    /// Some entities were missing when they were last checked, and are being registered again.
+    Missing,
+    /// The In-Vehicle Digital Twin Service could not be reached when it was last checked.
+    Unreachable,
+}
+
+impl fmt::Display for RegistrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RegistrationState::Unregistered => "unregistered",
+            RegistrationState::Registered => "registered",
+            RegistrationState::Missing => "missing",
+            RegistrationState::Unreachable => "unreachable",
+        })
+    }
+}
+
+/// The registration state of a provider's entities.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistrationStatus {
+    pub state: RegistrationState,
+    /// The URI of the In-Vehicle Digital Twin Service that the entities are registered with.
+    pub invehicle_digital_twin_uri: Option<String>,
+    /// How many times the entities have been registered.
+    pub registration_count: u32,
+    /// When the registration was last checked or changed.
+    pub last_updated: Option<SystemTime>,
+    /// The error of the last failed check or registration.
+    pub last_error: Option<String>,
+}
+
+impl Default for RegistrationStatus {
+    fn default() -> Self {
+        RegistrationStatus {
+            state: RegistrationState::Unregistered,
+            invehicle_digital_twin_uri: None,
+            registration_count: 0,
+            last_updated: None,
+            last_error: None,
+        }
+    }
+}
+
+/// Registers a provider's entities, and registers them again when they go missing.
+#[derive(Debug)]
+pub struct RegistrationSupervisor {
+    entities: Vec<EntityAccessInfo>,
+    discovery_cache: Arc<DiscoveryCache>,
+    retry_policy: RetryPolicy,
+    check_interval: Duration,
+    status: watch::Sender<RegistrationStatus>,
+}
+
+impl RegistrationSupervisor {
+    /// Create a supervisor.
+    ///
+    /// # Arguments
+    /// * `entities` - The entities' access information.
+    /// * `discovery_cache` - Discovers the In-Vehicle Digital Twin Service.
+    /// * `retry_policy` - How the register calls are retried.
+    /// * `check_interval` - How often the registration is checked. Zero disables the checks.
+    pub fn new(
+        entities: Vec<EntityAccessInfo>,
+        discovery_cache: Arc<DiscoveryCache>,
+        retry_policy: RetryPolicy,
+        check_interval: Duration,
+    ) -> Self {
+        RegistrationSupervisor {
+            entities,
+            discovery_cache,
+            retry_policy,
+            check_interval,
+            status: watch::channel(RegistrationStatus::default()).0,
+        }
+    }
+
+    /// Use a status channel that was created earlier, for example before the provider started.
+    ///
+    /// # Arguments
+    /// * `status` - The channel's sender.
+    pub fn with_status_sender(mut self, status: watch::Sender<RegistrationStatus>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Watch the registration status.
+    pub fn status(&self) -> watch::Receiver<RegistrationStatus> {
+        self.status.subscribe()
+    }
+
+    /// Register the entities. If the In-Vehicle Digital Twin Service cannot be reached, it is
+    /// rediscovered in case it moved, and the entities are registered again.
+    pub async fn register(&self) -> Result<(), DiscoveryError> {
+        let mut invehicle_digital_twin_uri = self
+            .discovery_cache
+            .discover_invehicle_digital_twin_service()
+            .await?;
+
+        debug!("Sending a register request to the In-Vehicle Digital Twin Service URI {invehicle_digital_twin_uri}");
+        let mut result = self.register_with(&invehicle_digital_twin_uri).await;
+        if matches!(&result, Err(status) if is_connection_error(status)) {
+            // The In-Vehicle Digital Twin Service may have moved, so rediscover it and try again.
+            warn!("Failed to register with the In-Vehicle Digital Twin Service at {invehicle_digital_twin_uri}, rediscovering it");
+            self.discovery_cache
+                .invalidate_uri(&invehicle_digital_twin_uri);
+            invehicle_digital_twin_uri = self
+                .discovery_cache
+                .discover_invehicle_digital_twin_service()
+                .await?;
+            result = self.register_with(&invehicle_digital_twin_uri).await;
+        }
+
+        match result {
+            Ok(()) => {
+                self.update(
+                    RegistrationState::Registered,
+                    &invehicle_digital_twin_uri,
+                    None,
+                );
+                Ok(())
+            }
+            Err(status) => {
+                let err = DiscoveryError::from(status).with_context(format!(
+                    "In-Vehicle Digital Twin Service at {invehicle_digital_twin_uri}"
+                ));
+                self.update(
+                    RegistrationState::Unreachable,
+                    &invehicle_digital_twin_uri,
+                    Some(err.to_string()),
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// Check the registration periodically until the token is cancelled, and register the
+    /// entities again when they are missing.
+    ///
+    /// # Arguments
+    /// * `shutdown` - Stops the checks when it is cancelled.
+    pub async fn supervise(self, shutdown: CancellationToken) {
+        if self.check_interval.is_zero() {
+            debug!("The registration checks are disabled.");
+            return;
+        }
+
+        let mut checks = interval(self.check_interval);
+        checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, and the entities were just registered.
+        checks.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = checks.tick() => self.check_and_repair().await,
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        debug!("Stopped checking the registration.");
+    }
+
+    /// Check that the entities are registered, and register them again if they are not.
+    async fn check_and_repair(&self) {
+        let invehicle_digital_twin_uri = match self
+            .discovery_cache
+            .discover_invehicle_digital_twin_service()
+            .await
+        {
+            Ok(uri) => uri,
+            Err(err) => {
+                self.update(RegistrationState::Unreachable, "", Some(err.to_string()));
+                return;
+            }
+        };
+
+        match self.find_missing(&invehicle_digital_twin_uri).await {
+            Ok(missing) if missing.is_empty() => {
+                self.update(
+                    RegistrationState::Registered,
+                    &invehicle_digital_twin_uri,
+                    None,
+                );
+            }
+            Ok(missing) => {
+                self.update(
+                    RegistrationState::Missing,
+                    &invehicle_digital_twin_uri,
+                    Some(format!("Missing entities: {}", missing.join(", "))),
+                );
+                if let Err(err) = self.register().await {
+                    warn!("Failed to register the missing entities again: {err}");
+                }
+            }
+            Err(status) => {
+                if is_connection_error(&status) {
+                    self.discovery_cache
+                        .invalidate_uri(&invehicle_digital_twin_uri);
+                }
+                self.update(
+                    RegistrationState::Unreachable,
+                    &invehicle_digital_twin_uri,
+                    Some(status.message().to_string()),
+                );
+            }
+        }
+    }
+
+    /// Find the entities that are not registered. Returns their ids.
+    ///
+    /// # Arguments
+    /// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI.
+    async fn find_missing(&self, invehicle_digital_twin_uri: &str) -> Result<Vec<String>, Status> {
+        // A check is a single attempt, since the next check follows soon.
+        let check_policy = self.retry_policy.single_attempt();
+
+        let mut missing = Vec::new();
+        for entity in &self.entities {
+            let result = check_policy
+                .run("FindById", || async {
+                    let mut client = InvehicleDigitalTwinClient::new(
+                        connect(invehicle_digital_twin_uri)
+                            .await
+                            .map_err(connection_error)?,
+                    );
+                    let request = tonic::Request::new(FindByIdRequest {
+                        id: entity.id.clone(),
+                    });
+                    client.find_by_id(request).await
+                })
+                .await;
+
+            match result {
+                Ok(_) => {}
+                Err(status) if status.code() == Code::NotFound => missing.push(entity.id.clone()),
+                Err(status) => return Err(status),
+            }
+        }
+
+        Ok(missing)
+    }
+
+    /// Register the entities with an In-Vehicle Digital Twin Service.
+    ///
+    /// # Arguments
+    /// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI.
+    async fn register_with(&self, invehicle_digital_twin_uri: &str) -> Result<(), Status> {
+        self.retry_policy
+            .run("Register", || async {
+                let mut client = InvehicleDigitalTwinClient::new(
+                    connect(invehicle_digital_twin_uri)
+                        .await
+                        .map_err(connection_error)?,
+                );
+                let request = tonic::Request::new(RegisterRequest {
+                    entity_access_info_list: self.entities.clone(),
+                });
+                client.register(request).await
+            })
+            .await?;
+
+        self.status
+            .send_modify(|status| status.registration_count += 1);
+
+        Ok(())
+    }
+
+    /// Update the registration status, and log it if the state changed.
+    ///
+    /// # Arguments
+    /// * `state` - The new state.
+    /// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI, if it is known.
+    /// * `error` - The error that caused the state, if any.
+    fn update(
+        &self,
+        state: RegistrationState,
+        invehicle_digital_twin_uri: &str,
+        error: Option<String>,
+    ) {
+        self.status.send_modify(|status| {
+            if status.state != state {
+                let error = error.as_deref().unwrap_or_default();
+                match state {
+                    RegistrationState::Registered => info!(
+                        "The entities are registered with the In-Vehicle Digital Twin Service at {invehicle_digital_twin_uri} (registration {})",
+                        status.registration_count
+                    ),
+                    RegistrationState::Missing => warn!(
+                        "The In-Vehicle Digital Twin Service at {invehicle_digital_twin_uri} lost the registration, registering again. {error}"
+                    ),
+                    RegistrationState::Unreachable => warn!(
+                        "Cannot check the registration with the In-Vehicle Digital Twin Service: {error}"
+                    ),
+                    RegistrationState::Unregistered => {}
+                }
+            }
+
+            status.state = state;
+            if !invehicle_digital_twin_uri.is_empty() {
+                status.invehicle_digital_twin_uri = Some(invehicle_digital_twin_uri.to_string());
+            }
+            status.last_updated = Some(SystemTime::now());
+            status.last_error = error;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use invehicle_stack_interfaces::invehicle_digital_twin::v1::invehicle_digital_twin_server::{
+        InvehicleDigitalTwin, InvehicleDigitalTwinServer,
+    };
+    use invehicle_stack_interfaces::invehicle_digital_twin::v1::{
+        FindByIdResponse, RegisterResponse,
+    };
+    use parking_lot::Mutex;
+    use tokio::net::TcpListener;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::Server;
+    use tonic::{Request, Response};
+
+    use super::*;
+    use crate::service_resolver::{ServiceDescriptor, StaticResolver};
+
+    const WEIGHT: &str = "dtmi:sdv:Trailer:Weight;2";
+    const IS_TRAILER_CONNECTED: &str = "dtmi:sdv:Trailer:IsTrailerConnected;2";
+
+    /// An In-Vehicle Digital Twin Service that keeps its registrations in memory.
+    #[derive(Clone, Default)]
+    struct FakeInvehicleDigitalTwin {
+        registrations: Arc<Mutex<HashMap<String, EntityAccessInfo>>>,
+    }
+
+    #[tonic::async_trait]
+    impl InvehicleDigitalTwin for FakeInvehicleDigitalTwin {
+        async fn find_by_id(
+            &self,
+            request: Request<FindByIdRequest>,
+        ) -> Result<Response<FindByIdResponse>, Status> {
+            let id = request.into_inner().id;
+            let entity_access_info = self
+                .registrations
+                .lock()
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| Status::not_found(format!("'{id}' is not registered")))?;
+
+            Ok(Response::new(FindByIdResponse {
+                entity_access_info: Some(entity_access_info),
+            }))
+        }
+
+        async fn register(
+            &self,
+            request: Request<RegisterRequest>,
+        ) -> Result<Response<RegisterResponse>, Status> {
+            let mut registrations = self.registrations.lock();
+            for entity_access_info in request.into_inner().entity_access_info_list {
+                registrations.insert(entity_access_info.id.clone(), entity_access_info);
+            }
+
+            Ok(Response::new(RegisterResponse {}))
+        }
+    }
+
+    /// Serve a fake In-Vehicle Digital Twin Service on a free local port. Returns the service and
+    /// its URI.
+    async fn serve() -> (FakeInvehicleDigitalTwin, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let service = FakeInvehicleDigitalTwin::default();
+
+        let server = Server::builder()
+            .add_service(InvehicleDigitalTwinServer::new(service.clone()))
+            .serve_with_incoming(incoming);
+        tokio::spawn(server);
+
+        (service, uri)
+    }
+
+    /// Get a local URI that nothing listens on.
+    async fn unused_uri() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    /// Create a supervisor of the weight and connection entities, which finds the In-Vehicle
+    /// Digital Twin Service at the provided URI.
+    ///
+    /// # Arguments
+    /// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin Service's URI.
+    /// * `check_interval` - How often the registration is checked.
+    fn supervisor(
+        invehicle_digital_twin_uri: &str,
+        check_interval: Duration,
+    ) -> RegistrationSupervisor {
+        let resolver = StaticResolver::new(HashMap::from([(
+            ServiceDescriptor::invehicle_digital_twin().static_key(),
+            invehicle_digital_twin_uri.to_string(),
+        )]));
+        let retry_policy =
+            RetryPolicy::no_retry().with_attempt_timeout(Some(Duration::from_secs(5)));
+        let discovery_cache = DiscoveryCache::new(
+            Duration::from_secs(60),
+            Box::new(resolver),
+            retry_policy.clone(),
+        );
+        let entities = [WEIGHT, IS_TRAILER_CONNECTED]
+            .into_iter()
+            .map(|id| EntityAccessInfo {
+                name: id.to_string(),
+                id: id.to_string(),
+                ..Default::default()
+            })
+            .collect();
+
+        RegistrationSupervisor::new(
+            entities,
+            Arc::new(discovery_cache),
+            retry_policy,
+            check_interval,
+        )
+    }
+
+    #[tokio::test]
+    async fn registering_updates_the_status() {
+        let (_service, uri) = serve().await;
+        let supervisor = supervisor(&uri, Duration::ZERO);
+        assert_eq!(
+            supervisor.status().borrow().state,
+            RegistrationState::Unregistered
+        );
+
+        supervisor.register().await.unwrap();
+
+        let status = supervisor.status().borrow().clone();
+        assert_eq!(status.state, RegistrationState::Registered);
+        assert_eq!(status.invehicle_digital_twin_uri, Some(uri));
+        assert_eq!(status.registration_count, 1);
+        assert_eq!(status.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn find_missing_reports_the_unregistered_entities() {
+        let (service, uri) = serve().await;
+        let supervisor = supervisor(&uri, Duration::ZERO);
+        assert_eq!(
+            supervisor.find_missing(&uri).await.unwrap(),
+            [WEIGHT, IS_TRAILER_CONNECTED]
+        );
+
+        supervisor.register().await.unwrap();
+        assert!(supervisor.find_missing(&uri).await.unwrap().is_empty());
+
+        service.registrations.lock().remove(IS_TRAILER_CONNECTED);
+        assert_eq!(
+            supervisor.find_missing(&uri).await.unwrap(),
+            [IS_TRAILER_CONNECTED]
+        );
+    }
+
+    #[tokio::test]
+    async fn lost_entities_are_registered_again() {
+        let (service, uri) = serve().await;
+        let supervisor = supervisor(&uri, Duration::ZERO);
+        supervisor.register().await.unwrap();
+
+        supervisor.check_and_repair().await;
+        assert_eq!(supervisor.status().borrow().registration_count, 1);
+
+        // The In-Vehicle Digital Twin Service restarted.
+        service.registrations.lock().clear();
+        supervisor.check_and_repair().await;
+
+        let status = supervisor.status().borrow().clone();
+        assert_eq!(status.state, RegistrationState::Registered);
+        assert_eq!(status.registration_count, 2);
+        assert!(supervisor.find_missing(&uri).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_service_is_reported() {
+        let uri = unused_uri().await;
+        let supervisor = supervisor(&uri, Duration::ZERO);
+
+        let err = supervisor.register().await.unwrap_err();
+        assert!(matches!(err, DiscoveryError::Connection { .. }), "{err}");
+        let status = supervisor.status().borrow().clone();
+        assert_eq!(status.state, RegistrationState::Unreachable);
+        assert_eq!(status.registration_count, 0);
+        assert!(status.last_error.is_some());
+
+        supervisor.check_and_repair().await;
+        assert_eq!(
+            supervisor.status().borrow().state,
+            RegistrationState::Unreachable
+        );
+    }
+
+    #[tokio::test]
+    async fn supervise_repairs_the_registration_until_shutdown() {
+        let (service, uri) = serve().await;
+        let supervisor = supervisor(&uri, Duration::from_millis(20));
+        supervisor.register().await.unwrap();
+        let mut status = supervisor.status();
+        let shutdown = CancellationToken::new();
+        let supervision = tokio::spawn(supervisor.supervise(shutdown.clone()));
+
+        service.registrations.lock().clear();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            status.wait_for(|status| status.registration_count == 2),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        shutdown.cancel();
+        supervision.await.unwrap();
+        assert_eq!(service.registrations.lock().len(), 2);
+    }
+}
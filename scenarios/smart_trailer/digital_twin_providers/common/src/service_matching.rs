@@ -0,0 +1,378 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Selection of a service among the services that are registered with Chariott.
+//!
+//! A service is compatible when its version satisfies a semver requirement, such as `^1`, its
+//! communication kind is one of the accepted kinds and its communication reference matches one of
+//! the accepted patterns. Versions that are not full semver versions, such as `1.0`, are padded
+//! with zeros. Patterns may contain `*`, which matches any sequence of characters, so that for
+//! example a proto URL can match any branch.
+
+use std::cmp::Reverse;
+use std::fmt;
+
+use invehicle_stack_interfaces::service_discovery::core::v1::ServiceMetadata;
+use semver::{Version, VersionReq};
+
+use crate::errors::DiscoveryError;
+use crate::service_resolver::ServiceDescriptor;
+
+/// A service that was not selected, and why.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+    pub service: ServiceMetadata,
+    pub reason: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version {} at '{}': {}",
+            self.service.version, self.service.uri, self.reason
+        )
+    }
+}
+
+/// The selected service, and the services that were rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceMatch {
+    pub service: ServiceMetadata,
+    pub rejections: Vec<Rejection>,
+}
+
+/// Parse a service version, padding missing minor and patch numbers with zeros.
+///
+/// # Arguments
+/// * `version` - The version, for example `1.0`.
+pub fn parse_version(version: &str) -> Result<Version, String> {
+    let version = version.trim();
+    let core_end = version.find(['-', '+']).unwrap_or(version.len());
+    let (core, suffix) = version.split_at(core_end);
+    let padding = match core.split('.').count() {
+        1 => ".0.0",
+        2 => ".0",
+        _ => "",
+    };
+
+    Version::parse(&format!("{core}{padding}{suffix}"))
+        .map_err(|err| format!("Invalid version '{version}': {err}"))
+}
+
+/// Parse a semver version requirement, for example `^1` or `>=1.2, <2`.
+///
+/// # Arguments
+/// * `requirement` - The requirement.
+pub fn parse_version_requirement(requirement: &str) -> Result<VersionReq, String> {
+    VersionReq::parse(requirement)
+        .map_err(|err| format!("Invalid version requirement '{requirement}': {err}"))
+}
+
+/// Does a value match a pattern, where `*` matches any sequence of characters?
+///
+/// # Arguments
+/// * `pattern` - The pattern.
+/// * `value` - The value.
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // There is no '*' in the pattern.
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Select the best compatible service: the one with the highest version, and among those the one
+/// whose communication kind comes first in the accepted kinds.
+///
+/// # Arguments
+/// * `requirements` - The required service.
+/// * `services` - The registered services. Services with another namespace or name are ignored.
+pub fn select_service(
+    requirements: &ServiceDescriptor,
+    services: Vec<ServiceMetadata>,
+) -> Result<ServiceMatch, DiscoveryError> {
+    let version_requirement = parse_version_requirement(&requirements.version)
+        .map_err(DiscoveryError::invalid_request)?;
+
+    let mut best: Option<(Version, usize, ServiceMetadata)> = None;
+    let mut rejections = Vec::new();
+
+    for service in services.into_iter().filter(|service| {
+        service.namespace == requirements.namespace && service.name == requirements.name
+    }) {
+        match check_service(requirements, &version_requirement, &service) {
+            Ok((version, kind_rank)) => {
+                let candidate = (version, kind_rank, service);
+                let outranked = match best.take() {
+                    Some(current) if is_better(&candidate, &current) => {
+                        best = Some(candidate);
+                        current
+                    }
+                    Some(current) => {
+                        best = Some(current);
+                        candidate
+                    }
+                    None => {
+                        best = Some(candidate);
+                        continue;
+                    }
+                };
+                rejections.push(Rejection {
+                    service: outranked.2,
+                    reason: "A compatible service with a higher version or a preferred communication kind was selected".to_string(),
+                });
+            }
+            Err(reason) => rejections.push(Rejection { service, reason }),
+        }
+    }
+
+    match best {
+        Some((_, _, service)) => Ok(ServiceMatch {
+            service,
+            rejections,
+        }),
+        None if rejections.is_empty() => Err(DiscoveryError::not_found(
+            "Did not find the service in Chariott",
+        )),
+        None => Err(DiscoveryError::protocol_mismatch(format!(
+            "Found {} services, but none is compatible: {}",
+            rejections.len(),
+            format_rejections(&rejections)
+        ))),
+    }
+}
+
+/// Describe why services were rejected, in a single line.
+///
+/// # Arguments
+/// * `rejections` - The rejected services.
+pub fn format_rejections(rejections: &[Rejection]) -> String {
+    rejections
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Is a compatible service better than another, that is, does it have a higher version or, with the
+/// same version, a preferred communication kind?
+///
+/// # Arguments
+/// * `candidate` - The version, communication kind rank and metadata of a service.
+/// * `current` - The version, communication kind rank and metadata of the other service.
+fn is_better(
+    candidate: &(Version, usize, ServiceMetadata),
+    current: &(Version, usize, ServiceMetadata),
+) -> bool {
+    (&candidate.0, Reverse(candidate.1)) > (&current.0, Reverse(current.1))
+}
+
+/// Check that a service is compatible with the requirements.
+/// Returns the service's version and the rank of its communication kind.
+///
+/// # Arguments
+/// * `requirements` - The required service.
+/// * `version_requirement` - The parsed version requirement.
+/// * `service` - The service.
+fn check_service(
+    requirements: &ServiceDescriptor,
+    version_requirement: &VersionReq,
+    service: &ServiceMetadata,
+) -> Result<(Version, usize), String> {
+    let version = parse_version(&service.version)?;
+    if !version_requirement.matches(&version) {
+        return Err(format!(
+            "The version does not satisfy the requirement '{version_requirement}'"
+        ));
+    }
+
+    let kind_rank = requirements
+        .communication_kinds
+        .iter()
+        .position(|kind| *kind == service.communication_kind)
+        .ok_or_else(|| {
+            format!(
+                "The communication kind '{}' is not one of {:?}",
+                service.communication_kind, requirements.communication_kinds
+            )
+        })?;
+
+    if !requirements
+        .communication_references
+        .iter()
+        .any(|pattern| matches_pattern(pattern, &service.communication_reference))
+    {
+        return Err(format!(
+            "The communication reference '{}' does not match any of {:?}",
+            service.communication_reference, requirements.communication_references
+        ));
+    }
+
+    if service.uri.is_empty() {
+        return Err("The service does not have a URI".to_string());
+    }
+
+    Ok((version, kind_rank))
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    const KIND: &str = "grpc+proto";
+    const REFERENCE: &str =
+        "https://github.com/eclipse-ibeji/ibeji/blob/*/invehicle_digital_twin.proto";
+
+    fn requirements(version: &str) -> ServiceDescriptor {
+        ServiceDescriptor::new(
+            "sdv",
+            "invehicle_digital_twin",
+            version,
+            vec![KIND.to_string(), "grpc+json".to_string()],
+            vec![REFERENCE.to_string()],
+        )
+    }
+
+    fn service(version: &str, uri: &str) -> ServiceMetadata {
+        ServiceMetadata {
+            namespace: "sdv".to_string(),
+            name: "invehicle_digital_twin".to_string(),
+            version: version.to_string(),
+            uri: uri.to_string(),
+            communication_kind: KIND.to_string(),
+            communication_reference:
+                "https://github.com/eclipse-ibeji/ibeji/blob/main/invehicle_digital_twin.proto"
+                    .to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_version_pads_partial_versions() {
+        assert_eq!(parse_version("1").unwrap(), Version::new(1, 0, 0));
+        assert_eq!(parse_version(" 1.2 ").unwrap(), Version::new(1, 2, 0));
+        assert_eq!(parse_version("1.2.3").unwrap(), Version::new(1, 2, 3));
+        assert_eq!(parse_version("1.2-rc.1").unwrap().to_string(), "1.2.0-rc.1");
+    }
+
+    #[test]
+    fn parse_version_rejects_bad_versions() {
+        for version in ["", "v1", "1.x", "1.2.3.4", "one"] {
+            let err = parse_version(version).unwrap_err();
+            assert!(err.starts_with("Invalid version"), "{version}: {err}");
+        }
+        assert!(parse_version_requirement("^1").is_ok());
+        assert!(parse_version_requirement("~>1").is_err());
+    }
+
+    #[test]
+    fn matches_pattern_supports_wildcards() {
+        assert!(matches_pattern("abc", "abc"));
+        assert!(!matches_pattern("abc", "abcd"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*", "abc"));
+        assert!(matches_pattern("*c", "abc"));
+        assert!(matches_pattern("a*c", "ac"));
+        assert!(matches_pattern("a*b*c", "a-b-c"));
+        assert!(!matches_pattern("a*b*c", "a-c"));
+        // The prefix and the suffix must not overlap.
+        assert!(!matches_pattern("ab*ba", "aba"));
+        assert!(matches_pattern(
+            REFERENCE,
+            "https://github.com/eclipse-ibeji/ibeji/blob/0.1.1/invehicle_digital_twin.proto"
+        ));
+        assert!(!matches_pattern(
+            REFERENCE,
+            "https://github.com/eclipse-ibeji/ibeji/blob/main/managed_subscribe.proto"
+        ));
+    }
+
+    #[test]
+    fn select_service_selects_the_highest_compatible_version() {
+        let services = vec![
+            service("1.0", "http://0.0.0.0:5010"),
+            service("1.2.0", "http://0.0.0.0:5012"),
+            service("2.0.0", "http://0.0.0.0:5020"),
+            service("1.1", "http://0.0.0.0:5011"),
+        ];
+
+        let selected = select_service(&requirements("^1"), services).unwrap();
+
+        assert_eq!(selected.service.uri, "http://0.0.0.0:5012");
+        assert_eq!(selected.rejections.len(), 3);
+        let rejected = selected
+            .rejections
+            .iter()
+            .find(|rejection| rejection.service.version == "2.0.0")
+            .unwrap();
+        assert!(rejected.reason.contains("does not satisfy"), "{rejected}");
+    }
+
+    #[test]
+    fn select_service_prefers_the_first_communication_kind() {
+        let mut json = service("1.0.0", "http://0.0.0.0:5011");
+        json.communication_kind = "grpc+json".to_string();
+        let services = vec![json, service("1.0.0", "http://0.0.0.0:5010")];
+
+        let selected = select_service(&requirements("^1"), services).unwrap();
+
+        assert_eq!(selected.service.uri, "http://0.0.0.0:5010");
+    }
+
+    #[test]
+    fn select_service_explains_why_no_service_is_compatible() {
+        let mut other_reference = service("1.0.0", "http://0.0.0.0:5011");
+        other_reference.communication_reference = "other.proto".to_string();
+        let services = vec![
+            service("2.0.0", "http://0.0.0.0:5010"),
+            other_reference,
+            service("banana", "http://0.0.0.0:5012"),
+            service("1.0.0", ""),
+        ];
+
+        let err = select_service(&requirements("^1"), services).unwrap_err();
+
+        assert_eq!(err.code(), Code::FailedPrecondition);
+        let message = err.to_string();
+        assert!(message.starts_with("Found 4 services, but none is compatible: version 2.0.0 at 'http://0.0.0.0:5010': The version does not satisfy"), "{message}");
+        assert!(message.contains("; version 1.0.0 at 'http://0.0.0.0:5011': The communication reference 'other.proto' does not match"), "{message}");
+        assert!(
+            message.contains("; version banana at 'http://0.0.0.0:5012': Invalid version 'banana'"),
+            "{message}"
+        );
+        assert!(
+            message.ends_with("; version 1.0.0 at '': The service does not have a URI"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn select_service_ignores_other_services() {
+        let mut other = service("1.0.0", "http://0.0.0.0:5010");
+        other.name = "managed_subscribe".to_string();
+
+        let err = select_service(&requirements("^1"), vec![other]).unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        let err = select_service(&requirements("one"), Vec::new()).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,310 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Resolution of the URIs of services, such as the In-Vehicle Digital Twin Service.
+//!
+//! Services are normally discovered with Chariott. A static map of service URIs lets the
+//! binaries run without Chariott, for example on a test bench, and a chain of resolvers falls
+//! back to the static map when Chariott cannot resolve a service.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use log::{debug, info, warn};
+use tonic::transport::Uri;
+
+use crate::constants::chariott::{
+    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
+    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE_PATTERN,
+    INVEHICLE_DIGITAL_TWIN_SERVICE_NAME, INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE,
+    INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION_REQUIREMENT,
+};
+use crate::errors::DiscoveryError;
+use crate::service_matching::format_rejections;
+use crate::utils::{discover_service_using_chariott, RetryPolicy};
+
+/// Describes a service to resolve, and which of its registrations are compatible.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ServiceDescriptor {
+    pub namespace: String,
+    pub name: String,
+    /// A semver requirement on the service's version, for example `^1`.
+    pub version: String,
+    /// The accepted communication kinds, the preferred one first.
+    pub communication_kinds: Vec<String>,
+    /// Patterns of the accepted communication references, where `*` matches any sequence of
+    /// characters.
+    pub communication_references: Vec<String>,
+}
+
+impl ServiceDescriptor {
+    /// Describe a service.
+    ///
+    /// # Arguments
+    /// * `namespace` - The service's namespace.
+    /// * `name` - The service's name.
+    /// * `version` - The semver requirement on the service's version.
+    /// * `communication_kinds` - The accepted communication kinds, the preferred one first.
+    /// * `communication_references` - Patterns of the accepted communication references.
+    pub fn new(
+        namespace: &str,
+        name: &str,
+        version: &str,
+        communication_kinds: Vec<String>,
+        communication_references: Vec<String>,
+    ) -> Self {
+        ServiceDescriptor {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            version: version.to_string(),
+            communication_kinds,
+            communication_references,
+        }
+    }
+
+    /// Describe the In-Vehicle Digital Twin Service.
+    pub fn invehicle_digital_twin() -> Self {
+        ServiceDescriptor::new(
+            INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE,
+            INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
+            INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION_REQUIREMENT,
+            vec![INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND.to_string()],
+            vec![INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE_PATTERN.to_string()],
+        )
+    }
+
+    /// The key of the service in a static service map, `<namespace>/<name>`.
+    pub fn static_key(&self) -> String {
+        format!("{}/{}", self.namespace, self.name)
+    }
+}
+
+impl fmt::Display for ServiceDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "service namespace '{}', name '{}' and version requirement '{}'",
+            self.namespace, self.name, self.version
+        )
+    }
+}
+
+/// Resolves the URI of a service.
+#[tonic::async_trait]
+pub trait ServiceResolver: fmt::Debug + Send + Sync {
+    /// Resolve the URI of a service.
+    ///
+    /// # Arguments
+    /// * `service` - The service.
+    async fn resolve(&self, service: &ServiceDescriptor) -> Result<String, DiscoveryError>;
+}
+
+/// Resolves services with Chariott.
+#[derive(Clone, Debug)]
+pub struct ChariottResolver {
+    chariott_uri: String,
+    retry_policy: RetryPolicy,
+}
+
+impl ChariottResolver {
+    /// Create a resolver that uses Chariott.
+    ///
+    /// # Arguments
+    /// * `chariott_uri` - Chariott's URI.
+    /// * `retry_policy` - How the calls to Chariott are retried.
+    pub fn new(chariott_uri: &str, retry_policy: RetryPolicy) -> Self {
+        ChariottResolver {
+            chariott_uri: chariott_uri.to_string(),
+            retry_policy,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ServiceResolver for ChariottResolver {
+    async fn resolve(&self, service: &ServiceDescriptor) -> Result<String, DiscoveryError> {
+        let service_match =
+            discover_service_using_chariott(&self.chariott_uri, service, &self.retry_policy)
+                .await?;
+
+        if !service_match.rejections.is_empty() {
+            info!(
+                "Selected version {} of the {service}, and rejected: {}",
+                service_match.service.version,
+                format_rejections(&service_match.rejections)
+            );
+        }
+
+        Ok(service_match.service.uri)
+    }
+}
+
+/// Resolves services from a static map of `<namespace>/<name>` to URI.
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    services: HashMap<String, String>,
+}
+
+impl StaticResolver {
+    /// Create a resolver from a map of `<namespace>/<name>` to URI.
+    ///
+    /// # Arguments
+    /// * `services` - The map.
+    pub fn new(services: HashMap<String, String>) -> Self {
+        StaticResolver { services }
+    }
+
+    /// Create a resolver from entries of the form `<namespace>/<name>=<uri>`.
+    ///
+    /// # Arguments
+    /// * `entries` - The entries.
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let services = entries
+            .iter()
+            .map(|entry| parse_static_service(entry))
+            .collect::<Result<_, _>>()?;
+
+        Ok(StaticResolver { services })
+    }
+
+    /// Is the map empty?
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+}
+
+#[tonic::async_trait]
+impl ServiceResolver for StaticResolver {
+    async fn resolve(&self, service: &ServiceDescriptor) -> Result<String, DiscoveryError> {
+        self.services
+            .get(&service.static_key())
+            .cloned()
+            .ok_or_else(|| {
+                DiscoveryError::not_found("The service is not in the static service map")
+                    .with_context(service.to_string())
+            })
+    }
+}
+
+/// Tries resolvers in order, until one of them resolves the service.
+#[derive(Debug, Default)]
+pub struct ChainResolver {
+    resolvers: Vec<Box<dyn ServiceResolver>>,
+}
+
+impl ChainResolver {
+    /// Create an empty chain.
+    pub fn new() -> Self {
+        ChainResolver::default()
+    }
+
+    /// Add a resolver to the end of the chain.
+    ///
+    /// # Arguments
+    /// * `resolver` - The resolver.
+    pub fn with(mut self, resolver: impl ServiceResolver + 'static) -> Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl ServiceResolver for ChainResolver {
+    /// Returns the URI from the first resolver that resolves the service, or the first
+    /// resolver's error if none of them does.
+    async fn resolve(&self, service: &ServiceDescriptor) -> Result<String, DiscoveryError> {
+        let mut first_error = None;
+
+        for (index, resolver) in self.resolvers.iter().enumerate() {
+            match resolver.resolve(service).await {
+                Ok(uri) => {
+                    if index > 0 {
+                        debug!("Resolved {service} to {uri} with fallback resolver {index}");
+                    }
+                    return Ok(uri);
+                }
+                Err(err) => {
+                    if index + 1 < self.resolvers.len() {
+                        warn!("Failed to resolve {service}, trying the next resolver: {err}");
+                    }
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+
+        Err(first_error.unwrap_or_else(|| {
+            DiscoveryError::not_found("There are no service resolvers")
+                .with_context(service.to_string())
+        }))
+    }
+}
+
+/// Parse an entry of a static service map, of the form `<namespace>/<name>=<uri>`.
+///
+/// # Arguments
+/// * `entry` - The entry.
+fn parse_static_service(entry: &str) -> Result<(String, String), String> {
+    let invalid =
+        || format!("Invalid static service '{entry}': expected '<namespace>/<name>=<uri>'");
+
+    let (key, uri) = entry.split_once('=').ok_or_else(invalid)?;
+    let (namespace, name) = key.split_once('/').ok_or_else(invalid)?;
+    if namespace.is_empty() || name.is_empty() || uri.is_empty() {
+        return Err(invalid());
+    }
+
+    // The URI is connected to as is, so it needs a scheme and an authority, such as
+    // `http://0.0.0.0:5010`.
+    let parsed = uri
+        .parse::<Uri>()
+        .map_err(|err| format!("Invalid URI '{uri}' of static service '{key}': {err}"))?;
+    if parsed.scheme().is_none() || parsed.authority().is_none() {
+        return Err(format!(
+            "Invalid URI '{uri}' of static service '{key}': expected a scheme and an authority"
+        ));
+    }
+
+    Ok((key.to_string(), uri.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_services_are_parsed() {
+        assert_eq!(
+            parse_static_service("sdv/invehicle_digital_twin=http://0.0.0.0:5010").unwrap(),
+            (
+                "sdv/invehicle_digital_twin".to_string(),
+                "http://0.0.0.0:5010".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn static_services_must_have_a_namespace_a_name_and_a_uri() {
+        for entry in [
+            "invehicle_digital_twin=http://0.0.0.0:5010",
+            "sdv/=http://0.0.0.0:5010",
+            "sdv/invehicle_digital_twin=",
+            "sdv/invehicle_digital_twin",
+        ] {
+            assert!(parse_static_service(entry).is_err(), "{entry}");
+        }
+    }
+
+    #[test]
+    fn static_service_uris_must_be_valid() {
+        for entry in [
+            "sdv/invehicle_digital_twin=0.0.0.0:5010",
+            "sdv/invehicle_digital_twin=/invehicle_digital_twin",
+            "sdv/invehicle_digital_twin=http://0.0.0.0:50 10",
+            "sdv/invehicle_digital_twin=http://",
+        ] {
+            let err = parse_static_service(entry).unwrap_err();
+            assert!(err.starts_with("Invalid URI"), "{entry}: {err}");
+        }
+    }
+}
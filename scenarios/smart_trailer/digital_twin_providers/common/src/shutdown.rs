@@ -0,0 +1,48 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Coordinated shutdown of the providers and the application.
+//!
+//! A [`CancellationToken`] is cancelled when the process receives control-c or SIGTERM, which is
+//! how podman, Ankaios and systemd stop a workload. Servers, publish tasks and MQTT clients wait
+//! for the token, so that they can stop cleanly and unregister before the process exits.
+
+use std::io;
+
+use log::{info, warn};
+use tokio::signal;
+pub use tokio_util::sync::CancellationToken;
+
+/// Wait until the process receives control-c or, on Unix, SIGTERM.
+pub async fn wait_for_shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = sigterm.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        signal::ctrl_c().await
+    }
+}
+
+/// Create a token that is cancelled when the process receives control-c or SIGTERM.
+pub fn shutdown_on_signal() -> CancellationToken {
+    let token = CancellationToken::new();
+
+    let signal_token = token.clone();
+    tokio::spawn(async move {
+        match wait_for_shutdown_signal().await {
+            Ok(()) => info!("Received a shutdown signal, shutting down."),
+            Err(err) => warn!("Failed to listen for shutdown signals, shutting down: {err}"),
+        }
+        signal_token.cancel();
+    });
+
+    token
+}
@@ -0,0 +1,250 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TLS and mutual TLS for the gRPC servers and clients.
+//!
+//! A provider serves with TLS when it has a certificate and a key, and can require the clients to
+//! present a certificate that is signed by its CA. The clients use TLS for `https://` URIs, trust
+//! the configured CA and, for mutual TLS, present their own certificate. The client configuration
+//! is set once at startup with [`configure_clients`], since the URIs that are called come from
+//! the configuration as well as from Chariott and the In-Vehicle Digital Twin Service.
+
+use std::fs;
+use std::sync::OnceLock;
+
+use tonic::transport::{
+    Certificate, Channel, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig,
+};
+
+use crate::config::TlsConfig;
+
+/// The TLS configuration of the clients, if it has been set.
+static CLIENT_TLS_CONFIG: OnceLock<ClientTlsConfig> = OnceLock::new();
+
+/// Set the TLS configuration that the clients use for `https://` URIs. It can only be set once.
+///
+/// # Arguments
+/// * `tls` - The TLS configuration.
+pub fn configure_clients(tls: &TlsConfig) -> Result<(), String> {
+    let mut client_tls_config = ClientTlsConfig::new();
+    if !tls.ca_cert_path.is_empty() {
+        client_tls_config = client_tls_config.ca_certificate(Certificate::from_pem(read(
+            "tls.ca_cert_path",
+            &tls.ca_cert_path,
+        )?));
+    }
+    if let Some(identity) = identity(tls)? {
+        client_tls_config = client_tls_config.identity(identity);
+    }
+    if !tls.domain_name.is_empty() {
+        client_tls_config = client_tls_config.domain_name(&tls.domain_name);
+    }
+
+    CLIENT_TLS_CONFIG
+        .set(client_tls_config)
+        .map_err(|_| "The TLS configuration of the clients is already set".to_string())
+}
+
+/// The TLS configuration of a server. Returns `None` if the server does not use TLS.
+///
+/// # Arguments
+/// * `tls` - The TLS configuration.
+pub fn server_config(tls: &TlsConfig) -> Result<Option<ServerTlsConfig>, String> {
+    let Some(identity) = identity(tls)? else {
+        return Ok(None);
+    };
+
+    let mut server_tls_config = ServerTlsConfig::new().identity(identity);
+    if tls.require_client_cert {
+        server_tls_config = server_tls_config.client_ca_root(Certificate::from_pem(read(
+            "tls.ca_cert_path",
+            &tls.ca_cert_path,
+        )?));
+    }
+
+    Ok(Some(server_tls_config))
+}
+
+/// Connect to a gRPC server. `https://` URIs are connected with TLS.
+///
+/// # Arguments
+/// * `uri` - The server's URI.
+pub async fn connect(uri: &str) -> Result<Channel, tonic::transport::Error> {
+    let mut endpoint = Endpoint::from_shared(uri.to_string())?;
+    if endpoint.uri().scheme_str() == Some("https") {
+        let client_tls_config = CLIENT_TLS_CONFIG
+            .get()
+            .cloned()
+            .unwrap_or_else(ClientTlsConfig::new);
+        endpoint = endpoint.tls_config(client_tls_config)?;
+    }
+
+    endpoint.connect().await
+}
+
+/// The certificate and key that identify a server or a client, if they are configured.
+///
+/// # Arguments
+/// * `tls` - The TLS configuration.
+fn identity(tls: &TlsConfig) -> Result<Option<Identity>, String> {
+    if tls.cert_path.is_empty() {
+        return Ok(None);
+    }
+
+    let cert = read("tls.cert_path", &tls.cert_path)?;
+    let key = read("tls.key_path", &tls.key_path)?;
+    Ok(Some(Identity::from_pem(cert, key)))
+}
+
+/// Read a PEM file.
+///
+/// # Arguments
+/// * `key` - The configuration key of the file's path, for messages.
+/// * `path` - The file's path.
+fn read(key: &str, path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("{key} '{path}' cannot be read: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use tokio::net::TcpListener;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::Server;
+    use tonic::Status;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+    use tonic_health::ServingStatus;
+
+    use super::*;
+
+    /// Generate a CA and a certificate signed by it with the scenario's script, in a directory of
+    /// the test's own. Returns the directory.
+    ///
+    /// # Arguments
+    /// * `test` - The test's name.
+    fn generate_certificates(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("smart_trailer_{test}_{}", std::process::id()));
+        let script = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../scripts/generate_test_certificates.sh"
+        );
+
+        let status = Command::new("bash").arg(script).arg(&dir).status().unwrap();
+        assert!(status.success(), "{script} failed with {status}");
+
+        dir
+    }
+
+    /// A TLS configuration that uses the generated certificates.
+    ///
+    /// # Arguments
+    /// * `dir` - The directory of the certificates.
+    /// * `require_client_cert` - Whether the server requires client certificates.
+    fn tls_config(dir: &Path, require_client_cert: bool) -> TlsConfig {
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+
+        TlsConfig {
+            ca_cert_path: path("ca.pem"),
+            cert_path: path("provider.pem"),
+            key_path: path("provider.key"),
+            require_client_cert,
+            domain_name: "localhost".to_string(),
+        }
+    }
+
+    /// Serve the health service on a free local port. Returns the server's `https://` URI.
+    ///
+    /// # Arguments
+    /// * `tls` - The TLS configuration of the server.
+    async fn serve(tls: &TlsConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("https://{}", listener.local_addr().unwrap());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let (_, health_service) = tonic_health::server::health_reporter();
+
+        let server = Server::builder()
+            .tls_config(server_config(tls).unwrap().unwrap())
+            .unwrap()
+            .add_service(health_service)
+            .serve_with_incoming(incoming);
+        tokio::spawn(server);
+
+        uri
+    }
+
+    /// Check the health of the whole server.
+    ///
+    /// # Arguments
+    /// * `channel` - The channel to the server.
+    async fn check(channel: Channel) -> Result<i32, Status> {
+        HealthClient::new(channel)
+            .check(HealthCheckRequest {
+                service: String::new(),
+            })
+            .await
+            .map(|response| response.into_inner().status)
+    }
+
+    #[tokio::test]
+    async fn clients_connect_to_servers_with_mutual_tls() {
+        let dir = generate_certificates("mutual_tls");
+        let tls = tls_config(&dir, true);
+        let uri = serve(&tls).await;
+
+        // This is the only test that sets the clients' configuration, which is global.
+        configure_clients(&tls).unwrap();
+        let status = check(connect(&uri).await.unwrap()).await;
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status.unwrap(), ServingStatus::Serving as i32);
+    }
+
+    #[tokio::test]
+    async fn servers_reject_clients_without_a_certificate() {
+        let dir = generate_certificates("no_client_cert");
+        let tls = tls_config(&dir, true);
+        let uri = serve(&tls).await;
+
+        let client_tls_config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(fs::read(&tls.ca_cert_path).unwrap()))
+            .domain_name(&tls.domain_name);
+        let endpoint = Endpoint::from_shared(uri)
+            .unwrap()
+            .tls_config(client_tls_config)
+            .unwrap();
+        // Depending on the TLS version, the certificate is checked during the handshake or on
+        // the first request.
+        let result = match endpoint.connect().await {
+            Ok(channel) => check(channel).await.map_err(|status| status.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err(), "{result:?}");
+    }
+
+    #[test]
+    fn servers_without_a_certificate_do_not_use_tls() {
+        assert!(server_config(&TlsConfig::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn unreadable_files_are_reported_with_their_key() {
+        let tls = TlsConfig {
+            cert_path: "/nonexistent/provider.pem".to_string(),
+            key_path: "/nonexistent/provider.key".to_string(),
+            ..Default::default()
+        };
+
+        let err = server_config(&tls).unwrap_err();
+
+        assert!(
+            err.starts_with("tls.cert_path '/nonexistent/provider.pem'"),
+            "{err}"
+        );
+    }
+}
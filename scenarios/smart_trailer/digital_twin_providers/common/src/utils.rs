@@ -2,56 +2,294 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+use digital_twin_model::dtmi::Dtmi;
 use invehicle_stack_interfaces::invehicle_digital_twin::v1::invehicle_digital_twin_client::InvehicleDigitalTwinClient;
-use invehicle_stack_interfaces::invehicle_digital_twin::v1::{EndpointInfo, FindByIdRequest};
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::{
+    EndpointInfo, EntityAccessInfo, FindByIdRequest,
+};
 use invehicle_stack_interfaces::service_discovery::core::v1::service_registry_client::ServiceRegistryClient;
-use invehicle_stack_interfaces::service_discovery::core::v1::DiscoverRequest;
-use log::{debug, info};
-use tonic::{Request, Status};
+use invehicle_stack_interfaces::service_discovery::core::v1::{
+    DiscoverByNamespaceRequest, ListRequest,
+};
+use log::{debug, info, warn};
+use rand::Rng;
+use std::future::Future;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tonic::{Code, Request, Status};
 
-/// Use Chariott Service Discovery to discover a service.
+use crate::errors::DiscoveryError;
+use crate::service_matching::{select_service, ServiceMatch};
+use crate::service_resolver::ServiceDescriptor;
+use crate::tls::connect;
+
+/// The gRPC status codes of transient failures, after which a call can be retried.
+pub const RETRYABLE_CODES: &[Code] = &[
+    Code::Unavailable,
+    Code::DeadlineExceeded,
+    Code::ResourceExhausted,
+    Code::Aborted,
+];
+
+/// How a gRPC call is retried after a transient failure.
+///
+/// The delay before each retry grows exponentially from `initial_backoff` up to `max_backoff`,
+/// with a random jitter so that clients that failed together do not retry together. Each attempt
+/// is bounded by `attempt_timeout`, and all attempts together by `overall_timeout`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry.
+    pub initial_backoff: Duration,
+    /// The maximum delay between two attempts.
+    pub max_backoff: Duration,
+    /// The factor that the delay grows by after each retry.
+    pub multiplier: f64,
+    /// The fraction of the delay that is randomly added or removed, between 0 and 1.
+    pub jitter: f64,
+    /// The deadline of each attempt.
+    pub attempt_timeout: Option<Duration>,
+    /// The deadline of all attempts together, including the delays between them.
+    pub overall_timeout: Option<Duration>,
+    /// The status codes after which the call is retried.
+    pub retryable_codes: Vec<Code>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+            attempt_timeout: Some(Duration::from_secs(5)),
+            overall_timeout: Some(Duration::from_secs(60)),
+            retryable_codes: RETRYABLE_CODES.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt.
+    pub fn no_retry() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// A policy that makes a single attempt within this policy's attempt timeout, for the calls
+    /// of an operation that is retried as a whole, so that the retries do not multiply.
+    pub fn single_attempt(&self) -> Self {
+        RetryPolicy::no_retry()
+            .with_attempt_timeout(self.attempt_timeout)
+            .with_overall_timeout(None)
+    }
+
+    /// Set the maximum number of attempts.
+    ///
+    /// # Arguments
+    /// * `max_attempts` - The maximum number of attempts, including the first one.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Set the exponential backoff between attempts.
+    ///
+    /// # Arguments
+    /// * `initial_backoff` - The delay before the first retry.
+    /// * `max_backoff` - The maximum delay between two attempts.
+    /// * `multiplier` - The factor that the delay grows by after each retry.
+    pub fn with_backoff(
+        mut self,
+        initial_backoff: Duration,
+        max_backoff: Duration,
+        multiplier: f64,
+    ) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set the jitter of the delay between attempts.
+    ///
+    /// # Arguments
+    /// * `jitter` - The fraction of the delay that is randomly added or removed, between 0 and 1.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the deadline of each attempt.
+    ///
+    /// # Arguments
+    /// * `attempt_timeout` - The deadline, or `None` for no deadline.
+    pub fn with_attempt_timeout(mut self, attempt_timeout: Option<Duration>) -> Self {
+        self.attempt_timeout = attempt_timeout;
+        self
+    }
+
+    /// Set the deadline of all attempts together.
+    ///
+    /// # Arguments
+    /// * `overall_timeout` - The deadline, or `None` for no deadline.
+    pub fn with_overall_timeout(mut self, overall_timeout: Option<Duration>) -> Self {
+        self.overall_timeout = overall_timeout;
+        self
+    }
+
+    /// Also retry after a status code that is not transient in general, for example `NotFound`
+    /// while waiting for a provider to register.
+    ///
+    /// # Arguments
+    /// * `code` - The status code.
+    pub fn with_retryable_code(mut self, code: Code) -> Self {
+        if !self.retryable_codes.contains(&code) {
+            self.retryable_codes.push(code);
+        }
+        self
+    }
+
+    /// Can a call be retried after it failed with the provided status?
+    ///
+    /// # Arguments
+    /// * `status` - The status of the failed call.
+    pub fn is_retryable(&self, status: &Status) -> bool {
+        self.retryable_codes.contains(&status.code())
+    }
+
+    /// The delay before a retry, including the jitter.
+    ///
+    /// # Arguments
+    /// * `retry` - The retry's number, starting at 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+
+        Duration::from_secs_f64((delay * factor).max(0.0))
+    }
+
+    /// Run a call until it succeeds, fails with a status that cannot be retried, or runs out of
+    /// attempts or time. Returns the result of the last attempt.
+    ///
+    /// # Arguments
+    /// * `operation` - A description of the call, for log and error messages.
+    /// * `call` - Makes one attempt of the call.
+    pub async fn run<T, F, Fut>(&self, operation: &str, mut call: F) -> Result<T, Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let deadline = self.overall_timeout.map(|overall| Instant::now() + overall);
+        let mut attempt = 1;
+
+        loop {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining.is_some_and(|remaining| remaining.is_zero()) {
+                return Err(Status::deadline_exceeded(format!(
+                    "{operation} did not complete within {:?}",
+                    self.overall_timeout.unwrap_or_default()
+                )));
+            }
+
+            let attempt_timeout = match (self.attempt_timeout, remaining) {
+                (Some(attempt_timeout), Some(remaining)) => Some(attempt_timeout.min(remaining)),
+                (attempt_timeout, remaining) => attempt_timeout.or(remaining),
+            };
+            let result = match attempt_timeout {
+                Some(attempt_timeout) => match timeout(attempt_timeout, call()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(Status::deadline_exceeded(format!(
+                        "{operation} did not complete within {attempt_timeout:?}"
+                    ))),
+                },
+                None => call().await,
+            };
+
+            let status = match result {
+                Ok(value) => return Ok(value),
+                Err(status) => status,
+            };
+
+            if attempt >= self.max_attempts || !self.is_retryable(&status) {
+                return Err(status);
+            }
+
+            let delay = self.backoff(attempt);
+            if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+                return Err(status);
+            }
+
+            warn!(
+                "{operation} failed on attempt {attempt} of {} with {:?}: '{}'. Retrying in {delay:?}.",
+                self.max_attempts,
+                status.code(),
+                status.message()
+            );
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Convert a failure to connect to a gRPC server to a status. The server may not be up yet, so
+/// the status can be retried.
+///
+/// # Arguments
+/// * `error` - The connection error.
+pub fn connection_error(error: tonic::transport::Error) -> Status {
+    Status::unavailable(format!("Failed to connect: {error}"))
+}
+
+/// Use Chariott Service Discovery to discover the services in a namespace, and select the best
+/// compatible one. Chariotts that do not support `DiscoverByNamespace` are asked to `List` all of
+/// their services instead.
+/// Returns the selected service and the services that were rejected.
 ///
 /// # Arguments
 /// * `chariott_uri` - Chariott's URI.
-/// * `namespace` - The service's namespace.
-/// * `name` - The service's name.
-/// * `version` - The service's version.
-/// # `communication_kind` - The service's communication kind.
-/// # `communication_reference` - The service's communication reference.
+/// * `service` - The required service.
+/// * `retry_policy` - How the calls to Chariott are retried.
 pub async fn discover_service_using_chariott(
     chariott_uri: &str,
-    namespace: &str,
-    name: &str,
-    version: &str,
-    communication_kind: &str,
-    communication_reference: &str,
-) -> Result<String, Status> {
-    let mut client = ServiceRegistryClient::connect(chariott_uri.to_string())
-        .await
-        .map_err(|e| Status::internal(e.to_string()))?;
+    service: &ServiceDescriptor,
+    retry_policy: &RetryPolicy,
+) -> Result<ServiceMatch, DiscoveryError> {
+    let context = || format!("Chariott at {chariott_uri}, {service}");
+    let namespace = &service.namespace;
 
-    let request = Request::new(DiscoverRequest {
-        namespace: namespace.to_string(),
-        name: name.to_string(),
-        version: version.to_string(),
-    });
+    let services = retry_policy
+        .run("Discover with Chariott", move || async move {
+            let mut client = ServiceRegistryClient::new(connect(chariott_uri)
+                .await
+                .map_err(connection_error)?);
 
-    let response = client
-        .discover(request)
-        .await
-        .map_err(|error| Status::internal(error.to_string()))?;
+            let request = Request::new(DiscoverByNamespaceRequest {
+                namespace: namespace.to_string(),
+            });
 
-    let service = response.into_inner().service.ok_or_else(|| Status::not_found("Did not find a service in Chariott with namespace '{namespace}', name '{name}' and version {version}"))?;
+            match client.discover_by_namespace(request).await {
+                Ok(response) => Ok(response.into_inner().services),
+                Err(status) if status.code() == Code::NotFound => Ok(Vec::new()),
+                Err(status) if status.code() == Code::Unimplemented => {
+                    debug!("Chariott at {chariott_uri} does not support DiscoverByNamespace, listing all services instead");
+                    let response = client.list(Request::new(ListRequest {})).await?;
+                    Ok(response.into_inner().services)
+                }
+                Err(status) => Err(status),
+            }
+        })
+        .await
+        .map_err(|status| DiscoveryError::from(status).with_context(context()))?;
 
-    if service.communication_kind == communication_kind
-        && service.communication_reference == communication_reference
-    {
-        Ok(service.uri)
-    } else {
-        Err(Status::not_found(
-            "Did not find a service in Chariott with namespace '{namespace}', name '{name}' and version {version} that has communication kind '{communication_kind} and communication_reference '{communication_reference}''",
-        ))
-    }
+    select_service(service, services).map_err(|err| err.with_context(context()))
 }
 
 /// Use Ibeji to discover the endpoint for a digital twin provider that satifies the requirements.
@@ -61,32 +299,57 @@ pub async fn discover_service_using_chariott(
 /// * `entity_id` - The matching entity id.
 /// * `protocol` - The required protocol.
 /// * `operations` - The required operations.
+/// * `retry_policy` - How the calls to the In-Vehicle Digital Twin Service are retried.
 pub async fn discover_digital_twin_provider_using_ibeji(
     invehicle_digitial_twin_service_uri: &str,
     entity_id: &str,
     protocol: &str,
     operations: &[String],
-) -> Result<EndpointInfo, String> {
+    retry_policy: &RetryPolicy,
+) -> Result<EndpointInfo, DiscoveryError> {
+    let context = || {
+        format!("In-Vehicle Digital Twin Service at {invehicle_digitial_twin_service_uri}, entity id {entity_id}")
+    };
+
+    let requested_id = Dtmi::parse(entity_id)
+        .map_err(|err| DiscoveryError::invalid_request(err).with_context(context()))?;
+
     info!("Sending a find_by_id request for entity id {entity_id} to the In-Vehicle Digital Twin Service URI {invehicle_digitial_twin_service_uri}");
 
-    let mut client =
-        InvehicleDigitalTwinClient::connect(invehicle_digitial_twin_service_uri.to_string())
-            .await
-            .map_err(|error| format!("{error}"))?;
-    let request = tonic::Request::new(FindByIdRequest {
-        id: entity_id.to_string(),
-    });
-    let response = client
-        .find_by_id(request)
+    let response = retry_policy
+        .run("FindById", move || async move {
+            let mut client = InvehicleDigitalTwinClient::new(
+                connect(invehicle_digitial_twin_service_uri)
+                    .await
+                    .map_err(connection_error)?,
+            );
+            let request = tonic::Request::new(FindByIdRequest {
+                id: entity_id.to_string(),
+            });
+
+            client.find_by_id(request).await
+        })
         .await
-        .map_err(|error| error.to_string())?;
+        .map_err(|status| DiscoveryError::from(status).with_context(context()))?;
     let response_inner = response.into_inner();
     debug!("Received the response for the find_by_id request");
     info!("response_payload: {:?}", response_inner.entity_access_info);
 
-    match response_inner
-        .entity_access_info
-        .ok_or_else(|| "Did not find the entity".to_string())?
+    let entity_access_info = response_inner.entity_access_info.ok_or_else(|| {
+        DiscoveryError::not_found("Did not find the entity").with_context(context())
+    })?;
+
+    let found_id = validate_entity_access_info(&entity_access_info)
+        .map_err(|err| DiscoveryError::malformed_response(err).with_context(context()))?;
+    // A provider may serve a later minor version of the requested entity.
+    if !found_id.is_compatible_with(&requested_id) {
+        return Err(DiscoveryError::malformed_response(format!(
+            "Requested entity id {requested_id}, but received the incompatible entity id {found_id}"
+        ))
+        .with_context(context()));
+    }
+
+    match entity_access_info
         .endpoint_info_list
         .iter()
         .find(|endpoint_info| {
@@ -103,8 +366,28 @@ pub async fn discover_digital_twin_provider_using_ibeji(
 
             Ok(result)
         }
-        None => Err("Did not find an endpoint that met our requirements".to_string()),
+        None => Err(DiscoveryError::protocol_mismatch(format!(
+            "Did not find an endpoint with protocol '{protocol}' and operations {operations:?}"
+        ))
+        .with_context(context())),
+    }
+}
+
+/// Validate the entity access info received from the In-Vehicle Digital Twin Service.
+/// Returns the entity's id.
+///
+/// # Arguments
+/// * `entity_access_info` - The entity access info.
+pub fn validate_entity_access_info(entity_access_info: &EntityAccessInfo) -> Result<Dtmi, String> {
+    let id = Dtmi::parse(&entity_access_info.id)?;
+
+    if entity_access_info.name.is_empty() {
+        return Err(format!(
+            "The entity access info for {id} does not have a name"
+        ));
     }
+
+    Ok(id)
 }
 
 /// Is the provided subset a subset of the provided superset?
@@ -119,3 +402,108 @@ fn is_subset(subset: &[String], superset: &[String]) -> bool {
             .any(|supserset_member| subset_member == supserset_member)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// A policy with a fixed delay of one second between attempts.
+    fn fixed_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::default()
+            .with_max_attempts(max_attempts)
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(1), 1.0)
+            .with_jitter(0.0)
+            .with_attempt_timeout(None)
+            .with_overall_timeout(None)
+    }
+
+    /// Run a call that fails with the provided codes in turn, and then succeeds.
+    /// Returns the result and the number of attempts.
+    async fn run_failing(policy: &RetryPolicy, codes: &[Code]) -> (Result<u32, Status>, usize) {
+        let attempts = Cell::new(0);
+        let result = policy
+            .run("Test", || {
+                let attempt = attempts.get();
+                attempts.set(attempt + 1);
+                let code = codes.get(attempt).copied();
+                async move {
+                    match code {
+                        Some(code) => Err(Status::new(code, "failed")),
+                        None => Ok(42),
+                    }
+                }
+            })
+            .await;
+
+        (result, attempts.get())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_the_retryable_codes() {
+        let policy = fixed_policy(10);
+
+        let (result, attempts) = run_failing(&policy, RETRYABLE_CODES).await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts, RETRYABLE_CODES.len() + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_other_codes() {
+        let policy = fixed_policy(10);
+
+        let (result, attempts) = run_failing(&policy, &[Code::NotFound]).await;
+        assert_eq!(result.unwrap_err().code(), Code::NotFound);
+        assert_eq!(attempts, 1);
+
+        let policy = policy.with_retryable_code(Code::NotFound);
+        let (result, attempts) = run_failing(&policy, &[Code::NotFound]).await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_after_max_attempts() {
+        let policy = fixed_policy(3);
+
+        let (result, attempts) = run_failing(&policy, &[Code::Unavailable; 5]).await;
+        assert_eq!(result.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(attempts, 3);
+
+        let (result, attempts) = run_failing(&policy.single_attempt(), &[Code::Unavailable]).await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_at_the_overall_deadline() {
+        let policy = fixed_policy(100).with_overall_timeout(Some(Duration::from_millis(3500)));
+        let start = Instant::now();
+
+        let (result, attempts) = run_failing(&policy, &[Code::Unavailable; 100]).await;
+        assert_eq!(result.unwrap_err().code(), Code::Unavailable);
+        // Attempts at 0, 1, 2 and 3 seconds; the next one would start after the deadline.
+        assert_eq!(attempts, 4);
+        assert!(start.elapsed() < Duration::from_millis(3500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn attempts_are_bounded_by_the_remaining_time() {
+        let policy = fixed_policy(100)
+            .with_attempt_timeout(Some(Duration::from_secs(2)))
+            .with_overall_timeout(Some(Duration::from_millis(3500)));
+        let start = Instant::now();
+
+        let result: Result<(), Status> = policy
+            .run("Test", || async {
+                sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+        // The first attempt times out after 2 seconds, and the second one after the remaining
+        // half second.
+        assert_eq!(result.unwrap_err().code(), Code::DeadlineExceeded);
+        assert_eq!(start.elapsed(), Duration::from_millis(3500));
+    }
+}
@@ -2,7 +2,42 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("../interfaces/digital_twin_get_provider.proto")?;
+    // The file descriptor sets are served by the gRPC reflection service.
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("digital_twin_get_provider_descriptor.bin"))
+        .compile(
+            &["../interfaces/digital_twin_get_provider.proto"],
+            &["../interfaces/"],
+        )?;
+    // The protos that other providers serve as well are in the scenario's interfaces directory.
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("digital_twin_get_provider_v2_descriptor.bin"))
+        .compile(
+            &["../../../interfaces/digital_twin_get_provider/v2/digital_twin_get_provider.proto"],
+            &["../../../interfaces/"],
+        )?;
+    // The Set provider's values are the Get provider's version 2 values. Its code is generated in
+    // a directory of its own, so that the code that is generated for the imported file, which
+    // refers to the extern path, does not overwrite the Get provider's code.
+    let set_out_dir = out_dir.join("digital_twin_set_provider");
+    fs::create_dir_all(&set_out_dir)?;
+    tonic_build::configure()
+        .out_dir(&set_out_dir)
+        .file_descriptor_set_path(out_dir.join("digital_twin_set_provider_descriptor.bin"))
+        .extern_path(
+            ".digital_twin_get_provider.v2",
+            "crate::digital_twin_get_provider::v2",
+        )
+        .compile(
+            &["../../../interfaces/digital_twin_set_provider/v1/digital_twin_set_provider.proto"],
+            &["../../../interfaces/"],
+        )?;
     Ok(())
 }
@@ -5,5 +5,27 @@
 pub mod digital_twin_get_provider {
     pub mod v1 {
         tonic::include_proto!("digital_twin_get_provider");
+
+        pub const FILE_DESCRIPTOR_SET: &[u8] =
+            tonic::include_file_descriptor_set!("digital_twin_get_provider_descriptor");
+    }
+
+    pub mod v2 {
+        tonic::include_proto!("digital_twin_get_provider.v2");
+
+        pub const FILE_DESCRIPTOR_SET: &[u8] =
+            tonic::include_file_descriptor_set!("digital_twin_get_provider_v2_descriptor");
+    }
+}
+
+pub mod digital_twin_set_provider {
+    pub mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/digital_twin_set_provider/digital_twin_set_provider.v1.rs"
+        ));
+
+        pub const FILE_DESCRIPTOR_SET: &[u8] =
+            tonic::include_file_descriptor_set!("digital_twin_set_provider_descriptor");
     }
 }
@@ -0,0 +1,588 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding of the trailer's connection state from CAN frames.
+//!
+//! The state is a bit of the frames with a configured id: the trailer is connected when any bit of
+//! the mask is set in the configured data byte. The frames are read from a candump file, either in
+//! the log format of `candump -l`, such as `(1700000000.000000) vcan0 300#01`, or in the console
+//! format of `candump`, such as `vcan0  300   [1]  01`. On Linux, they can also be read live from
+//! a SocketCAN interface such as `vcan0`.
+
+use std::fmt;
+use std::fs;
+
+use log::{debug, warn};
+use tokio::time::{sleep, Duration};
+
+use crate::connection_source::ConnectionSource;
+
+/// The largest standard CAN id, which has 11 bits.
+const MAX_STANDARD_ID: u32 = 0x7FF;
+/// The largest extended CAN id, which has 29 bits.
+const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+
+/// A CAN id. A standard and an extended id with the same number are different ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CanId {
+    /// An 11 bit id.
+    Standard(u16),
+    /// A 29 bit id.
+    Extended(u32),
+}
+
+impl fmt::Display for CanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CanId::Standard(id) => write!(f, "{id:03X}"),
+            CanId::Extended(id) => write!(f, "{id:08X}"),
+        }
+    }
+}
+
+/// A CAN frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanFrame {
+    pub id: CanId,
+    pub data: Vec<u8>,
+}
+
+/// Parse a CAN id, in hexadecimal with or without a `0x` prefix. As with `candump` and `cansend`,
+/// an id of up to 3 digits is a standard id and an id of 8 digits is an extended id, so `300` and
+/// `00000300` are different ids.
+///
+/// # Arguments
+/// * `id` - The id.
+pub fn parse_frame_id(id: &str) -> Result<CanId, String> {
+    let digits = id.trim().trim_start_matches("0x").trim_start_matches("0X");
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Invalid CAN id '{id}': expected hexadecimal digits"
+        ));
+    }
+    let value =
+        u32::from_str_radix(digits, 16).map_err(|err| format!("Invalid CAN id '{id}': {err}"))?;
+
+    match digits.len() {
+        1..=3 if value <= MAX_STANDARD_ID => Ok(CanId::Standard(value as u16)),
+        8 if value <= MAX_EXTENDED_ID => Ok(CanId::Extended(value)),
+        1..=3 => Err(format!(
+            "Invalid CAN id '{id}': a standard id must not be greater than {MAX_STANDARD_ID:#X}"
+        )),
+        8 => Err(format!(
+            "Invalid CAN id '{id}': an extended id must not be greater than {MAX_EXTENDED_ID:#X}"
+        )),
+        _ => Err(format!(
+            "Invalid CAN id '{id}': expected up to 3 digits for a standard id, or 8 digits for an extended id"
+        )),
+    }
+}
+
+/// Parse a line of a candump file. Returns the frame and its timestamp in seconds, if the line
+/// has one, or `None` for an empty line or a remote frame, which has no data.
+///
+/// # Arguments
+/// * `line` - The line.
+pub fn parse_candump_line(line: &str) -> Result<Option<(Option<f64>, CanFrame)>, String> {
+    let mut tokens = line.split_whitespace().peekable();
+
+    let timestamp = match tokens.peek() {
+        None => return Ok(None),
+        Some(token) if token.starts_with('(') => {
+            let token = tokens.next().unwrap_or_default();
+            let seconds = token
+                .trim_start_matches('(')
+                .trim_end_matches(')')
+                .parse()
+                .map_err(|err| format!("Invalid timestamp '{token}': {err}"))?;
+            Some(seconds)
+        }
+        Some(_) => None,
+    };
+
+    // The interface name.
+    tokens.next().ok_or("The line has no interface")?;
+    let frame = tokens.next().ok_or("The line has no frame")?;
+
+    let (id, data) = match frame.split_once('#') {
+        // The log format: <id>#<data>, <id>#R for remote frames, or <id>##<flags><data> for CAN FD.
+        Some((id, data)) => {
+            if data.starts_with('R') {
+                return Ok(None);
+            }
+            let data = match data.strip_prefix('#') {
+                Some(fd_data) => fd_data.get(1..).unwrap_or_default(),
+                None => data,
+            };
+            (id, parse_data(data)?)
+        }
+        // The console format: <id> [<length>] <byte> <byte> ...
+        None => {
+            let length = tokens.next().ok_or("The line has no length")?;
+            if length.starts_with("[R") || tokens.peek() == Some(&"remote") {
+                return Ok(None);
+            }
+            let data = tokens
+                .take_while(|token| !token.starts_with('\''))
+                .collect::<String>();
+            (frame, parse_data(&data)?)
+        }
+    };
+
+    Ok(Some((
+        timestamp,
+        CanFrame {
+            id: parse_frame_id(id)?,
+            data,
+        },
+    )))
+}
+
+/// Parse hexadecimal frame data.
+///
+/// # Arguments
+/// * `data` - The data, two hexadecimal digits per byte, optionally separated by dots.
+fn parse_data(data: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = data.chars().filter(|c| *c != '.').collect();
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(format!("Invalid frame data '{data}'"));
+    }
+
+    pairs
+        .map(|pair| {
+            u8::from_str_radix(&pair.iter().collect::<String>(), 16)
+                .map_err(|err| format!("Invalid frame data '{data}': {err}"))
+        })
+        .collect()
+}
+
+/// Decodes the connection state from a bit of the frames with an id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanDecoder {
+    frame_id: CanId,
+    byte: usize,
+    mask: u8,
+}
+
+impl CanDecoder {
+    /// Create a decoder.
+    ///
+    /// # Arguments
+    /// * `frame_id` - The id of the frames that carry the state.
+    /// * `byte` - The index of the data byte that carries the state.
+    /// * `mask` - The bits of the byte that are set when the trailer is connected.
+    pub fn new(frame_id: CanId, byte: usize, mask: u8) -> Self {
+        CanDecoder {
+            frame_id,
+            byte,
+            mask,
+        }
+    }
+
+    /// Decode the connection state from a frame. Returns `None` for frames with another id,
+    /// including the other format's id with the same number, and for frames that are too short.
+    ///
+    /// # Arguments
+    /// * `frame` - The frame.
+    pub fn decode(&self, frame: &CanFrame) -> Option<bool> {
+        if frame.id != self.frame_id {
+            return None;
+        }
+
+        frame
+            .data
+            .get(self.byte)
+            .map(|value| value & self.mask != 0)
+    }
+}
+
+impl fmt::Display for CanDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mask {:#04x} of byte {} of CAN frame {}",
+            self.mask, self.byte, self.frame_id
+        )
+    }
+}
+
+/// Reads the connection state from a candump file, optionally with the file's timing.
+#[derive(Debug)]
+pub struct CanLogSource {
+    path: String,
+    lines: std::vec::IntoIter<String>,
+    line_number: usize,
+    decoder: CanDecoder,
+    replay_timing: bool,
+    last_timestamp: Option<f64>,
+}
+
+impl CanLogSource {
+    /// Open a candump file.
+    ///
+    /// # Arguments
+    /// * `path` - The file's path.
+    /// * `decoder` - Decodes the connection state from the frames.
+    /// * `replay_timing` - Whether the frames are delayed according to their timestamps.
+    pub fn open(path: &str, decoder: CanDecoder, replay_timing: bool) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("The candump file '{path}' cannot be read: {err}"))?;
+        debug!("The trailer's connection state is the {decoder}");
+
+        Ok(CanLogSource {
+            path: path.to_string(),
+            lines: content
+                .lines()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .into_iter(),
+            line_number: 0,
+            decoder,
+            replay_timing,
+            last_timestamp: None,
+        })
+    }
+}
+
+impl fmt::Display for CanLogSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the candump file '{}'", self.path)
+    }
+}
+
+#[tonic::async_trait]
+impl ConnectionSource for CanLogSource {
+    async fn next_state(&mut self) -> Result<Option<bool>, String> {
+        for line in self.lines.by_ref() {
+            self.line_number += 1;
+
+            let (timestamp, frame) = match parse_candump_line(&line) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!(
+                        "Skipping line {} of the candump file '{}': {err}",
+                        self.line_number, self.path
+                    );
+                    continue;
+                }
+            };
+
+            let Some(connected) = self.decoder.decode(&frame) else {
+                continue;
+            };
+
+            if let (true, Some(timestamp)) = (self.replay_timing, timestamp) {
+                if let Some(last_timestamp) = self.last_timestamp {
+                    sleep(Duration::from_secs_f64(
+                        (timestamp - last_timestamp).max(0.0),
+                    ))
+                    .await;
+                }
+                self.last_timestamp = Some(timestamp);
+            }
+
+            return Ok(Some(connected));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Open a source that reads the connection state from a SocketCAN interface.
+///
+/// # Arguments
+/// * `interface` - The interface, for example `vcan0`.
+/// * `decoder` - Decodes the connection state from the frames.
+#[cfg(target_os = "linux")]
+pub fn open_socket_source(
+    interface: &str,
+    decoder: CanDecoder,
+) -> Result<Box<dyn ConnectionSource>, String> {
+    Ok(Box::new(socket::CanSocketSource::open(interface, decoder)?))
+}
+
+/// Open a source that reads the connection state from a SocketCAN interface.
+///
+/// # Arguments
+/// * `interface` - The interface, for example `vcan0`.
+/// * `decoder` - Decodes the connection state from the frames.
+#[cfg(not(target_os = "linux"))]
+pub fn open_socket_source(
+    interface: &str,
+    _decoder: CanDecoder,
+) -> Result<Box<dyn ConnectionSource>, String> {
+    Err(format!(
+        "Cannot read the CAN interface '{interface}': SocketCAN is only supported on Linux"
+    ))
+}
+
+#[cfg(target_os = "linux")]
+mod socket {
+    use std::fmt;
+
+    use futures::StreamExt;
+    use log::debug;
+    use socketcan::tokio::CanSocket;
+    use socketcan::{EmbeddedFrame, Id};
+
+    use super::{CanDecoder, CanFrame, CanId};
+    use crate::connection_source::ConnectionSource;
+
+    /// Reads the connection state from a SocketCAN interface.
+    #[derive(Debug)]
+    pub struct CanSocketSource {
+        interface: String,
+        socket: CanSocket,
+        decoder: CanDecoder,
+    }
+
+    impl CanSocketSource {
+        /// Open a CAN socket that is bound to an interface.
+        ///
+        /// # Arguments
+        /// * `interface` - The interface, for example `vcan0`.
+        /// * `decoder` - Decodes the connection state from the frames.
+        pub fn open(interface: &str, decoder: CanDecoder) -> Result<Self, String> {
+            let socket = CanSocket::open(interface)
+                .map_err(|err| format!("Cannot read the CAN interface '{interface}': {err}"))?;
+
+            debug!("The trailer's connection state is the {decoder}");
+            Ok(CanSocketSource {
+                interface: interface.to_string(),
+                socket,
+                decoder,
+            })
+        }
+    }
+
+    impl fmt::Display for CanSocketSource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "the CAN interface '{}'", self.interface)
+        }
+    }
+
+    #[tonic::async_trait]
+    impl ConnectionSource for CanSocketSource {
+        async fn next_state(&mut self) -> Result<Option<bool>, String> {
+            while let Some(frame) = self.socket.next().await {
+                let frame = frame
+                    .map_err(|err| format!("Failed to read from '{}': {err}", self.interface))?;
+                if let Some(connected) =
+                    to_frame(&frame).and_then(|frame| self.decoder.decode(&frame))
+                {
+                    return Ok(Some(connected));
+                }
+            }
+
+            Ok(None)
+        }
+    }
+
+    /// Convert a SocketCAN frame. Returns `None` for remote and error frames, which have no data.
+    ///
+    /// # Arguments
+    /// * `frame` - The frame.
+    fn to_frame(frame: &socketcan::CanFrame) -> Option<CanFrame> {
+        let socketcan::CanFrame::Data(frame) = frame else {
+            return None;
+        };
+        let id = match frame.id() {
+            Id::Standard(id) => CanId::Standard(id.as_raw()),
+            Id::Extended(id) => CanId::Extended(id.as_raw()),
+        };
+
+        Some(CanFrame {
+            id,
+            data: frame.data().to_vec(),
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use socketcan::{ExtendedId, StandardId};
+
+        use super::*;
+
+        #[test]
+        fn socketcan_frames_keep_their_id_format() {
+            let standard =
+                socketcan::CanFrame::new(StandardId::new(0x300).unwrap(), &[0x01]).unwrap();
+            let extended =
+                socketcan::CanFrame::new(ExtendedId::new(0x300).unwrap(), &[0x01]).unwrap();
+
+            assert_eq!(
+                to_frame(&standard).map(|frame| frame.id),
+                Some(CanId::Standard(0x300))
+            );
+            assert_eq!(
+                to_frame(&extended).map(|frame| frame.id),
+                Some(CanId::Extended(0x300))
+            );
+        }
+
+        #[test]
+        fn remote_frames_are_skipped() {
+            let remote =
+                socketcan::CanFrame::new_remote(StandardId::new(0x300).unwrap(), 1).unwrap();
+
+            assert_eq!(to_frame(&remote), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: CanId = CanId::Standard(0x300);
+
+    fn frame(id: CanId, data: &[u8]) -> CanFrame {
+        CanFrame {
+            id,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn log_lines_are_parsed() {
+        assert_eq!(
+            parse_candump_line("(1700000000.500000) vcan0 300#01").unwrap(),
+            Some((Some(1700000000.5), frame(ID, &[0x01])))
+        );
+        assert_eq!(
+            parse_candump_line("vcan0 18FEF100#01.FF").unwrap(),
+            Some((None, frame(CanId::Extended(0x18FE_F100), &[0x01, 0xFF])))
+        );
+    }
+
+    #[test]
+    fn console_lines_are_parsed() {
+        assert_eq!(
+            parse_candump_line("  vcan0  300   [2]  01 FF").unwrap(),
+            Some((None, frame(ID, &[0x01, 0xFF])))
+        );
+        // The ASCII column of `candump -a` is not data.
+        assert_eq!(
+            parse_candump_line("(1700000000.000000)  vcan0  300   [1]  41   'A'").unwrap(),
+            Some((Some(1700000000.0), frame(ID, &[0x41])))
+        );
+    }
+
+    #[test]
+    fn fd_frames_are_parsed_without_their_flags() {
+        assert_eq!(
+            parse_candump_line("vcan0 300##10102").unwrap(),
+            Some((None, frame(ID, &[0x01, 0x02])))
+        );
+    }
+
+    #[test]
+    fn remote_frames_and_empty_lines_are_skipped() {
+        assert_eq!(parse_candump_line("vcan0 300#R").unwrap(), None);
+        assert_eq!(parse_candump_line("vcan0 300#R1").unwrap(), None);
+        assert_eq!(
+            parse_candump_line("  vcan0  300   [1]  remote request").unwrap(),
+            None
+        );
+        assert_eq!(parse_candump_line("").unwrap(), None);
+        assert_eq!(parse_candump_line("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        for line in [
+            "(abc) vcan0 300#01",
+            "vcan0",
+            "vcan0 XYZ#01",
+            "vcan0 300#0G",
+            "vcan0 300#012",
+            "  vcan0  300",
+            "  vcan0  300   [1]  0",
+        ] {
+            assert!(parse_candump_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn data_is_parsed_with_or_without_dots() {
+        assert_eq!(parse_data("0102").unwrap(), vec![0x01, 0x02]);
+        assert_eq!(parse_data("01.02").unwrap(), vec![0x01, 0x02]);
+        assert_eq!(parse_data("").unwrap(), Vec::<u8>::new());
+        assert!(parse_data("1").is_err());
+        assert!(parse_data("zz").is_err());
+    }
+
+    #[test]
+    fn frame_ids_are_parsed_with_or_without_a_prefix() {
+        assert_eq!(parse_frame_id("300"), Ok(ID));
+        assert_eq!(parse_frame_id("0x300"), Ok(ID));
+        assert_eq!(parse_frame_id("7FF"), Ok(CanId::Standard(0x7FF)));
+        assert_eq!(
+            parse_frame_id("0X18FEF100"),
+            Ok(CanId::Extended(0x18FE_F100))
+        );
+        assert_eq!(parse_frame_id("00000300"), Ok(CanId::Extended(0x300)));
+    }
+
+    #[test]
+    fn frame_ids_out_of_their_format_are_rejected() {
+        for id in [
+            "0x",
+            "800",
+            "0x20000000",
+            "3000",
+            "0000300",
+            "000000300",
+            "+30",
+            "-1",
+        ] {
+            assert!(parse_frame_id(id).is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn frame_ids_are_displayed_in_their_format() {
+        assert_eq!(ID.to_string(), "300");
+        assert_eq!(CanId::Extended(0x300).to_string(), "00000300");
+    }
+
+    #[test]
+    fn extended_frames_are_parsed_from_candump_lines() {
+        assert_eq!(
+            parse_candump_line("vcan0 00000300#01").unwrap(),
+            Some((None, frame(CanId::Extended(0x300), &[0x01])))
+        );
+        assert_eq!(
+            parse_candump_line("  vcan0  00000300   [1]  01").unwrap(),
+            Some((None, frame(CanId::Extended(0x300), &[0x01])))
+        );
+    }
+
+    #[test]
+    fn the_state_is_decoded_from_the_masked_bits() {
+        let decoder = CanDecoder::new(ID, 1, 0x04);
+
+        assert_eq!(decoder.decode(&frame(ID, &[0x00, 0x04])), Some(true));
+        assert_eq!(decoder.decode(&frame(ID, &[0xFF, 0x0C])), Some(true));
+        assert_eq!(decoder.decode(&frame(ID, &[0xFF, 0xFB])), Some(false));
+    }
+
+    #[test]
+    fn other_frames_and_short_frames_are_not_decoded() {
+        let decoder = CanDecoder::new(ID, 1, 0x04);
+
+        assert_eq!(
+            decoder.decode(&frame(CanId::Standard(0x301), &[0x00, 0x04])),
+            None
+        );
+        assert_eq!(
+            decoder.decode(&frame(CanId::Extended(0x300), &[0x00, 0x04])),
+            None
+        );
+        assert_eq!(decoder.decode(&frame(ID, &[0x04])), None);
+        assert_eq!(decoder.decode(&frame(ID, &[])), None);
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+use digital_twin_providers_common::config::{
+    ChariottConfig, Config, DiscoveryConfig, MqttConfig, ProviderConfig, RetryConfig, TlsConfig,
+};
+use serde_derive::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use crate::can::{open_socket_source, parse_frame_id, CanDecoder, CanLogSource};
+use crate::connection_source::{ConnectionSource, FileSource, ScriptedSource};
+
+const PROVIDER_AUTHORITY: &str = "0.0.0.0:4020";
+const MQTT_CLIENT_ID: &str = "trailer-connected-publisher";
+
+const DEFAULT_SCRIPT: &str = "connected";
+const DEFAULT_POLL_INTERVAL_MS: u64 = 500;
+const DEFAULT_CAN_INTERFACE: &str = "vcan0";
+const DEFAULT_CAN_FRAME_ID: &str = "0x300";
+const DEFAULT_CAN_MASK: u8 = 0x01;
+
+/// The trailer connected provider's configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrailerConnectedProviderConfig {
+    pub chariott: ChariottConfig,
+    pub provider: ProviderConfig,
+    pub retry: RetryConfig,
+    pub discovery: DiscoveryConfig,
+    pub tls: TlsConfig,
+    pub mqtt: MqttConfig,
+    pub connection: ConnectionConfig,
+}
+
+impl Default for TrailerConnectedProviderConfig {
+    fn default() -> Self {
+        TrailerConnectedProviderConfig {
+            chariott: ChariottConfig::default(),
+            provider: ProviderConfig::new(PROVIDER_AUTHORITY),
+            retry: RetryConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            tls: TlsConfig::default(),
+            mqtt: MqttConfig::new(MQTT_CLIENT_ID),
+            connection: ConnectionConfig::default(),
+        }
+    }
+}
+
+impl Config for TrailerConnectedProviderConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.chariott.validate()?;
+        self.provider.validate()?;
+        self.retry.validate()?;
+        self.discovery.validate()?;
+        self.tls.validate()?;
+        self.mqtt.validate()?;
+        self.connection.validate()
+    }
+}
+
+/// Where the trailer's connection state is read from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionSourceKind {
+    /// Simulate the state with `connection.script`.
+    #[default]
+    Scripted,
+    /// Poll `connection.file_path`, for example a sysfs GPIO value.
+    File,
+    /// Replay the CAN frames of the candump file `connection.can_log_path`.
+    CanLog,
+    /// Read the CAN frames of the SocketCAN interface `connection.can_interface`.
+    CanSocket,
+}
+
+/// The configuration of the trailer's connection state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionConfig {
+    /// Where the state is read from.
+    pub source: ConnectionSourceKind,
+    /// The simulated states, as comma separated steps of the form `<state>[:<duration_ms>]`.
+    pub script: String,
+    /// Whether the script starts over after its last step.
+    pub repeat: bool,
+    /// The file that contains the state, such as `/sys/class/gpio/gpio17/value`.
+    pub file_path: String,
+    /// How often the file is read.
+    pub poll_interval_ms: u64,
+    /// Whether the file's value is inverted, so that `0` means connected.
+    pub active_low: bool,
+    /// The candump file, in the log or the console format.
+    pub can_log_path: String,
+    /// Whether the candump file is replayed with the timing of its frames.
+    pub replay_timing: bool,
+    /// The SocketCAN interface.
+    pub can_interface: String,
+    /// The id of the CAN frames that carry the state, in hexadecimal.
+    pub can_frame_id: String,
+    /// The index of the data byte that carries the state.
+    pub can_byte: usize,
+    /// The bits of the data byte that are set when the trailer is connected.
+    pub can_mask: u8,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            source: ConnectionSourceKind::default(),
+            script: DEFAULT_SCRIPT.to_string(),
+            repeat: true,
+            file_path: String::new(),
+            poll_interval_ms: DEFAULT_POLL_INTERVAL_MS,
+            active_low: false,
+            can_log_path: String::new(),
+            replay_timing: true,
+            can_interface: DEFAULT_CAN_INTERFACE.to_string(),
+            can_frame_id: DEFAULT_CAN_FRAME_ID.to_string(),
+            can_byte: 0,
+            can_mask: DEFAULT_CAN_MASK,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Check that the configuration values are valid.
+    fn validate(&self) -> Result<(), String> {
+        match self.source {
+            ConnectionSourceKind::Scripted => {
+                ScriptedSource::parse(&self.script, self.repeat)
+                    .map_err(|err| format!("connection.script: {err}"))?;
+            }
+            ConnectionSourceKind::File => {
+                if self.file_path.is_empty() {
+                    return Err("connection.file_path must be set with the file source".to_string());
+                }
+                if self.poll_interval_ms == 0 {
+                    return Err("connection.poll_interval_ms must be greater than 0".to_string());
+                }
+            }
+            ConnectionSourceKind::CanLog | ConnectionSourceKind::CanSocket => {
+                if self.source == ConnectionSourceKind::CanLog && self.can_log_path.is_empty() {
+                    return Err(
+                        "connection.can_log_path must be set with the can_log source".to_string(),
+                    );
+                }
+                if self.source == ConnectionSourceKind::CanSocket && self.can_interface.is_empty() {
+                    return Err(
+                        "connection.can_interface must be set with the can_socket source"
+                            .to_string(),
+                    );
+                }
+                self.decoder()?;
+                if self.can_byte >= 8 {
+                    return Err(format!(
+                        "connection.can_byte ({}) must be less than 8",
+                        self.can_byte
+                    ));
+                }
+                if self.can_mask == 0 {
+                    return Err("connection.can_mask must not be 0".to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create the connection source.
+    pub fn source(&self) -> Result<Box<dyn ConnectionSource>, String> {
+        Ok(match self.source {
+            ConnectionSourceKind::Scripted => {
+                Box::new(ScriptedSource::parse(&self.script, self.repeat)?)
+            }
+            ConnectionSourceKind::File => Box::new(FileSource::new(
+                &self.file_path,
+                Duration::from_millis(self.poll_interval_ms),
+                self.active_low,
+            )),
+            ConnectionSourceKind::CanLog => Box::new(CanLogSource::open(
+                &self.can_log_path,
+                self.decoder()?,
+                self.replay_timing,
+            )?),
+            ConnectionSourceKind::CanSocket => {
+                open_socket_source(&self.can_interface, self.decoder()?)?
+            }
+        })
+    }
+
+    /// Create the decoder of the CAN frames.
+    fn decoder(&self) -> Result<CanDecoder, String> {
+        let frame_id = parse_frame_id(&self.can_frame_id)
+            .map_err(|err| format!("connection.can_frame_id: {err}"))?;
+
+        Ok(CanDecoder::new(frame_id, self.can_byte, self.can_mask))
+    }
+}
@@ -0,0 +1,474 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sources of the trailer's connection state.
+//!
+//! A [`ConnectionSource`] reports whether the trailer is coupled. The provider watches its source
+//! with [`watch_connection`], which logs every transition. The sources are a scripted simulator,
+//! a watched file such as a sysfs GPIO value, and the CAN sources in [`crate::can`].
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use digital_twin_providers_common::shutdown::CancellationToken;
+use log::{error, info, warn};
+use tokio::fs;
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration};
+
+/// The trailer's connection state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionState {
+    /// Whether the trailer is connected.
+    pub connected: bool,
+    /// When the source reported the change to this state.
+    pub since: SystemTime,
+}
+
+/// A source of the trailer's connection state.
+#[tonic::async_trait]
+pub trait ConnectionSource: fmt::Display + Send {
+    /// Wait for the next reading of the connection state, where `true` means that the trailer is
+    /// connected. A reading may repeat the previous state. Returns `None` when the source has no
+    /// more readings.
+    async fn next_state(&mut self) -> Result<Option<bool>, String>;
+}
+
+/// Watch a connection source until the token is cancelled. The state is `None` until the source
+/// reports it, and after the source fails. The state is kept until the token is cancelled, even
+/// after the source ends or fails, so the receivers only see the sender go away on shutdown.
+///
+/// # Arguments
+/// * `source` - The connection source.
+/// * `shutdown` - Stops the watching when it is cancelled.
+pub fn watch_connection(
+    mut source: Box<dyn ConnectionSource>,
+    shutdown: CancellationToken,
+) -> watch::Receiver<Option<ConnectionState>> {
+    let (sender, receiver) = watch::channel::<Option<ConnectionState>>(None);
+
+    tokio::spawn(async move {
+        info!("Reading the trailer's connection state from {source}");
+        loop {
+            let result = tokio::select! {
+                result = source.next_state() => result,
+                _ = shutdown.cancelled() => break,
+            };
+
+            match result {
+                Ok(Some(connected)) => {
+                    sender.send_if_modified(|state| {
+                        if state.map(|state| state.connected) == Some(connected) {
+                            return false;
+                        }
+
+                        if connected {
+                            info!("The trailer is connected, according to {source}.");
+                        } else {
+                            info!("The trailer is disconnected, according to {source}.");
+                        }
+                        *state = Some(ConnectionState {
+                            connected,
+                            since: SystemTime::now(),
+                        });
+                        true
+                    });
+                }
+                Ok(None) => {
+                    info!("There are no more readings from {source}, so the trailer's connection state is kept.");
+                    break;
+                }
+                Err(err) => {
+                    error!("Failed to read the trailer's connection state from {source}: {err}");
+                    sender.send_replace(None);
+                    break;
+                }
+            }
+        }
+
+        shutdown.cancelled().await;
+    });
+
+    receiver
+}
+
+/// Parse a connection state, such as `connected`, `true` or `1`.
+///
+/// # Arguments
+/// * `value` - The value.
+pub fn parse_state(value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "connected" | "coupled" | "true" | "on" | "1" => Ok(true),
+        "disconnected" | "uncoupled" | "false" | "off" | "0" => Ok(false),
+        other => Err(format!("'{other}' is not a connection state")),
+    }
+}
+
+/// A step of a scripted simulation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ScriptStep {
+    connected: bool,
+    /// How long the state is held. `None` holds it forever.
+    duration: Option<Duration>,
+}
+
+/// Simulates the trailer being connected and disconnected according to a script.
+#[derive(Debug)]
+pub struct ScriptedSource {
+    steps: Vec<ScriptStep>,
+    repeat: bool,
+    next_step: usize,
+    pending_delay: Option<Duration>,
+    held: bool,
+}
+
+impl ScriptedSource {
+    /// Parse a script of comma separated steps of the form `<state>[:<duration_ms>]`, for example
+    /// `connected:60000,disconnected:10000`. A step without a duration is held forever.
+    ///
+    /// # Arguments
+    /// * `script` - The script.
+    /// * `repeat` - Whether the script starts over after its last step. A repeated script must
+    ///   hold a state for some time, or it would report states without ever waiting.
+    pub fn parse(script: &str, repeat: bool) -> Result<Self, String> {
+        let steps = script
+            .split(',')
+            .map(|step| {
+                let (state, duration) = match step.split_once(':') {
+                    Some((state, duration)) => {
+                        let duration_ms: u64 = duration.trim().parse().map_err(|err| {
+                            format!("Invalid duration in the script step '{step}': {err}")
+                        })?;
+                        (state, Some(Duration::from_millis(duration_ms)))
+                    }
+                    None => (step, None),
+                };
+
+                Ok(ScriptStep {
+                    connected: parse_state(state)
+                        .map_err(|err| format!("Invalid script step '{step}': {err}"))?,
+                    duration,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        if repeat
+            && steps
+                .iter()
+                .all(|step| step.duration == Some(Duration::ZERO))
+        {
+            return Err(format!(
+                "The repeated script '{script}' must have a step with a duration greater than 0"
+            ));
+        }
+
+        Ok(ScriptedSource {
+            steps,
+            repeat,
+            next_step: 0,
+            pending_delay: None,
+            held: false,
+        })
+    }
+}
+
+impl fmt::Display for ScriptedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the scripted simulator")
+    }
+}
+
+#[tonic::async_trait]
+impl ConnectionSource for ScriptedSource {
+    async fn next_state(&mut self) -> Result<Option<bool>, String> {
+        if self.held {
+            // The last state is held forever.
+            std::future::pending::<()>().await;
+        }
+        if let Some(delay) = self.pending_delay.take() {
+            sleep(delay).await;
+        }
+
+        if self.next_step == self.steps.len() {
+            if !self.repeat {
+                return Ok(None);
+            }
+            self.next_step = 0;
+        }
+
+        let step = &self.steps[self.next_step];
+        self.next_step += 1;
+        match step.duration {
+            Some(duration) => self.pending_delay = Some(duration),
+            None => self.held = true,
+        }
+
+        Ok(Some(step.connected))
+    }
+}
+
+/// Reads the connection state from a file that is polled, such as a sysfs GPIO `value` file that
+/// contains `1` or `0`.
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+    poll_interval: Duration,
+    active_low: bool,
+    polled: bool,
+    /// Whether the file was read before.
+    was_read: bool,
+    last_error: Option<String>,
+}
+
+impl FileSource {
+    /// Create a file source.
+    ///
+    /// # Arguments
+    /// * `path` - The file's path.
+    /// * `poll_interval` - How often the file is read.
+    /// * `active_low` - Whether the value is inverted, so that `0` means connected.
+    pub fn new(path: &str, poll_interval: Duration, active_low: bool) -> Self {
+        FileSource {
+            path: PathBuf::from(path),
+            poll_interval,
+            active_low,
+            polled: false,
+            was_read: false,
+            last_error: None,
+        }
+    }
+}
+
+impl fmt::Display for FileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the file '{}'", self.path.display())
+    }
+}
+
+#[tonic::async_trait]
+impl ConnectionSource for FileSource {
+    async fn next_state(&mut self) -> Result<Option<bool>, String> {
+        loop {
+            if self.polled {
+                sleep(self.poll_interval).await;
+            }
+            self.polled = true;
+
+            // The file may not exist yet, for example before a GPIO is exported, so failed reads
+            // are logged once and retried. Once the file was read, a failed read means that the
+            // state is no longer known, for example because the GPIO was unexported.
+            let result = fs::read_to_string(&self.path)
+                .await
+                .map_err(|err| err.to_string())
+                .and_then(|content| parse_state(&content));
+
+            match result {
+                Ok(value) => {
+                    self.was_read = true;
+                    self.last_error = None;
+                    return Ok(Some(value != self.active_low));
+                }
+                Err(err) if self.was_read => return Err(err),
+                Err(err) => {
+                    if self.last_error.as_ref() != Some(&err) {
+                        warn!(
+                            "Failed to read the trailer's connection state from the file '{}': {err}",
+                            self.path.display()
+                        );
+                        self.last_error = Some(err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    /// A source whose readings are sent by the test. It ends when the sender is dropped.
+    struct TestSource(mpsc::UnboundedReceiver<Result<Option<bool>, String>>);
+
+    impl fmt::Display for TestSource {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "the test source")
+        }
+    }
+
+    #[tonic::async_trait]
+    impl ConnectionSource for TestSource {
+        async fn next_state(&mut self) -> Result<Option<bool>, String> {
+            self.0.recv().await.unwrap_or(Ok(None))
+        }
+    }
+
+    fn step(connected: bool, duration_ms: Option<u64>) -> ScriptStep {
+        ScriptStep {
+            connected,
+            duration: duration_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn states_are_parsed() {
+        for value in ["connected", "Coupled", "true", "ON", "1", " 1\n"] {
+            assert_eq!(parse_state(value), Ok(true), "{value}");
+        }
+        for value in ["disconnected", "uncoupled", "FALSE", "off", "0"] {
+            assert_eq!(parse_state(value), Ok(false), "{value}");
+        }
+    }
+
+    #[test]
+    fn unknown_states_are_rejected() {
+        assert_eq!(
+            parse_state("maybe"),
+            Err("'maybe' is not a connection state".to_string())
+        );
+        assert!(parse_state("").is_err());
+        assert!(parse_state("2").is_err());
+    }
+
+    #[test]
+    fn scripts_are_parsed() {
+        let source = ScriptedSource::parse("connected:60000, disconnected:100,on", true).unwrap();
+
+        assert_eq!(
+            source.steps,
+            vec![
+                step(true, Some(60000)),
+                step(false, Some(100)),
+                step(true, None)
+            ]
+        );
+        assert!(source.repeat);
+    }
+
+    #[test]
+    fn steps_with_invalid_durations_are_rejected() {
+        let err = ScriptedSource::parse("connected:abc", false).unwrap_err();
+
+        assert!(
+            err.starts_with("Invalid duration in the script step 'connected:abc'"),
+            "{err}"
+        );
+        assert!(ScriptedSource::parse("connected:-1", false).is_err());
+        assert!(ScriptedSource::parse("connected:", false).is_err());
+    }
+
+    #[test]
+    fn steps_with_invalid_states_are_rejected() {
+        let err = ScriptedSource::parse("connected:100,parked:100", false).unwrap_err();
+
+        assert!(err.starts_with("Invalid script step 'parked:100'"), "{err}");
+    }
+
+    #[test]
+    fn empty_scripts_are_rejected() {
+        assert!(ScriptedSource::parse("", false).is_err());
+        assert!(ScriptedSource::parse("connected,", false).is_err());
+    }
+
+    #[tokio::test]
+    async fn scripts_end_unless_they_repeat() {
+        let mut source = ScriptedSource::parse("connected:0,disconnected:0", false).unwrap();
+
+        assert_eq!(source.next_state().await, Ok(Some(true)));
+        assert_eq!(source.next_state().await, Ok(Some(false)));
+        assert_eq!(source.next_state().await, Ok(None));
+
+        let mut source = ScriptedSource::parse("connected:0,disconnected:1", true).unwrap();
+
+        assert_eq!(source.next_state().await, Ok(Some(true)));
+        assert_eq!(source.next_state().await, Ok(Some(false)));
+        assert_eq!(source.next_state().await, Ok(Some(true)));
+    }
+
+    #[test]
+    fn repeated_scripts_without_durations_are_rejected() {
+        let err = ScriptedSource::parse("connected:0,disconnected:0", true).unwrap_err();
+
+        assert!(err.contains("duration greater than 0"), "{err}");
+        assert!(ScriptedSource::parse("connected:0,disconnected:0", false).is_ok());
+        assert!(ScriptedSource::parse("connected:0,disconnected", true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn files_are_retried_until_they_are_first_read() {
+        let path = std::env::temp_dir().join(format!(
+            "trailer_connected_provider_{}_retried",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut source = FileSource::new(path.to_str().unwrap(), Duration::from_millis(10), false);
+
+        let reading = tokio::spawn(async move { source.next_state().await });
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        assert!(!reading.is_finished());
+
+        std::fs::write(&path, "1").unwrap();
+        assert_eq!(reading.await.unwrap(), Ok(Some(true)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn files_fail_once_they_cannot_be_read_anymore() {
+        let path = std::env::temp_dir().join(format!(
+            "trailer_connected_provider_{}_removed",
+            std::process::id()
+        ));
+        std::fs::write(&path, "0").unwrap();
+        let mut source = FileSource::new(path.to_str().unwrap(), Duration::from_millis(10), true);
+
+        assert_eq!(source.next_state().await, Ok(Some(true)));
+
+        std::fs::write(&path, "unknown").unwrap();
+        assert!(source.next_state().await.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(source.next_state().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn the_state_is_unknown_after_the_source_fails() {
+        let (readings, receiver) = mpsc::unbounded_channel();
+        let shutdown = CancellationToken::new();
+        let mut state = watch_connection(Box::new(TestSource(receiver)), shutdown.clone());
+        assert_eq!(*state.borrow(), None);
+
+        readings.send(Ok(Some(true))).unwrap();
+        state.changed().await.unwrap();
+        assert_eq!(state.borrow().map(|state| state.connected), Some(true));
+
+        readings.send(Err("unplugged".to_string())).unwrap();
+        state.changed().await.unwrap();
+        assert_eq!(*state.borrow(), None);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn the_state_is_kept_until_shutdown_after_the_source_ends() {
+        let (readings, receiver) = mpsc::unbounded_channel();
+        let shutdown = CancellationToken::new();
+        let mut state = watch_connection(Box::new(TestSource(receiver)), shutdown.clone());
+
+        readings.send(Ok(Some(false))).unwrap();
+        state.changed().await.unwrap();
+        drop(readings);
+
+        assert!(timeout(Duration::from_millis(100), state.changed())
+            .await
+            .is_err());
+        assert_eq!(state.borrow().map(|state| state.connected), Some(false));
+
+        shutdown.cancel();
+        assert!(state.changed().await.is_err());
+    }
+}
@@ -2,111 +2,107 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::sync::Arc;
+
+use digital_twin_model::model_registry::ModelRegistry;
 use digital_twin_model::trailer_v1;
 
-use digital_twin_providers_common::constants::chariott::{
-    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
-    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE, INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
-    INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE, INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION,
-};
+use config::TrailerConnectedProviderConfig;
+use connection_source::watch_connection;
+use digital_twin_providers_common::config::ConfigLoader;
 use digital_twin_providers_common::constants::{digital_twin_operation, digital_twin_protocol};
-use digital_twin_providers_common::utils::discover_service_using_chariott;
-use env_logger::{Builder, Target};
-use invehicle_stack_interfaces::invehicle_digital_twin::v1::invehicle_digital_twin_client::InvehicleDigitalTwinClient;
-use invehicle_stack_interfaces::invehicle_digital_twin::v1::{
-    EndpointInfo, EntityAccessInfo, RegisterRequest,
+use digital_twin_providers_common::provider_runtime::{
+    init_logging, EntityDescriptor, ProviderRuntime,
 };
-use log::{debug, info, LevelFilter};
-use smart_trailer_interfaces::digital_twin_get_provider::v1::digital_twin_get_provider_server::DigitalTwinGetProviderServer;
-use std::net::SocketAddr;
-use tokio::signal;
-use tonic::transport::Server;
-use tonic::Status;
+use digital_twin_providers_common::shutdown::shutdown_on_signal;
+use digital_twin_providers_common::tls::configure_clients;
+use invehicle_stack_interfaces::module::managed_subscribe::v1::managed_subscribe_callback_server::ManagedSubscribeCallbackServer;
+use invehicle_stack_interfaces::module::managed_subscribe::v1::FILE_DESCRIPTOR_SET as MANAGED_SUBSCRIBE_FILE_DESCRIPTOR_SET;
+use log::{info, LevelFilter};
+use smart_trailer_interfaces::digital_twin_get_provider::{v1, v2};
 use trailer_connected_provider_impl::TrailerConnectedProviderImpl;
 
+mod can;
+mod config;
+mod connection_source;
 mod trailer_connected_provider_impl;
 
-// Note: These could be provided in configuration files.
-// We ignore the DevSkim warning because this is a sample application. In production, https should be used.
-const CHARIOTT_SERVICE_DISCOVERY_URI: &str = "http://0.0.0.0:50000"; // Devskim: ignore DS137138
-const PROVIDER_AUTHORITY: &str = "0.0.0.0:4020";
-
-/// Register the "is trailer connected" property's endpoint.
-///
-/// # Arguments
-/// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI.
-/// * `provider_uri` - The provider's URI.
-async fn register_entity(
-    invehicle_digital_twin_uri: &str,
-    provider_uri: &str,
-) -> Result<(), Status> {
-    let is_trailer_connected_endpoint_info = EndpointInfo {
-        protocol: digital_twin_protocol::GRPC.to_string(),
-        operations: vec![digital_twin_operation::GET.to_string()],
-        uri: provider_uri.to_string(),
-        context: trailer_v1::trailer::is_trailer_connected::ID.to_string(),
-    };
-    let entity_access_info = EntityAccessInfo {
-        name: trailer_v1::trailer::is_trailer_connected::NAME.to_string(),
-        id: trailer_v1::trailer::is_trailer_connected::ID.to_string(),
-        description: trailer_v1::trailer::is_trailer_connected::DESCRIPTION.to_string(),
-        endpoint_info_list: vec![is_trailer_connected_endpoint_info],
-    };
-
-    let mut client = InvehicleDigitalTwinClient::connect(invehicle_digital_twin_uri.to_string())
-        .await
-        .map_err(|e| Status::internal(e.to_string()))?;
-    let request = tonic::Request::new(RegisterRequest {
-        entity_access_info_list: vec![entity_access_info],
-    });
-    client.register(request).await?;
+const CONFIG_NAME: &str = "trailer_connected_provider";
 
-    Ok(())
-}
+// The provider's registration with Chariott.
+const SERVICE_NAME: &str = "trailer_connected_provider";
+const SERVICE_COMMUNICATION_REFERENCE: &str =
+    "digital_twin_providers/trailer_connected_provider/interfaces/digital_twin_get_provider.proto";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Set up logging.
-    Builder::new()
-        .filter(None, LevelFilter::Debug)
-        .target(Target::Stdout)
-        .init();
+    init_logging(LevelFilter::Debug);
 
     info!("The Provider has started.");
 
-    // We ignore the DevSkim warning because this is a sample application. In production, https should be used.
-    let provider_uri = format!("http://{PROVIDER_AUTHORITY}"); // DevSkim: ignore DS137138 
-    debug!("The Provider URI is {}", &provider_uri);
-
-    // Setup the HTTP server.
-    let addr: SocketAddr = PROVIDER_AUTHORITY.parse()?;
-    let provider_impl = TrailerConnectedProviderImpl::default();
-    let server_future = Server::builder()
-        .add_service(DigitalTwinGetProviderServer::new(provider_impl))
-        .serve(addr);
-    info!("The HTTP server is listening on address '{PROVIDER_AUTHORITY}'");
-
-    // Get the In-vehicle Digital Twin Uri from the service discovery system
-    // This could be enhanced to add retries for robustness
-    let invehicle_digital_twin_uri = discover_service_using_chariott(
-        CHARIOTT_SERVICE_DISCOVERY_URI,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE,
-    )
-    .await?;
+    let config: TrailerConnectedProviderConfig = ConfigLoader::new(CONFIG_NAME).load()?;
+    configure_clients(&config.tls)?;
 
-    debug!("Sending a register request to the In-Vehicle Digital Twin Service URI {invehicle_digital_twin_uri}");
+    // Stop on control-c or SIGTERM.
+    let shutdown = shutdown_on_signal();
 
-    // This could be enhanced to add retries for robustness
-    register_entity(&invehicle_digital_twin_uri, &provider_uri).await?;
-    server_future.await?;
+    // Follow the trailer's connection state.
+    let connection_state = watch_connection(config.connection.source()?, shutdown.clone());
 
-    signal::ctrl_c()
-        .await
-        .expect("Failed to listen for control-c event");
+    // Load the model used to validate the published values.
+    let model_registry = Arc::new(ModelRegistry::with_builtin_models()?);
+
+    let provider = Arc::new(TrailerConnectedProviderImpl::new(
+        connection_state,
+        &config.mqtt.client_id,
+        model_registry,
+        shutdown.clone(),
+    ));
+
+    // The "is trailer connected" property is served with the Get operation, and its changes are
+    // published through the Managed Subscribe module.
+    let is_trailer_connected = EntityDescriptor::new(
+        trailer_v1::trailer::is_trailer_connected::NAME,
+        trailer_v1::trailer::is_trailer_connected::ID,
+        trailer_v1::trailer::is_trailer_connected::DESCRIPTION,
+    )
+    .with_endpoint(
+        digital_twin_protocol::GRPC,
+        &[digital_twin_operation::GET],
+        trailer_v1::trailer::is_trailer_connected::ID,
+    )
+    .with_endpoint(
+        digital_twin_protocol::GRPC,
+        &[digital_twin_operation::MANAGEDSUBSCRIBE],
+        "GetSubscriptionInfo",
+    );
+
+    ProviderRuntime::new(SERVICE_NAME, &config.provider)
+        .with_chariott(&config.chariott)
+        .with_discovery(&config.discovery)
+        .with_tls(&config.tls)
+        .with_retry_policy(config.retry.policy())
+        .with_communication_reference(SERVICE_COMMUNICATION_REFERENCE)
+        .with_entity(is_trailer_connected)
+        .with_service(
+            v1::digital_twin_get_provider_server::DigitalTwinGetProviderServer::from_arc(
+                provider.clone(),
+            ),
+        )
+        .with_service(
+            v2::digital_twin_get_provider_server::DigitalTwinGetProviderServer::from_arc(
+                provider.clone(),
+            ),
+        )
+        .with_service(ManagedSubscribeCallbackServer::from_arc(provider.clone()))
+        .with_file_descriptor_set(v1::FILE_DESCRIPTOR_SET)
+        .with_file_descriptor_set(v2::FILE_DESCRIPTOR_SET)
+        .with_file_descriptor_set(MANAGED_SUBSCRIBE_FILE_DESCRIPTOR_SET)
+        .with_shutdown_token(shutdown)
+        .run()
+        .await?;
+
+    provider.stop_publishing().await;
 
     info!("The Provider has completed.");
 
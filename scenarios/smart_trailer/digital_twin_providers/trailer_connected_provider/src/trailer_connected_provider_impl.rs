@@ -4,24 +4,490 @@
 
 //! Module containing gRPC service implementation based on [`invehicle_stack_interfaces::digital_twin_get_provider.proto`].
 //!
-//! Provides a gRPC endpoint for determining if the trailer is connected.
-use smart_trailer_interfaces::digital_twin_get_provider::v1::digital_twin_get_provider_server::DigitalTwinGetProvider;
-use smart_trailer_interfaces::digital_twin_get_provider::v1::{GetRequest, GetResponse};
+//! Provides gRPC endpoints for determining if the trailer is connected, with versions 1 and 2 of
+//! the Get provider protocol, and publishes the connection state through the Managed Subscribe
+//! module every time that it changes.
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use digital_twin_model::dtmi::Dtmi;
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::property_envelope::PropertyEnvelope;
+use digital_twin_model::trailer_v1;
+use digital_twin_providers_common::errors::ProviderError;
+use digital_twin_providers_common::managed_subscribe::{publish_message, TopicManager};
+use digital_twin_providers_common::property_value::{batch_get, get_bool, get_typed};
+use digital_twin_providers_common::shutdown::CancellationToken;
+use invehicle_stack_interfaces::module::managed_subscribe::v1::managed_subscribe_callback_server::ManagedSubscribeCallback;
+use invehicle_stack_interfaces::module::managed_subscribe::v1::{
+    CallbackPayload, TopicManagementRequest, TopicManagementResponse,
+};
+use log::{debug, info, warn};
+use serde_json::Value;
+use smart_trailer_interfaces::digital_twin_get_provider::{v1, v2};
+use tokio::sync::watch;
 use tonic::{Request, Response, Status};
 
+use crate::connection_source::ConnectionState;
+
 /// Base structure for the Trailer Connected Provider gRPC service.
-#[derive(Default)]
-pub struct TrailerConnectedProviderImpl {}
+#[derive(Debug)]
+pub struct TrailerConnectedProviderImpl {
+    /// The trailer's connection state, or `None` if it is not known.
+    connection_state: watch::Receiver<Option<ConnectionState>>,
+    mqtt_client_id: String,
+    model_registry: Arc<ModelRegistry>,
+    topics: TopicManager,
+}
+
+/// Create the envelope of the "is trailer connected" property.
+///
+/// # Arguments
+/// * `is_trailer_connected` - The trailer's connection state.
+fn is_trailer_connected_envelope(
+    is_trailer_connected: trailer_v1::trailer::is_trailer_connected::TYPE,
+) -> PropertyEnvelope<Value> {
+    PropertyEnvelope::new(
+        trailer_v1::trailer::is_trailer_connected::NAME,
+        trailer_v1::trailer::is_trailer_connected::ID,
+        serde_json::json!(is_trailer_connected),
+    )
+}
+
+/// Create the JSON of the "is trailer connected" property.
+///
+/// # Arguments
+/// * `is_trailer_connected` - The trailer's connection state.
+/// * `model_registry` - The model used to validate the value.
+fn create_property_json(
+    is_trailer_connected: trailer_v1::trailer::is_trailer_connected::TYPE,
+    model_registry: &ModelRegistry,
+) -> Result<String, String> {
+    let envelope = is_trailer_connected_envelope(is_trailer_connected);
+
+    // Only publish values that conform to the model.
+    model_registry.validate_envelope(&envelope)?;
+
+    envelope.encode()
+}
+
+impl TrailerConnectedProviderImpl {
+    /// Create a new TrailerConnectedProviderImpl.
+    ///
+    /// # Arguments
+    /// * `connection_state` - The trailer's connection state, from a connection source.
+    /// * `mqtt_client_id` - The client id used to publish to the MQTT broker.
+    /// * `model_registry` - The model used to validate values before they are published.
+    /// * `shutdown` - Stops all publish tasks when it is cancelled.
+    pub fn new(
+        connection_state: watch::Receiver<Option<ConnectionState>>,
+        mqtt_client_id: &str,
+        model_registry: Arc<ModelRegistry>,
+        shutdown: CancellationToken,
+    ) -> Self {
+        TrailerConnectedProviderImpl {
+            connection_state,
+            mqtt_client_id: mqtt_client_id.to_string(),
+            model_registry,
+            topics: TopicManager::new([trailer_v1::trailer::is_trailer_connected::ID], shutdown),
+        }
+    }
+
+    /// Handles the 'PUBLISH' action from the callback. The current connection state is published
+    /// right away if it is known, and then every time that it changes.
+    ///
+    /// # Arguments
+    /// `payload` - Payload sent with the 'PUBLISH' action.
+    pub fn handle_publish_action(&self, payload: CallbackPayload) -> Result<(), ProviderError> {
+        let entity_id = Dtmi::parse(&payload.entity_id).map_err(ProviderError::invalid_argument)?;
+        let topic = payload.topic;
+        let context = || format!("Publish {entity_id} to {topic}");
+
+        // This should not be empty.
+        let subscription_info = payload.subscription_info.ok_or_else(|| {
+            ProviderError::invalid_argument("Failed to get subscription info")
+                .with_context(context())
+        })?;
+
+        let mut connection_state = self.connection_state.clone();
+        let model_registry = self.model_registry.clone();
+        let mqtt_client_id = self.mqtt_client_id.clone();
+        let entity_id_string = entity_id.to_string();
+        let task_topic = topic.clone();
+
+        self.topics.start_publishing(&entity_id, &topic, |stop| async move {
+            let (entity_id, topic) = (entity_id_string, task_topic);
+            loop {
+                let state = *connection_state.borrow_and_update();
+                if let Some(ConnectionState {
+                    connected: is_trailer_connected,
+                    ..
+                }) = state
+                {
+                    match create_property_json(is_trailer_connected, &model_registry) {
+                        Ok(content) => {
+                            info!("Publish to {topic} for {entity_id} with value {is_trailer_connected}");
+
+                            // A failed publish is not retried, since the next change is published
+                            // anyway.
+                            match publish_message(
+                                &subscription_info.uri,
+                                &mqtt_client_id,
+                                &topic,
+                                &content,
+                            )
+                            .await
+                            {
+                                Ok(()) => debug!("Completed publish to {topic}."),
+                                Err(err) => warn!("Publish failed due to '{err:?}'"),
+                            }
+                        }
+                        Err(err) => warn!("Skipping publish to {topic}: {err}"),
+                    }
+                } else {
+                    // The source has not reported the state yet, or has failed.
+                    info!("The trailer's connection state is unknown, so nothing is published to {topic} until it is known.");
+                }
+
+                // Wait for the next change, unless publishing stops.
+                tokio::select! {
+                    changed = connection_state.changed() => {
+                        if changed.is_err() {
+                            // The state is watched until the provider stops, so this is not
+                            // expected. Keep the topic until it is removed, like the others.
+                            warn!("The connection state is no longer watched, so nothing more is published to {topic}.");
+                            stop.cancelled().await;
+                            break;
+                        }
+                    }
+                    _ = stop.cancelled() => break,
+                }
+            }
+
+            info!("Shutdown thread for {topic}.");
+        })
+    }
+
+    /// Get the value of a property, and when the provider's source produced it.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    fn get_property(
+        &self,
+        entity_id: &str,
+    ) -> Result<(PropertyEnvelope<Value>, SystemTime), ProviderError> {
+        let entity_id = Dtmi::parse(entity_id).map_err(ProviderError::invalid_argument)?;
+        let context = || format!("Get {entity_id}");
+
+        match entity_id.to_string().as_str() {
+            trailer_v1::trailer::is_trailer_connected::ID => {
+                let state = (*self.connection_state.borrow()).ok_or_else(|| {
+                    ProviderError::unavailable("The trailer's connection state is not known")
+                        .with_context(context())
+                })?;
+                Ok((is_trailer_connected_envelope(state.connected), state.since))
+            }
+            _ => Err(
+                ProviderError::not_found("This provider does not provide the entity")
+                    .with_context(context()),
+            ),
+        }
+    }
+
+    /// Get the value of a boolean property, for version 1 of the Get provider protocol.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    fn get_bool(&self, entity_id: &str) -> Result<bool, ProviderError> {
+        let (envelope, _) = self.get_property(entity_id)?;
+
+        get_bool(entity_id, &envelope)
+    }
+
+    /// Get the typed value of a property, for version 2 of the Get provider protocol.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    fn get_typed(&self, entity_id: &str) -> Result<v2::PropertyValue, ProviderError> {
+        let (envelope, source_timestamp) = self.get_property(entity_id)?;
+
+        get_typed(&self.model_registry, entity_id, &envelope, source_timestamp)
+    }
+
+    /// Stop every publish task and wait for them to finish.
+    pub async fn stop_publishing(&self) {
+        self.topics.stop_publishing().await;
+    }
+}
+
+#[tonic::async_trait]
+impl v1::digital_twin_get_provider_server::DigitalTwinGetProvider for TrailerConnectedProviderImpl {
+    /// This function returns the value of the requested property.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity id.
+    async fn get(
+        &self,
+        request: Request<v1::GetRequest>,
+    ) -> Result<Response<v1::GetResponse>, Status> {
+        let property_value = self.get_bool(&request.into_inner().entity_id)?;
+
+        Ok(Response::new(v1::GetResponse { property_value }))
+    }
+
+    /// This function returns the values of several properties, each with its own status.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity ids.
+    async fn batch_get(
+        &self,
+        request: Request<v1::BatchGetRequest>,
+    ) -> Result<Response<v1::BatchGetResponse>, Status> {
+        let results = batch_get(request.into_inner().entity_ids, |entity_id| {
+            self.get_bool(entity_id)
+        });
+
+        Ok(Response::new(v1::BatchGetResponse { results }))
+    }
+}
 
 #[tonic::async_trait]
-impl DigitalTwinGetProvider for TrailerConnectedProviderImpl {
-    /// This function returns the value of "is_trailer_connected" property
-    async fn get(&self, _request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
-        // For now, we assume that if this provider is active, the trailer is connected
-        // To expand this use case, we could simulate the trailer being disconnected as well
-        let get_response = GetResponse {
-            property_value: true,
-        };
-        Ok(Response::new(get_response))
+impl v2::digital_twin_get_provider_server::DigitalTwinGetProvider for TrailerConnectedProviderImpl {
+    /// This function returns the typed value of the requested property.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity id.
+    async fn get(
+        &self,
+        request: Request<v2::GetRequest>,
+    ) -> Result<Response<v2::GetResponse>, Status> {
+        let value = self.get_typed(&request.into_inner().entity_id)?;
+
+        Ok(Response::new(v2::GetResponse { value: Some(value) }))
+    }
+
+    /// This function returns the typed values of several properties, each with its own status.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity ids.
+    async fn batch_get(
+        &self,
+        request: Request<v2::BatchGetRequest>,
+    ) -> Result<Response<v2::BatchGetResponse>, Status> {
+        let results = batch_get(request.into_inner().entity_ids, |entity_id| {
+            self.get_typed(entity_id)
+        });
+
+        Ok(Response::new(v2::BatchGetResponse { results }))
+    }
+}
+
+#[tonic::async_trait]
+impl ManagedSubscribeCallback for TrailerConnectedProviderImpl {
+    /// Callback for a provider, will process a provider action.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the action and associated payload.
+    async fn topic_management_cb(
+        &self,
+        request: Request<TopicManagementRequest>,
+    ) -> Result<Response<TopicManagementResponse>, Status> {
+        let response = self
+            .topics
+            .handle_topic_management(request.into_inner(), |payload| {
+                self.handle_publish_action(payload)
+            })?;
+
+        Ok(Response::new(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tonic::Code;
+
+    use super::*;
+
+    const ID: &str = trailer_v1::trailer::is_trailer_connected::ID;
+    const OTHER_ID: &str = "dtmi:sdv:Trailer:Weight;2";
+    const MALFORMED_ID: &str = "sdv:Trailer:IsTrailerConnected";
+
+    /// Create a provider with a connection state, and the sender that keeps the state watched.
+    fn provider(
+        state: Option<ConnectionState>,
+    ) -> (
+        TrailerConnectedProviderImpl,
+        watch::Sender<Option<ConnectionState>>,
+    ) {
+        let (sender, receiver) = watch::channel(state);
+        let model_registry = Arc::new(ModelRegistry::with_builtin_models().unwrap());
+        let provider = TrailerConnectedProviderImpl::new(
+            receiver,
+            "test",
+            model_registry,
+            CancellationToken::new(),
+        );
+
+        (provider, sender)
+    }
+
+    fn connected() -> ConnectionState {
+        ConnectionState {
+            connected: true,
+            since: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        }
+    }
+
+    #[test]
+    fn the_connection_state_is_returned_by_get() {
+        let (provider, sender) = provider(Some(connected()));
+
+        assert_eq!(provider.get_bool(ID), Ok(true));
+        let value = provider.get_typed(ID).unwrap();
+        assert_eq!(
+            value.value,
+            Some(v2::property_value::Value::BoolValue(true))
+        );
+        let metadata = value.metadata.unwrap();
+        assert_eq!(metadata.model_id, ID);
+        assert_eq!(
+            metadata.source_timestamp.map(|timestamp| timestamp.seconds),
+            Some(1_700_000_000)
+        );
+
+        sender.send_replace(Some(ConnectionState {
+            connected: false,
+            ..connected()
+        }));
+        assert_eq!(provider.get_bool(ID), Ok(false));
+    }
+
+    #[test]
+    fn other_entities_are_not_found() {
+        let (provider, _sender) = provider(Some(connected()));
+
+        assert_eq!(
+            provider.get_bool(OTHER_ID).unwrap_err().code(),
+            Code::NotFound
+        );
+        assert_eq!(
+            provider.get_typed(OTHER_ID).unwrap_err().code(),
+            Code::NotFound
+        );
+    }
+
+    #[test]
+    fn malformed_ids_are_invalid_arguments() {
+        let (provider, _sender) = provider(Some(connected()));
+
+        assert_eq!(
+            provider.get_bool(MALFORMED_ID).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(
+            provider.get_typed(MALFORMED_ID).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn unknown_states_are_unavailable() {
+        let (provider, _sender) = provider(None);
+
+        assert_eq!(provider.get_bool(ID).unwrap_err().code(), Code::Unavailable);
+        assert_eq!(
+            provider.get_typed(ID).unwrap_err().code(),
+            Code::Unavailable
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_get_keeps_the_order_and_reports_each_status() {
+        let (provider, _sender) = provider(Some(connected()));
+        let entity_ids = vec![
+            OTHER_ID.to_string(),
+            ID.to_string(),
+            MALFORMED_ID.to_string(),
+        ];
+
+        let results = v1::digital_twin_get_provider_server::DigitalTwinGetProvider::batch_get(
+            &provider,
+            Request::new(v1::BatchGetRequest {
+                entity_ids: entity_ids.clone(),
+            }),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+        let statuses: Vec<(&str, Code, bool)> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.entity_id.as_str(),
+                    Code::from(result.status_code),
+                    result.property_value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (OTHER_ID, Code::NotFound, false),
+                (ID, Code::Ok, true),
+                (MALFORMED_ID, Code::InvalidArgument, false)
+            ]
+        );
+        assert!(results[0].status_message.contains(OTHER_ID), "{results:?}");
+        assert!(results[1].status_message.is_empty(), "{results:?}");
+
+        let results = v2::digital_twin_get_provider_server::DigitalTwinGetProvider::batch_get(
+            &provider,
+            Request::new(v2::BatchGetRequest { entity_ids }),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+        let statuses: Vec<(&str, Code, bool)> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.entity_id.as_str(),
+                    Code::from(result.status_code),
+                    result.value.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (OTHER_ID, Code::NotFound, false),
+                (ID, Code::Ok, true),
+                (MALFORMED_ID, Code::InvalidArgument, false)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_get_reports_unknown_states_per_entity() {
+        let (provider, _sender) = provider(None);
+
+        let results = v2::digital_twin_get_provider_server::DigitalTwinGetProvider::batch_get(
+            &provider,
+            Request::new(v2::BatchGetRequest {
+                entity_ids: vec![ID.to_string()],
+            }),
+        )
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(Code::from(results[0].status_code), Code::Unavailable);
+        assert_eq!(results[0].value, None);
     }
 }
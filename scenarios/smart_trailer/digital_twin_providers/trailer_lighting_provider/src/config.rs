@@ -0,0 +1,77 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+use digital_twin_providers_common::config::{
+    ChariottConfig, Config, DiscoveryConfig, ProviderConfig, RetryConfig, TlsConfig,
+};
+use serde_derive::{Deserialize, Serialize};
+
+const PROVIDER_AUTHORITY: &str = "0.0.0.0:4040";
+
+const DEFAULT_INITIAL_MODE: &str = "off";
+
+/// The trailer lighting provider's configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrailerLightingProviderConfig {
+    pub chariott: ChariottConfig,
+    pub provider: ProviderConfig,
+    pub retry: RetryConfig,
+    pub discovery: DiscoveryConfig,
+    pub tls: TlsConfig,
+    pub lighting: LightingConfig,
+}
+
+impl Default for TrailerLightingProviderConfig {
+    fn default() -> Self {
+        TrailerLightingProviderConfig {
+            chariott: ChariottConfig::default(),
+            provider: ProviderConfig::new(PROVIDER_AUTHORITY),
+            retry: RetryConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            tls: TlsConfig::default(),
+            lighting: LightingConfig::default(),
+        }
+    }
+}
+
+impl Config for TrailerLightingProviderConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.chariott.validate()?;
+        self.provider.validate()?;
+        self.retry.validate()?;
+        self.discovery.validate()?;
+        self.tls.validate()?;
+        self.lighting.validate()
+    }
+}
+
+/// The configuration of the trailer's lights.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightingConfig {
+    /// The lighting mode when the provider starts, one of the values of the model's lighting mode
+    /// Enum: `off`, `running` or `hazard`.
+    pub initial_mode: String,
+}
+
+impl Default for LightingConfig {
+    fn default() -> Self {
+        LightingConfig {
+            initial_mode: DEFAULT_INITIAL_MODE.to_string(),
+        }
+    }
+}
+
+impl LightingConfig {
+    /// Check that the configuration values are valid. The initial mode is checked against the
+    /// model when the provider starts.
+    fn validate(&self) -> Result<(), String> {
+        if self.initial_mode.is_empty() {
+            return Err("lighting.initial_mode must be set".to_string());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+mod config;
+mod trailer_lighting_provider_impl;
+
+use std::sync::Arc;
+
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::trailer_v2;
+use digital_twin_providers_common::config::ConfigLoader;
+use digital_twin_providers_common::constants::{digital_twin_operation, digital_twin_protocol};
+use digital_twin_providers_common::provider_runtime::{
+    init_logging, EntityDescriptor, ProviderRuntime,
+};
+use digital_twin_providers_common::shutdown::shutdown_on_signal;
+use digital_twin_providers_common::tls::configure_clients;
+use log::{info, LevelFilter};
+use smart_trailer_interfaces::digital_twin_get_provider::{v1, v2};
+use smart_trailer_interfaces::digital_twin_set_provider::v1 as set_v1;
+
+use crate::config::TrailerLightingProviderConfig;
+use crate::trailer_lighting_provider_impl::TrailerLightingProviderImpl;
+
+const CONFIG_NAME: &str = "trailer_lighting_provider";
+
+// The provider's registration with Chariott.
+const SERVICE_NAME: &str = "trailer_lighting_provider";
+const SERVICE_COMMUNICATION_REFERENCE: &str =
+    "interfaces/digital_twin_set_provider/v1/digital_twin_set_provider.proto";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    init_logging(LevelFilter::Debug);
+
+    info!("The Provider has started.");
+
+    let config: TrailerLightingProviderConfig = ConfigLoader::new(CONFIG_NAME).load()?;
+    configure_clients(&config.tls)?;
+
+    // Stop on control-c or SIGTERM.
+    let shutdown = shutdown_on_signal();
+
+    // Load the model used to validate the lighting modes.
+    let model_registry = Arc::new(ModelRegistry::with_builtin_models()?);
+
+    let provider = Arc::new(TrailerLightingProviderImpl::new(
+        &config.lighting.initial_mode,
+        model_registry,
+    )?);
+
+    // The lighting mode is writable, so it is served with both the Get and the Set operation. Like
+    // the other providers, the provider serves both versions of the Get provider protocol.
+    let lighting_mode = EntityDescriptor::new(
+        trailer_v2::trailer::lights::lighting_mode::NAME,
+        trailer_v2::trailer::lights::lighting_mode::ID,
+        trailer_v2::trailer::lights::lighting_mode::DESCRIPTION,
+    )
+    .with_endpoint(
+        digital_twin_protocol::GRPC,
+        &[digital_twin_operation::GET, digital_twin_operation::SET],
+        trailer_v2::trailer::lights::lighting_mode::ID,
+    );
+
+    ProviderRuntime::new(SERVICE_NAME, &config.provider)
+        .with_chariott(&config.chariott)
+        .with_discovery(&config.discovery)
+        .with_tls(&config.tls)
+        .with_retry_policy(config.retry.policy())
+        .with_communication_reference(SERVICE_COMMUNICATION_REFERENCE)
+        .with_entity(lighting_mode)
+        .with_service(
+            v1::digital_twin_get_provider_server::DigitalTwinGetProviderServer::from_arc(
+                provider.clone(),
+            ),
+        )
+        .with_service(
+            v2::digital_twin_get_provider_server::DigitalTwinGetProviderServer::from_arc(
+                provider.clone(),
+            ),
+        )
+        .with_service(
+            set_v1::digital_twin_set_provider_server::DigitalTwinSetProviderServer::from_arc(
+                provider,
+            ),
+        )
+        .with_file_descriptor_set(v1::FILE_DESCRIPTOR_SET)
+        .with_file_descriptor_set(v2::FILE_DESCRIPTOR_SET)
+        .with_file_descriptor_set(set_v1::FILE_DESCRIPTOR_SET)
+        .with_shutdown_token(shutdown)
+        .run()
+        .await?;
+
+    info!("The Provider has completed.");
+
+    Ok(())
+}
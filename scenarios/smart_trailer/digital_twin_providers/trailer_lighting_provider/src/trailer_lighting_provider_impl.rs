@@ -0,0 +1,309 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Module containing gRPC service implementation based on [`smart_trailer_interfaces::digital_twin_set_provider`].
+//!
+//! Provides gRPC endpoints for getting and setting the lighting mode of the trailer's lights,
+//! with the Get provider protocol and the Set provider protocol. Version 1 of the Get provider
+//! protocol can only return booleans, so it rejects the lighting mode with the advice to use
+//! version 2. Set values are validated against the model before they are applied.
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use digital_twin_model::dtmi::Dtmi;
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::property_envelope::PropertyEnvelope;
+use digital_twin_model::trailer_v2;
+use digital_twin_providers_common::errors::ProviderError;
+use digital_twin_providers_common::property_value::{
+    batch_get, from_property_value, get_bool, get_typed,
+};
+use log::info;
+use parking_lot::RwLock;
+use serde_json::Value;
+use smart_trailer_interfaces::digital_twin_get_provider::{v1, v2};
+use smart_trailer_interfaces::digital_twin_set_provider::v1 as set_v1;
+use tonic::{Request, Response, Status};
+
+/// The value of a property, and when it was set.
+#[derive(Clone, Debug)]
+struct PropertyState {
+    envelope: PropertyEnvelope<Value>,
+    since: SystemTime,
+}
+
+/// Base structure for the Trailer Lighting Provider gRPC service.
+#[derive(Debug)]
+pub struct TrailerLightingProviderImpl {
+    lighting_mode: RwLock<PropertyState>,
+    model_registry: Arc<ModelRegistry>,
+}
+
+impl TrailerLightingProviderImpl {
+    /// Create a new TrailerLightingProviderImpl.
+    ///
+    /// # Arguments
+    /// * `initial_mode` - The lighting mode when the provider starts.
+    /// * `model_registry` - The model used to validate the lighting modes.
+    pub fn new(initial_mode: &str, model_registry: Arc<ModelRegistry>) -> Result<Self, String> {
+        let envelope = PropertyEnvelope::new(
+            trailer_v2::trailer::lights::lighting_mode::NAME,
+            trailer_v2::trailer::lights::lighting_mode::ID,
+            serde_json::json!(initial_mode),
+        );
+        model_registry
+            .validate_envelope(&envelope)
+            .map_err(|err| format!("lighting.initial_mode: {err}"))?;
+
+        Ok(TrailerLightingProviderImpl {
+            lighting_mode: RwLock::new(PropertyState {
+                envelope,
+                since: SystemTime::now(),
+            }),
+            model_registry,
+        })
+    }
+
+    /// Get the envelope of a property, and when its value was set.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    fn get_property(&self, entity_id: &str) -> Result<PropertyState, ProviderError> {
+        let entity_id = Dtmi::parse(entity_id).map_err(ProviderError::invalid_argument)?;
+
+        match entity_id.to_string().as_str() {
+            trailer_v2::trailer::lights::lighting_mode::ID => Ok(self.lighting_mode.read().clone()),
+            _ => Err(
+                ProviderError::not_found("This provider does not provide the entity")
+                    .with_context(format!("Get {entity_id}")),
+            ),
+        }
+    }
+
+    /// Get the value of a boolean property, for version 1 of the Get provider protocol.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    fn get_bool(&self, entity_id: &str) -> Result<bool, ProviderError> {
+        let state = self.get_property(entity_id)?;
+
+        get_bool(entity_id, &state.envelope)
+    }
+
+    /// Get the typed value of a property, for version 2 of the Get provider protocol.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    fn get_typed(&self, entity_id: &str) -> Result<v2::PropertyValue, ProviderError> {
+        let state = self.get_property(entity_id)?;
+
+        get_typed(
+            &self.model_registry,
+            entity_id,
+            &state.envelope,
+            state.since,
+        )
+    }
+
+    /// Set the value of a writable property, and return its new typed value.
+    ///
+    /// # Arguments
+    /// * `entity_id` - The property's entity id.
+    /// * `value` - The new value.
+    fn set_typed(
+        &self,
+        entity_id: &str,
+        value: &v2::PropertyValue,
+    ) -> Result<v2::PropertyValue, ProviderError> {
+        let entity_id = Dtmi::parse(entity_id).map_err(ProviderError::invalid_argument)?;
+        let context = || format!("Set {entity_id}");
+
+        match entity_id.to_string().as_str() {
+            trailer_v2::trailer::lights::lighting_mode::ID => {
+                // Only values that conform to the model are applied.
+                let envelope =
+                    from_property_value(&self.model_registry, &entity_id.to_string(), value)
+                        .map_err(|err| {
+                            ProviderError::invalid_argument(err).with_context(context())
+                        })?;
+
+                let mut lighting_mode = self.lighting_mode.write();
+                if lighting_mode.envelope.value != envelope.value {
+                    info!(
+                        "The trailer's lighting mode changed from {} to {}.",
+                        lighting_mode.envelope.value, envelope.value
+                    );
+                    *lighting_mode = PropertyState {
+                        envelope,
+                        since: SystemTime::now(),
+                    };
+                }
+            }
+            _ => {
+                return Err(
+                    ProviderError::not_found("This provider does not provide the entity")
+                        .with_context(context()),
+                )
+            }
+        }
+
+        self.get_typed(&entity_id.to_string())
+    }
+}
+
+#[tonic::async_trait]
+impl v1::digital_twin_get_provider_server::DigitalTwinGetProvider for TrailerLightingProviderImpl {
+    /// This function returns the value of the requested property, if it is a boolean.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity id.
+    async fn get(
+        &self,
+        request: Request<v1::GetRequest>,
+    ) -> Result<Response<v1::GetResponse>, Status> {
+        let property_value = self.get_bool(&request.into_inner().entity_id)?;
+
+        Ok(Response::new(v1::GetResponse { property_value }))
+    }
+
+    /// This function returns the values of several properties, each with its own status.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity ids.
+    async fn batch_get(
+        &self,
+        request: Request<v1::BatchGetRequest>,
+    ) -> Result<Response<v1::BatchGetResponse>, Status> {
+        let results = batch_get(request.into_inner().entity_ids, |entity_id| {
+            self.get_bool(entity_id)
+        });
+
+        Ok(Response::new(v1::BatchGetResponse { results }))
+    }
+}
+
+#[tonic::async_trait]
+impl v2::digital_twin_get_provider_server::DigitalTwinGetProvider for TrailerLightingProviderImpl {
+    /// This function returns the typed value of the requested property.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity id.
+    async fn get(
+        &self,
+        request: Request<v2::GetRequest>,
+    ) -> Result<Response<v2::GetResponse>, Status> {
+        let value = self.get_typed(&request.into_inner().entity_id)?;
+
+        Ok(Response::new(v2::GetResponse { value: Some(value) }))
+    }
+
+    /// This function returns the typed values of several properties, each with its own status.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity ids.
+    async fn batch_get(
+        &self,
+        request: Request<v2::BatchGetRequest>,
+    ) -> Result<Response<v2::BatchGetResponse>, Status> {
+        let results = batch_get(request.into_inner().entity_ids, |entity_id| {
+            self.get_typed(entity_id)
+        });
+
+        Ok(Response::new(v2::BatchGetResponse { results }))
+    }
+}
+
+#[tonic::async_trait]
+impl set_v1::digital_twin_set_provider_server::DigitalTwinSetProvider
+    for TrailerLightingProviderImpl
+{
+    /// This function sets the value of the requested property, and returns its new value.
+    ///
+    /// # Arguments
+    /// * `request` - The request with the entity id and the value.
+    async fn set(
+        &self,
+        request: Request<set_v1::SetRequest>,
+    ) -> Result<Response<set_v1::SetResponse>, Status> {
+        let request = request.into_inner();
+        let value = request.value.ok_or_else(|| {
+            ProviderError::invalid_argument("The request does not have a value")
+                .with_context(format!("Set {}", request.entity_id))
+        })?;
+        let value = self.set_typed(&request.entity_id, &value)?;
+
+        Ok(Response::new(set_v1::SetResponse { value: Some(value) }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    fn provider() -> TrailerLightingProviderImpl {
+        let model_registry = Arc::new(ModelRegistry::with_builtin_models().unwrap());
+        TrailerLightingProviderImpl::new("off", model_registry).unwrap()
+    }
+
+    fn mode(value: &str) -> v2::PropertyValue {
+        v2::PropertyValue {
+            value: Some(v2::property_value::Value::StringValue(value.to_string())),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn initial_modes_must_be_in_the_enum() {
+        let model_registry = Arc::new(ModelRegistry::with_builtin_models().unwrap());
+
+        let err = TrailerLightingProviderImpl::new("disco", model_registry).unwrap_err();
+
+        assert!(err.starts_with("lighting.initial_mode: "), "{err}");
+    }
+
+    #[test]
+    fn the_mode_is_not_a_version_1_value() {
+        let err = provider()
+            .get_bool(trailer_v2::trailer::lights::lighting_mode::ID)
+            .unwrap_err();
+
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn set_modes_are_returned_by_get() {
+        let provider = provider();
+        let id = trailer_v2::trailer::lights::lighting_mode::ID;
+
+        let value = provider.set_typed(id, &mode("hazard")).unwrap();
+
+        assert_eq!(value.value, mode("hazard").value);
+        assert_eq!(provider.get_typed(id).unwrap().value, mode("hazard").value);
+    }
+
+    #[test]
+    fn modes_that_are_not_in_the_enum_are_rejected() {
+        let provider = provider();
+        let id = trailer_v2::trailer::lights::lighting_mode::ID;
+
+        let err = provider.set_typed(id, &mode("disco")).unwrap_err();
+
+        assert_eq!(err.code(), Code::InvalidArgument);
+        assert_eq!(provider.get_typed(id).unwrap().value, mode("off").value);
+    }
+
+    #[test]
+    fn other_entities_are_not_found() {
+        let id = "dtmi:sdv:Trailer:IsTrailerConnected;1";
+
+        assert_eq!(provider().get_typed(id).unwrap_err().code(), Code::NotFound);
+        assert_eq!(provider().get_bool(id).unwrap_err().code(), Code::NotFound);
+        assert_eq!(
+            provider().set_typed(id, &mode("off")).unwrap_err().code(),
+            Code::NotFound
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+use digital_twin_model::units::{semantic_type, Unit};
+use digital_twin_providers_common::config::{
+    ChariottConfig, Config, DiscoveryConfig, MqttConfig, ProviderConfig, RetryConfig, TlsConfig,
+};
+use serde_derive::{Deserialize, Serialize};
+
+const PROVIDER_AUTHORITY: &str = "0.0.0.0:4030";
+const MQTT_CLIENT_ID: &str = "trailer-properties-publisher";
+
+const DEFAULT_MIN_INTERVAL_MS: u64 = 10000; // 10 seconds
+
+/// The trailer properties provider's configuration.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrailerPropertiesProviderConfig {
+    pub chariott: ChariottConfig,
+    pub provider: ProviderConfig,
+    pub retry: RetryConfig,
+    pub discovery: DiscoveryConfig,
+    pub tls: TlsConfig,
+    pub mqtt: MqttConfig,
+    pub publish: PublishConfig,
+    pub simulation: SimulationConfig,
+}
+
+impl Default for TrailerPropertiesProviderConfig {
+    fn default() -> Self {
+        TrailerPropertiesProviderConfig {
+            chariott: ChariottConfig::default(),
+            provider: ProviderConfig::new(PROVIDER_AUTHORITY),
+            retry: RetryConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            tls: TlsConfig::default(),
+            mqtt: MqttConfig::new(MQTT_CLIENT_ID),
+            publish: PublishConfig::default(),
+            simulation: SimulationConfig::default(),
+        }
+    }
+}
+
+impl Config for TrailerPropertiesProviderConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.chariott.validate()?;
+        self.provider.validate()?;
+        self.retry.validate()?;
+        self.discovery.validate()?;
+        self.tls.validate()?;
+        self.mqtt.validate()?;
+        self.publish.validate()?;
+        self.simulation.validate()
+    }
+}
+
+/// The configuration of the published trailer weight.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublishConfig {
+    /// The minimum interval between two updates of the trailer weight.
+    pub min_interval_ms: u64,
+}
+
+impl Default for PublishConfig {
+    fn default() -> Self {
+        PublishConfig {
+            min_interval_ms: DEFAULT_MIN_INTERVAL_MS,
+        }
+    }
+}
+
+impl PublishConfig {
+    /// Check that the configuration values are valid.
+    fn validate(&self) -> Result<(), String> {
+        if self.min_interval_ms == 0 {
+            return Err("publish.min_interval_ms must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// The configuration of the simulated trailer weight.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationConfig {
+    /// The unit of the simulated weights. They are converted to the model's unit when published.
+    pub unit: Unit,
+    /// The lower bound of the trailer weight.
+    pub min_weight: f64,
+    /// The upper bound of the trailer weight.
+    pub max_weight: f64,
+    /// The weight change when a large amount of cargo is loaded.
+    pub loaded_weight: f64,
+    /// The weight change when a smaller delivery is made.
+    pub delivered_weight: f64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            unit: Unit::Kilogram,
+            min_weight: 1000.0,
+            max_weight: 2000.0,
+            loaded_weight: 500.0,
+            delivered_weight: 50.0,
+        }
+    }
+}
+
+impl SimulationConfig {
+    /// Check that the configuration values are valid.
+    fn validate(&self) -> Result<(), String> {
+        if self.unit.semantic_type() != semantic_type::MASS {
+            return Err(format!(
+                "simulation.unit '{}' is not a unit of {}",
+                self.unit.as_str(),
+                semantic_type::MASS
+            ));
+        }
+        if self.min_weight < 0.0 || self.min_weight >= self.max_weight {
+            return Err(format!(
+                "simulation.min_weight ({}) must be at least 0 and less than simulation.max_weight ({})",
+                self.min_weight, self.max_weight
+            ));
+        }
+        if self.loaded_weight <= 0.0 || self.delivered_weight <= 0.0 {
+            return Err(
+                "simulation.loaded_weight and simulation.delivered_weight must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -2,92 +2,74 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+mod config;
 mod trailer_properties_provider_impl;
 
-use std::net::SocketAddr;
+use std::sync::Arc;
 
-use digital_twin_model::trailer_v1;
-use digital_twin_providers_common::constants::chariott::{
-    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
-    INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE, INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
-    INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE, INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION,
-};
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::units::Unit;
+use digital_twin_model::{trailer_v1, trailer_v2};
+use digital_twin_providers_common::config::ConfigLoader;
 use digital_twin_providers_common::constants::{digital_twin_operation, digital_twin_protocol};
-use digital_twin_providers_common::utils::discover_service_using_chariott;
-use env_logger::{Builder, Target};
-use invehicle_stack_interfaces::invehicle_digital_twin::v1::invehicle_digital_twin_client::InvehicleDigitalTwinClient;
-use invehicle_stack_interfaces::invehicle_digital_twin::v1::{
-    EndpointInfo, EntityAccessInfo, RegisterRequest,
+use digital_twin_providers_common::provider_runtime::{
+    init_logging, EntityDescriptor, ProviderRuntime,
 };
+use digital_twin_providers_common::shutdown::shutdown_on_signal;
+use digital_twin_providers_common::tls::configure_clients;
 use invehicle_stack_interfaces::module::managed_subscribe::v1::managed_subscribe_callback_server::ManagedSubscribeCallbackServer;
+use invehicle_stack_interfaces::module::managed_subscribe::v1::FILE_DESCRIPTOR_SET;
 use log::{debug, info, warn, LevelFilter};
-use tokio::signal;
 use tokio::sync::watch;
 use tokio::time::{sleep, Duration};
-use tonic::transport::Server;
-use tonic::Status;
 
+use crate::config::{SimulationConfig, TrailerPropertiesProviderConfig};
 use crate::trailer_properties_provider_impl::TrailerPropertiesProviderImpl;
 
-// Note: These could be provided in configuration files.
-// We ignore the DevSkim warning because this is a sample application. In production, https should be used.
-const CHARIOTT_SERVICE_DISCOVERY_URI: &str = "http://0.0.0.0:50000"; // DevSkim: ignore DS137138 
-const PROVIDER_AUTHORITY: &str = "0.0.0.0:4030";
-
-const DEFAULT_MIN_INTERVAL_MS: u64 = 10000; // 10 seconds
+const CONFIG_NAME: &str = "trailer_properties_provider";
 
-// Weight bounds on the trailer weight in kilograms
-const MIN_TRAILER_WEIGHT: i32 = 1000;
-const MAX_TRAILER_WEIGHT: i32 = 2000;
+// The provider's registration with Chariott.
+const SERVICE_NAME: &str = "trailer_properties_provider";
+const SERVICE_COMMUNICATION_REFERENCE: &str = "https://github.com/eclipse-ibeji/ibeji/blob/0.1.1/interfaces/module/managed_subscribe/v1/managed_subscribe.proto";
 
-/// Register the trailer weight property's endpoint.
+/// Convert a simulated weight to the type and unit of the trailer weight model.
 ///
 /// # Arguments
-/// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI.
-/// * `provider_uri` - The provider's URI.
-async fn register_trailer_weight(
-    invehicle_digital_twin_uri: &str,
-    provider_uri: &str,
-) -> Result<(), Status> {
-    let endpoint_info = EndpointInfo {
-        protocol: digital_twin_protocol::GRPC.to_string(),
-        operations: vec![digital_twin_operation::MANAGEDSUBSCRIBE.to_string()],
-        uri: provider_uri.to_string(),
-        context: "GetSubscriptionInfo".to_string(),
-    };
-
-    let entity_access_info = EntityAccessInfo {
-        name: trailer_v1::trailer::trailer_weight::NAME.to_string(),
-        id: trailer_v1::trailer::trailer_weight::ID.to_string(),
-        description: trailer_v1::trailer::trailer_weight::DESCRIPTION.to_string(),
-        endpoint_info_list: vec![endpoint_info],
-    };
-
-    let mut client = InvehicleDigitalTwinClient::connect(invehicle_digital_twin_uri.to_string())
-        .await
-        .map_err(|e| Status::internal(e.to_string()))?;
-    let request = tonic::Request::new(RegisterRequest {
-        entity_access_info_list: vec![entity_access_info],
-    });
-    client.register(request).await?;
-
-    Ok(())
+/// * `weight` - The simulated weight.
+/// * `simulated_unit` - The unit of the simulated weight.
+fn to_model_weight(
+    weight: f64,
+    simulated_unit: Unit,
+) -> Result<trailer_v2::trailer::weight::TYPE, String> {
+    let unit = trailer_v2::trailer::weight::UNIT
+        .ok_or_else(|| "The trailer weight model does not declare a unit".to_string())?;
+
+    Ok(simulated_unit.convert(weight, unit)?.round() as trailer_v2::trailer::weight::TYPE)
 }
 
 /// Start the trailer weight data stream.
 ///
 /// # Arguments
 /// `min_interval_ms` - minimum frequency for data stream.
-fn start_trailer_weight_data_stream(min_interval_ms: u64) -> watch::Receiver<i32> {
+/// `simulation` - The simulated weights.
+fn start_trailer_weight_data_stream(
+    min_interval_ms: u64,
+    simulation: &SimulationConfig,
+) -> Result<watch::Receiver<trailer_v2::trailer::weight::TYPE>, String> {
     debug!("Starting the Provider's trailer weight data stream.");
-    let mut weight: i32 = MIN_TRAILER_WEIGHT;
+    let min_weight = to_model_weight(simulation.min_weight, simulation.unit)?;
+    let max_weight = to_model_weight(simulation.max_weight, simulation.unit)?;
+    let loaded_weight = to_model_weight(simulation.loaded_weight, simulation.unit)?;
+    let delivered_weight = to_model_weight(simulation.delivered_weight, simulation.unit)?;
+
+    let mut weight = min_weight;
     let (sender, reciever) = watch::channel(weight);
     tokio::spawn(async move {
-        let mut delta = 500;
+        let mut delta = loaded_weight;
         loop {
             debug!(
                 "Recording new value for {} of {weight}",
-                trailer_v1::trailer::trailer_weight::ID
+                trailer_v2::trailer::weight::ID
             );
 
             if let Err(err) = sender.send(weight) {
@@ -98,73 +80,87 @@ fn start_trailer_weight_data_stream(min_interval_ms: u64) -> watch::Receiver<i32
             debug!("Completed the publish request");
 
             // Calculate the new weight.
-            // It bounces back and forth between MIN_TRAILER_WEIGHT and MAX_TRAILER_WEIGHT.
-            // It increases in increments of 500 to simulate a large amount of cargo being loaded
-            // And decreases in increments of 50 to simulate smaller deliveries being made
+            // It bounces back and forth between the minimum and maximum weights.
+            // It increases in increments of the loaded weight to simulate a large amount of cargo being loaded
+            // And decreases in increments of the delivered weight to simulate smaller deliveries being made
             weight += delta;
 
-            if weight >= MAX_TRAILER_WEIGHT {
-                delta = -50;
-            } else if weight <= MIN_TRAILER_WEIGHT {
-                delta = 500;
+            if weight >= max_weight {
+                delta = -delivered_weight;
+            } else if weight <= min_weight {
+                delta = loaded_weight;
             }
 
             sleep(Duration::from_millis(min_interval_ms)).await;
         }
     });
 
-    reciever
+    Ok(reciever)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Setup logging.
-    Builder::new()
-        .filter(None, LevelFilter::Info)
-        .target(Target::Stdout)
-        .init();
+    init_logging(LevelFilter::Info);
 
     info!("The Provider has started.");
 
-    // We ignore the DevSkim warning because this is a sample application. In production, https should be used.
-    let provider_uri = format!("http://{PROVIDER_AUTHORITY}"); // DevSkim: ignore DS137138 
+    let config: TrailerPropertiesProviderConfig = ConfigLoader::new(CONFIG_NAME).load()?;
+    configure_clients(&config.tls)?;
 
-    // Get the In-vehicle Digital Twin Uri from the service discovery system
-    // This could be enhanced to add retries for robustness
-    let invehicle_digital_twin_uri = discover_service_using_chariott(
-        CHARIOTT_SERVICE_DISCOVERY_URI,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_NAMESPACE,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_NAME,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_VERSION,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_KIND,
-        INVEHICLE_DIGITAL_TWIN_SERVICE_COMMUNICATION_REFERENCE,
-    )
-    .await?;
-
-    debug!("The Provider retrieved Chariott's Service Discovery URI.");
+    // Stop on control-c or SIGTERM.
+    let shutdown = shutdown_on_signal();
 
     // Start mock data stream.
-    let data_stream = start_trailer_weight_data_stream(DEFAULT_MIN_INTERVAL_MS);
+    let data_stream =
+        start_trailer_weight_data_stream(config.publish.min_interval_ms, &config.simulation)?;
     debug!("The Provider has started the trailer weight data stream.");
 
-    // Setup provider management cb endpoint.
-    let provider = TrailerPropertiesProviderImpl::new(data_stream, DEFAULT_MIN_INTERVAL_MS);
-
-    // Start service.
-    let addr: SocketAddr = PROVIDER_AUTHORITY.parse()?;
-    let server_future = Server::builder()
-        .add_service(ManagedSubscribeCallbackServer::new(provider))
-        .serve(addr);
-
-    // This could be enhanced with retries for robustness
-    register_trailer_weight(&invehicle_digital_twin_uri, &provider_uri).await?;
-    debug!("The Provider has registered with Ibeji.");
+    // Load the model used to validate the published values.
+    let model_registry = Arc::new(ModelRegistry::with_builtin_models()?);
 
-    server_future.await?;
+    // Setup provider management cb endpoint.
+    let provider = Arc::new(TrailerPropertiesProviderImpl::new(
+        data_stream,
+        config.publish.min_interval_ms,
+        &config.mqtt.client_id,
+        model_registry,
+        shutdown.clone(),
+    ));
+
+    // The v2 trailer weight is also advertised as the v1 trailer weight, so that v1 consumers
+    // can keep using this provider.
+    let trailer_weight = |name, id, description| {
+        EntityDescriptor::new(name, id, description).with_endpoint(
+            digital_twin_protocol::GRPC,
+            &[digital_twin_operation::MANAGEDSUBSCRIBE],
+            "GetSubscriptionInfo",
+        )
+    };
 
-    signal::ctrl_c()
-        .await
-        .expect("Failed to listen for control-c event");
+    ProviderRuntime::new(SERVICE_NAME, &config.provider)
+        .with_entity(trailer_weight(
+            trailer_v2::trailer::weight::NAME,
+            trailer_v2::trailer::weight::ID,
+            trailer_v2::trailer::weight::DESCRIPTION,
+        ))
+        .with_entity(trailer_weight(
+            trailer_v1::trailer::trailer_weight::NAME,
+            trailer_v1::trailer::trailer_weight::ID,
+            trailer_v1::trailer::trailer_weight::DESCRIPTION,
+        ))
+        .with_chariott(&config.chariott)
+        .with_discovery(&config.discovery)
+        .with_tls(&config.tls)
+        .with_retry_policy(config.retry.policy())
+        .with_communication_reference(SERVICE_COMMUNICATION_REFERENCE)
+        .with_service(ManagedSubscribeCallbackServer::from_arc(provider.clone()))
+        .with_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .with_readiness_check("The MQTT broker", provider.broker_connectivity())
+        .with_shutdown_token(shutdown)
+        .run()
+        .await?;
+
+    provider.stop_publishing().await;
 
     info!("The Provider has completed.");
 
@@ -2,7 +2,6 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -11,100 +10,56 @@ use invehicle_stack_interfaces::module::managed_subscribe::v1::{
     CallbackPayload, TopicManagementRequest, TopicManagementResponse,
 };
 
-use digital_twin_model::{trailer_v1, Metadata};
+use digital_twin_model::dtmi::Dtmi;
+use digital_twin_model::model_mapping;
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_model::property_envelope::PropertyEnvelope;
+use digital_twin_model::{trailer_v1, trailer_v2};
+use digital_twin_providers_common::errors::ProviderError;
+use digital_twin_providers_common::managed_subscribe::{publish_message, TopicManager};
+use digital_twin_providers_common::shutdown::CancellationToken;
 use log::{debug, info, warn};
-use paho_mqtt as mqtt;
-use parking_lot::RwLock;
-use serde_derive::{Deserialize, Serialize};
-use strum_macros::{Display, EnumString};
-use tokio::sync::{mpsc, watch};
-use tokio::task::JoinHandle;
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration};
 use tonic::{Request, Response, Status};
 
-const MQTT_CLIENT_ID: &str = "trailer-properties-publisher";
 const FREQUENCY_MS: &str = "frequency_ms";
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TrailerWeightProperty {
-    #[serde(rename = "TrailerWeight")]
-    trailer_weight: trailer_v1::trailer::trailer_weight::TYPE,
-    #[serde(rename = "$metadata")]
-    metadata: Metadata,
-}
-
-/// Actions that are returned from the Pub Sub Service.
-#[derive(Clone, EnumString, Eq, Display, Debug, PartialEq)]
-pub enum ProviderAction {
-    #[strum(serialize = "PUBLISH")]
-    Publish,
-
-    #[strum(serialize = "STOP_PUBLISH")]
-    StopPublish,
-}
-
-#[derive(Debug)]
-pub struct TopicInfo {
-    topic: String,
-    stop_channel: mpsc::Sender<bool>,
-}
-
 #[derive(Debug)]
 pub struct TrailerPropertiesProviderImpl {
-    pub data_stream: watch::Receiver<i32>,
+    pub data_stream: watch::Receiver<trailer_v2::trailer::weight::TYPE>,
     pub min_interval_ms: u64,
-    entity_map: Arc<RwLock<HashMap<String, Vec<TopicInfo>>>>,
-}
-
-/// Create the JSON for the trailer weight property.
-///
-/// # Arguments
-/// * `trailer_weight` - The trailer weight value.
-fn create_property_json(trailer_weight: i32) -> String {
-    let metadata = Metadata {
-        model: trailer_v1::trailer::trailer_weight::ID.to_string(),
-    };
-
-    let property: TrailerWeightProperty = TrailerWeightProperty {
-        trailer_weight,
-        metadata,
-    };
-
-    serde_json::to_string(&property).unwrap()
+    mqtt_client_id: String,
+    model_registry: Arc<ModelRegistry>,
+    topics: TopicManager,
+    broker_connected: Arc<watch::Sender<bool>>,
 }
 
-/// Publish a message to a MQTT broker located.
+/// Create the JSON for the requested version of the trailer weight property.
 ///
 /// # Arguments
-/// `broker_uri` - The MQTT broker's URI.
-/// `topic` - The topic to publish to.
-/// `content` - The message to publish.
-fn publish_message(broker_uri: &str, topic: &str, content: &str) -> Result<(), String> {
-    let create_opts = mqtt::CreateOptionsBuilder::new()
-        .server_uri(broker_uri)
-        .client_id(MQTT_CLIENT_ID.to_string())
-        .finalize();
-
-    let client = mqtt::Client::new(create_opts)
-        .map_err(|err| format!("Failed to create the client due to '{err:?}'"))?;
-
-    let conn_opts = mqtt::ConnectOptionsBuilder::new()
-        .keep_alive_interval(Duration::from_secs(30))
-        .clean_session(true)
-        .finalize();
-
-    let _connect_response = client
-        .connect(conn_opts)
-        .map_err(|err| format!("Failed to connect due to '{err:?}"));
-
-    let msg = mqtt::Message::new(topic, content, mqtt::types::QOS_1);
-    client.publish(msg)
-        .map_err(|err| format!("Failed to publish message due to '{err:?}"))?;
-
-    client.disconnect(None)
-        .map_err(|err| format!("Failed to disconnect from topic '{topic}' on broker {broker_uri} due to {err:?}"))?;
-
-    Ok(())
+/// * `entity_id` - The id of the requested trailer weight entity.
+/// * `trailer_weight` - The trailer weight value in the v2 model.
+/// * `model_registry` - The model used to validate the value.
+fn create_property_json(
+    entity_id: &str,
+    trailer_weight: trailer_v2::trailer::weight::TYPE,
+    model_registry: &ModelRegistry,
+) -> Result<String, String> {
+    let envelope = PropertyEnvelope::new(
+        trailer_v2::trailer::weight::NAME,
+        trailer_v2::trailer::weight::ID,
+        serde_json::json!(trailer_weight),
+    )
+    .with_unit(trailer_v2::trailer::weight::UNIT);
+
+    // Map the value to the requested model version.
+    let envelope = model_mapping::adapt_envelope(envelope, entity_id)?;
+
+    // Only publish values that conform to the model.
+    model_registry.validate_envelope(&envelope)?;
+
+    envelope.encode()
 }
 
 impl TrailerPropertiesProviderImpl {
@@ -113,126 +68,125 @@ impl TrailerPropertiesProviderImpl {
     /// # Arguments
     /// * `data_stream` - Receiver for data stream for entity.
     /// * `min_interval_ms` - The frequency of the data coming over the data stream.
-    pub fn new(data_stream: watch::Receiver<i32>, min_interval_ms: u64) -> Self {
-        // Initialize entity map.
-        let entity_map = HashMap::from([(
-            trailer_v1::trailer::trailer_weight::ID.to_string(),
-            Vec::new(),
-        )]);
+    /// * `mqtt_client_id` - The client id used to publish to the MQTT broker.
+    /// * `model_registry` - The model used to validate values before they are published.
+    /// * `shutdown` - Stops all publish tasks when it is cancelled.
+    pub fn new(
+        data_stream: watch::Receiver<trailer_v2::trailer::weight::TYPE>,
+        min_interval_ms: u64,
+        mqtt_client_id: &str,
+        model_registry: Arc<ModelRegistry>,
+        shutdown: CancellationToken,
+    ) -> Self {
+        // The v2 trailer weight is also served as the v1 trailer weight for v1 consumers.
+        let topics = TopicManager::new(
+            [
+                trailer_v2::trailer::weight::ID,
+                trailer_v1::trailer::trailer_weight::ID,
+            ],
+            shutdown,
+        );
 
         // Create new instance.
         TrailerPropertiesProviderImpl {
             data_stream,
             min_interval_ms,
-            entity_map: Arc::new(RwLock::new(entity_map)),
+            mqtt_client_id: mqtt_client_id.to_string(),
+            model_registry,
+            topics,
+            // No broker is known before the first subscription, so there is nothing to fail.
+            broker_connected: Arc::new(watch::channel(true).0),
         }
     }
 
+    /// Watch whether the last publish to an MQTT broker succeeded.
+    pub fn broker_connectivity(&self) -> watch::Receiver<bool> {
+        self.broker_connected.subscribe()
+    }
+
     /// Handles the 'PUBLISH' action from the callback.
     ///
     /// # Arguments
     /// `payload` - Payload sent with the 'PUBLISH' action.
-    pub fn handle_publish_action(&self, payload: CallbackPayload) -> Result<(), String> {
+    pub fn handle_publish_action(&self, payload: CallbackPayload) -> Result<(), ProviderError> {
         // Get payload information.
+        let entity_id = Dtmi::parse(&payload.entity_id).map_err(ProviderError::invalid_argument)?;
         let topic = payload.topic;
-        let constraints = payload.constraints;
-        let min_interval_ms = self.min_interval_ms;
+        let context = || format!("Publish {entity_id} to {topic}");
 
         // This should not be empty.
-        let subscription_info = payload
-            .subscription_info
-            .ok_or_else(|| "Failed to get subscription info".to_string())?;
-
-        // Create stop publish channel.
-        let (sender, mut reciever) = mpsc::channel(10);
-
-        // Create topic info.
-        let topic_info = TopicInfo {
-            topic: topic.clone(),
-            stop_channel: sender,
-        };
+        let subscription_info = payload.subscription_info.ok_or_else(|| {
+            ProviderError::invalid_argument("Failed to get subscription info")
+                .with_context(context())
+        })?;
 
-        // Record new topic in entity map.
-        {
-            let mut entity_lock = self.entity_map.write();
-            let get_result = entity_lock.get_mut(&payload.entity_id);
-            get_result
-                .ok_or_else(|| "Failed to get entity information".to_string())?
-                .push(topic_info);
+        // Get constraints information.
+        let mut frequency_ms = self.min_interval_ms;
+        for constraint in payload.constraints {
+            if constraint.r#type == *FREQUENCY_MS {
+                frequency_ms = u64::from_str(&constraint.value).map_err(|err| {
+                    ProviderError::invalid_argument(format!(
+                        "Failed to parse frequency constraint '{}' due to '{err}'",
+                        constraint.value
+                    ))
+                    .with_context(context())
+                })?;
+            };
         }
 
         let data_stream = self.data_stream.clone();
-
-        // Start thread for new topic.
-        let _handle: JoinHandle<Result<(), String>> = tokio::spawn(async move {
-            // Get constraints information.
-            let mut frequency_ms = min_interval_ms;
-
-            for constraint in constraints {
-                if constraint.r#type == *FREQUENCY_MS {
-                    frequency_ms = u64::from_str(&constraint.value).map_err(|err| {
-                        format!("Failed to parse frequency constraint due to '{err:?}'")
-                    })?;
-                };
-            }
-
+        let model_registry = self.model_registry.clone();
+        let mqtt_client_id = self.mqtt_client_id.clone();
+        let broker_connected = self.broker_connected.clone();
+        let entity_id_string = entity_id.to_string();
+        let task_topic = topic.clone();
+
+        self.topics.start_publishing(&entity_id, &topic, |stop| async move {
+            let (entity_id, topic) = (entity_id_string, task_topic);
             loop {
-                // See if we need to shutdown.
-                if reciever.try_recv() == Err(mpsc::error::TryRecvError::Disconnected) {
-                    info!("Shutdown thread for {topic}.");
-                    return Ok(());
-                }
-
                 // Get data from stream at the current instant.
                 let data = *data_stream.borrow();
-                let content = create_property_json(data);
-                let broker_uri = subscription_info.uri.clone();
-
-                // Publish message to broker.
-                info!(
-                    "Publish to {topic} for {} with value {data}",
-                    trailer_v1::trailer::trailer_weight::NAME
-                );
 
-                if let Err(err) = publish_message(&broker_uri, &topic, &content) {
-                    warn!("Publish failed due to '{err:?}'");
-                    break;
+                match create_property_json(&entity_id, data, &model_registry) {
+                    Ok(content) => {
+                        // Publish message to broker.
+                        info!("Publish to {topic} for {entity_id} with value {data}");
+
+                        let result =
+                            publish_message(&subscription_info.uri, &mqtt_client_id, &topic, &content)
+                                .await;
+                        broker_connected.send_if_modified(|connected| {
+                            std::mem::replace(connected, result.is_ok()) != result.is_ok()
+                        });
+                        // The broker may come back, so keep the topic and publish again on
+                        // the next tick. The health service reports the provider as not serving
+                        // in the meantime.
+                        match result {
+                            Ok(()) => debug!("Completed publish to {topic}."),
+                            Err(err) => warn!(
+                                "Publish to {topic} failed due to '{err:?}', retrying in {frequency_ms} ms"
+                            ),
+                        }
+                    }
+                    Err(err) => warn!("Skipping publish to {topic}: {err}"),
                 }
 
-                debug!("Completed publish to {topic}.");
-
-                // Sleep for requested amount of time.
-                sleep(Duration::from_millis(frequency_ms)).await;
+                // Sleep for requested amount of time, unless publishing stops.
+                tokio::select! {
+                    _ = sleep(Duration::from_millis(frequency_ms)) => {}
+                    _ = stop.cancelled() => break,
+                }
             }
-            Ok(())
-        });
-        Ok(())
-    }
-
-    /// Handles the 'STOP_PUBLISH' action from the callback.
-    ///
-    /// # Arguments
-    /// `payload` - Payload sent with the 'STOP_PUBLISH' action.
-    pub fn handle_stop_publish_action(&self, payload: CallbackPayload) -> Result<(), String> {
-        let topic_info: TopicInfo;
-
-        let mut entity_lock = self.entity_map.write();
-        let get_result = entity_lock.get_mut(&payload.entity_id);
 
-        let topics = get_result.ok_or_else(|| "Failed to get entity information".to_string())?;
-
-        // Check to see if topic exists.
-        if let Some(index) = topics.iter_mut().position(|t| t.topic == payload.topic) {
-            // Remove topic.
-            topic_info = topics.swap_remove(index);
+            info!("Shutdown thread for {topic}.");
+        })
+    }
 
-            // Stop publishing to removed topic.
-            drop(topic_info.stop_channel);
-            Ok(())
-        } else {
-            warn!("No topic found matching {}", payload.topic);
-            Err(format!("No topic found matching {}", payload.topic))
-        }
+    /// Stop every publish task and wait for them to finish. Each message is published with its
+    /// own MQTT connection, which is disconnected after the message, so no connection is left
+    /// open once the tasks have finished.
+    pub async fn stop_publishing(&self) {
+        self.topics.stop_publishing().await;
     }
 }
 
@@ -246,25 +200,12 @@ impl ManagedSubscribeCallback for TrailerPropertiesProviderImpl {
         &self,
         request: Request<TopicManagementRequest>,
     ) -> Result<Response<TopicManagementResponse>, Status> {
-        let inner = request.into_inner();
-        let action = inner.action;
-        let payload = inner
-            .payload
-            .ok_or_else(|| Status::invalid_argument("Failed to get payload".to_string()))?;
-
-        let provider_action = ProviderAction::from_str(&action).map_err(|err| {
-            Status::invalid_argument(format!("Failed to parse action due to '{err:?}'"))
-        })?;
-
-        match provider_action {
-            ProviderAction::Publish => {
-                Self::handle_publish_action(self, payload).map_err(Status::internal)?
-            }
-            ProviderAction::StopPublish => {
-                Self::handle_stop_publish_action(self, payload).map_err(Status::internal)?
-            }
-        }
+        let response = self
+            .topics
+            .handle_topic_management(request.into_inner(), |payload| {
+                self.handle_publish_action(payload)
+            })?;
 
-        Ok(Response::new(TopicManagementResponse {}))
+        Ok(Response::new(response))
     }
 }
@@ -2,8 +2,15 @@
 // Licensed under the Apache License, Version 2.0.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The file descriptor sets are served by the gRPC reflection service.
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
     tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("invehicle_digital_twin_descriptor.bin"))
         .message_attribute(
             "EndpointInfo",
             "#[derive(serde::Deserialize, serde::Serialize)]",
@@ -17,6 +24,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &["../interfaces/invehicle_digital_twin/v1/"],
         )?;
     tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("managed_subscribe_descriptor.bin"))
         .message_attribute(
             "Constraint",
             "#[derive(serde::Deserialize, serde::Serialize)]",
@@ -33,10 +41,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &["../interfaces/module/managed_subscribe/v1/managed_subscribe.proto"],
             &["../interfaces/module/managed_subscribe/v1/"],
         )?;
-    tonic_build::configure().compile(
-        &["../interfaces/service_discovery/v1/service_registry.proto"],
-        &["../interfaces/service_discovery/v1/"],
-    )?;
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("service_registry_descriptor.bin"))
+        .compile(
+            &["../interfaces/service_discovery/v1/service_registry.proto"],
+            &["../interfaces/service_discovery/v1/"],
+        )?;
 
     Ok(())
 }
@@ -5,6 +5,9 @@
 pub mod invehicle_digital_twin {
     pub mod v1 {
         tonic::include_proto!("invehicle_digital_twin");
+
+        pub const FILE_DESCRIPTOR_SET: &[u8] =
+            tonic::include_file_descriptor_set!("invehicle_digital_twin_descriptor");
     }
 }
 
@@ -12,6 +15,9 @@ pub mod module {
     pub mod managed_subscribe {
         pub mod v1 {
             tonic::include_proto!("managed_subscribe");
+
+            pub const FILE_DESCRIPTOR_SET: &[u8] =
+                tonic::include_file_descriptor_set!("managed_subscribe_descriptor");
         }
     }
 }
@@ -20,6 +26,9 @@ pub mod service_discovery {
     pub mod core {
         pub mod v1 {
             tonic::include_proto!("service_registry");
+
+            pub const FILE_DESCRIPTOR_SET: &[u8] =
+                tonic::include_file_descriptor_set!("service_registry_descriptor");
         }
     }
 }
@@ -0,0 +1,46 @@
+#!/bin/bash
+
+# Copyright (c) Microsoft Corporation.
+# Licensed under the Apache License, Version 2.0.
+# SPDX-License-Identifier: Apache-2.0
+
+set -eu
+
+# Generates a self-signed CA and a certificate signed by it, for testing TLS and mutual TLS
+# locally. The certificate is valid for localhost, 127.0.0.1 and 0.0.0.0, and can be used by the
+# servers as well as by the clients.
+#
+# Usage: generate_test_certificates.sh [output directory] [validity in days]
+#
+# The output directory gets ca.pem and ca.key, the CA's certificate and key, and provider.pem and
+# provider.key, the certificate and key to configure as tls.cert_path and tls.key_path.
+
+# Check if openssl is installed
+if ! command -v openssl &> /dev/null
+then
+  echo "openssl could not be found; please install it and run again"
+  exit 1
+fi
+
+OUT_DIR="${1:-.}"
+DAYS="${2:-30}"
+
+mkdir -p "$OUT_DIR"
+cd "$OUT_DIR"
+
+# The self-signed CA
+openssl req -x509 -newkey rsa:2048 -nodes -keyout ca.key -out ca.pem -days "$DAYS" \
+  -subj "/CN=smart-trailer-test-ca" \
+  -addext "basicConstraints=critical,CA:TRUE" \
+  -addext "keyUsage=critical,keyCertSign,cRLSign" 2> /dev/null
+
+# The certificate of the providers and their clients, signed by the CA
+openssl req -newkey rsa:2048 -nodes -keyout provider.key -out provider.csr \
+  -subj "/CN=provider" 2> /dev/null
+printf "subjectAltName=DNS:localhost,IP:127.0.0.1,IP:0.0.0.0\nextendedKeyUsage=serverAuth,clientAuth\n" > provider.ext
+openssl x509 -req -in provider.csr -CA ca.pem -CAkey ca.key -CAcreateserial -out provider.pem \
+  -days "$DAYS" -extfile provider.ext 2> /dev/null
+
+rm -f provider.csr provider.ext ca.srl
+
+echo "Generated ca.pem, ca.key, provider.pem and provider.key in $(pwd)"
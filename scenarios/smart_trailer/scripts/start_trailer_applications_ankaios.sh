@@ -53,26 +53,21 @@ METHOD="FindById"
 # The request body: The IsTrailerConnected signal
 BODY='{"id":"dtmi:sdv:Trailer:IsTrailerConnected;1"}'
 
-# Curl the proto file from the repository and save it locally
-PROTO_URL="https://github.com/eclipse-ibeji/ibeji/releases/download/0.1.1/invehicle_digital_twin.proto"
-PROTO_PATH="${SCRIPT_DIR}"
+# The Ibeji proto is part of this repository, so no download is needed. The devcontainers mount
+# scenarios/smart_trailer/interfaces next to the directory of this script.
+PROTO_PATH="${SCRIPT_DIR}/../interfaces/invehicle_digital_twin/v1"
 PROTO="invehicle_digital_twin.proto"
-curl -sL "${PROTO_URL}" -o "${PROTO_PATH}/${PROTO}"
 
-if [ $? -ne 0 ]; then
-  echo "Failed to download the invehicle-digital twin proto file."
+# Check if the proto can be found
+if [ ! -f "${PROTO_PATH}/${PROTO}" ]
+then
+  echo "${PROTO} could not be found in ${PROTO_PATH}; please mount scenarios/smart_trailer/interfaces at $(dirname "${SCRIPT_DIR}")/interfaces and run again"
   exit 1
 fi
 
 EXPECTED_PROTOCOL="grpc"
 EXPECTED_OPERATION="get"
 
-trap 'cleanup_routine' EXIT SIGTERM SIGQUIT SIGKILL
-
-cleanup_routine() {
-  rm "${PROTO_PATH}/${PROTO}"
-}
-
 log_info() {
   echo -e "[$(date -u +"%Y-%m-%dT%H:%M:%SZ") INFO]    $1"
 }
@@ -121,12 +116,14 @@ do
 
         # Start up the other workloads using podman
         CFG_PROVIDER=$'image: ghcr.io/eclipse-sdv-blueprints/software-orchestration/invehicle-stack/trailer-properties-provider:0.1.0\ncommandOptions: ["--network", "host", "--name", "trailer_properties_provider"]'
+        CFG_LIGHTING_PROVIDER=$'image: ghcr.io/eclipse-sdv-blueprints/software-orchestration/invehicle-stack/trailer-lighting-provider:0.1.0\ncommandOptions: ["--network", "host", "--name", "trailer_lighting_provider"]'
         CFG_APP=$'image: ghcr.io/eclipse-sdv-blueprints/software-orchestration/invehicle-stack/smart-trailer-application:0.1.0\ncommandOptions: ["--network", "host", "--name", "smart_trailer_application"]'
 
         ank run workload trailer_properties_provider --runtime podman --config "$CFG_PROVIDER" --agent agent_A
+        ank run workload trailer_lighting_provider --runtime podman --config "$CFG_LIGHTING_PROVIDER" --agent agent_A
         ank run workload smart_trailer_application --runtime podman --config "$CFG_APP" --agent agent_A
 
-        log_debug "Called Ankaios to start the Trailer Properties Digital Twin Provider and Smart Trailer Application"
+        log_debug "Called Ankaios to start the Trailer Properties and Trailer Lighting Digital Twin Providers and Smart Trailer Application"
         log_debug "Check Ankaios status with 'ank get workloads'"
         exit 0
       fi
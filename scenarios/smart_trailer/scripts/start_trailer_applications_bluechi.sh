@@ -37,11 +37,17 @@ METHOD="FindById"
 # The request body: The IsTrailerConnected signal
 BODY='{"id":"dtmi:sdv:Trailer:IsTrailerConnected;1"}'
 
-# Curl the proto file from the repository and save it locally
-PROTO_URL="https://github.com/eclipse-ibeji/ibeji/releases/download/0.1.1/invehicle_digital_twin.proto"
-PROTO_PATH="${SCRIPT_DIR}"
+# The Ibeji proto is part of this repository, so no download is needed. The devcontainers mount
+# scenarios/smart_trailer/interfaces next to the directory of this script.
+PROTO_PATH="${SCRIPT_DIR}/../interfaces/invehicle_digital_twin/v1"
 PROTO="invehicle_digital_twin.proto"
-curl -L "${PROTO_URL}" -o "${PROTO_PATH}/${PROTO}"
+
+# Check if the proto can be found
+if [ ! -f "${PROTO_PATH}/${PROTO}" ]
+then
+  echo "${PROTO} could not be found in ${PROTO_PATH}; please mount scenarios/smart_trailer/interfaces at $(dirname "${SCRIPT_DIR}")/interfaces and run again"
+  exit 1
+fi
 
 EXPECTED_PROTOCOL="grpc"
 EXPECTED_OPERATION="get"
@@ -85,16 +91,15 @@ do
         # See /etc/containers/systemd for these files
         # Start up the other workloads using systemctl
         systemctl start trailer_properties_provider
+        systemctl start trailer_lighting_provider
         systemctl start smart_trailer_application
 
-        echo "Called systemctl to start the Trailer Properties Digital Twin Provider and Smart Trailer Application"
-        echo "Check systemctl status with 'systemctl status trailer_properties_provider' and 'systemctl status smart_trailer_application' for status"
-        rm "${PROTO_PATH}/${PROTO}"
+        echo "Called systemctl to start the Trailer Properties and Trailer Lighting Digital Twin Providers and Smart Trailer Application"
+        echo "Check systemctl status with 'systemctl status trailer_properties_provider', 'systemctl status trailer_lighting_provider' and 'systemctl status smart_trailer_application' for status"
         exit 0
       fi
     done
   fi
 done
 # We didn't find an endpoint which satisfied our conditions
-rm "${PROTO_PATH}/${PROTO}"
 exit 1
\ No newline at end of file
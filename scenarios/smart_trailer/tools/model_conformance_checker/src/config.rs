@@ -0,0 +1,53 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+use digital_twin_providers_common::config::{
+    validate_uri, ChariottConfig, Config, RetryConfig, TlsConfig,
+};
+use serde_derive::{Deserialize, Serialize};
+
+/// The model conformance checker's configuration.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelConformanceCheckerConfig {
+    pub chariott: ChariottConfig,
+    pub checker: CheckerConfig,
+    pub retry: RetryConfig,
+    pub tls: TlsConfig,
+}
+
+impl Config for ModelConformanceCheckerConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.chariott.validate()?;
+        self.checker.validate()?;
+        self.retry.validate()?;
+        self.tls.validate()
+    }
+}
+
+/// What to check, and where to find the registrations.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckerConfig {
+    /// A directory of DTDL files to load the model from, instead of the built-in model.
+    pub dtdl_dir: Option<String>,
+    /// A file of recorded registrations to check, instead of the live registrations.
+    pub registrations: Option<String>,
+    /// The In-Vehicle Digital Twin Service's URI. It is discovered with Chariott if not set.
+    pub ibeji_uri: Option<String>,
+    /// The ids of the entities that must be registered.
+    pub expect: Vec<String>,
+    /// The ids of the interfaces whose value entities must all be registered.
+    pub expect_interface: Vec<String>,
+}
+
+impl CheckerConfig {
+    /// Check that the configuration values are valid.
+    fn validate(&self) -> Result<(), String> {
+        match &self.ibeji_uri {
+            Some(uri) => validate_uri("checker.ibeji_uri", uri),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,779 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks that the entities that digital twin providers register with the In-Vehicle Digital
+//! Twin Service conform to the DTDL model.
+//!
+//! Registrations can either be recorded in a JSON file, or looked up in a running In-Vehicle
+//! Digital Twin Service by entity id.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use digital_twin_model::dtdl::element_type;
+use digital_twin_model::dtmi::Dtmi;
+use digital_twin_model::model_registry::{Entity, ModelRegistry};
+use digital_twin_providers_common::constants::{digital_twin_operation, digital_twin_protocol};
+use digital_twin_providers_common::tls::connect;
+use digital_twin_providers_common::utils::{connection_error, RetryPolicy};
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::invehicle_digital_twin_client::InvehicleDigitalTwinClient;
+use invehicle_stack_interfaces::invehicle_digital_twin::v1::{EntityAccessInfo, FindByIdRequest};
+use log::debug;
+use serde_derive::Deserialize;
+use tonic::Code;
+
+/// All digital twin operations.
+const OPERATIONS: &[&str] = &[
+    digital_twin_operation::GET,
+    digital_twin_operation::SET,
+    digital_twin_operation::SUBSCRIBE,
+    digital_twin_operation::UNSUBSCRIBE,
+    digital_twin_operation::INVOKE,
+    digital_twin_operation::STREAM,
+    digital_twin_operation::MANAGEDSUBSCRIBE,
+];
+
+/// All digital twin protocols.
+const PROTOCOLS: &[&str] = &[digital_twin_protocol::GRPC, digital_twin_protocol::MQTT];
+
+/// How serious a finding is.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A way in which a registration does not conform to the model.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Finding {
+    /// The registered id is not a valid DTMI.
+    InvalidId { id: String, reason: String },
+    /// The registered id is not an entity of the model.
+    UnknownEntity { id: String },
+    /// The registered id is a Relationship or Component, which does not have a value.
+    NotAValueEntity { id: String, element_type: String },
+    /// The registered name differs from the model's.
+    NameMismatch {
+        id: String,
+        expected: String,
+        actual: String,
+    },
+    /// The registered description differs from the model's.
+    DescriptionMismatch {
+        id: String,
+        expected: String,
+        actual: String,
+    },
+    /// The entity was registered more than once.
+    DuplicateRegistration { id: String },
+    /// The registration does not have any endpoints.
+    NoEndpoints { id: String },
+    /// An endpoint does not have a URI.
+    MissingUri { id: String },
+    /// An endpoint uses an unknown protocol.
+    UnknownProtocol { id: String, protocol: String },
+    /// An endpoint offers an unknown operation.
+    UnknownOperation { id: String, operation: String },
+    /// An endpoint offers an operation that the model does not allow for the entity.
+    OperationNotAllowed {
+        id: String,
+        operation: String,
+        reason: String,
+    },
+    /// No provider registered an entity that is expected to have one.
+    MissingProvider { id: String },
+}
+
+impl Finding {
+    /// How serious this finding is.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Finding::DescriptionMismatch { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// The id of the entity that this finding is about.
+    pub fn entity_id(&self) -> &str {
+        match self {
+            Finding::InvalidId { id, .. }
+            | Finding::UnknownEntity { id }
+            | Finding::NotAValueEntity { id, .. }
+            | Finding::NameMismatch { id, .. }
+            | Finding::DescriptionMismatch { id, .. }
+            | Finding::DuplicateRegistration { id }
+            | Finding::NoEndpoints { id }
+            | Finding::MissingUri { id }
+            | Finding::UnknownProtocol { id, .. }
+            | Finding::UnknownOperation { id, .. }
+            | Finding::OperationNotAllowed { id, .. }
+            | Finding::MissingProvider { id } => id,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::InvalidId { id, reason } => write!(f, "'{id}' is not a valid id: {reason}"),
+            Finding::UnknownEntity { id } => write!(f, "'{id}' is not an entity of the model"),
+            Finding::NotAValueEntity { id, element_type } => write!(
+                f,
+                "'{id}' is a {element_type}, which does not have a value to provide"
+            ),
+            Finding::NameMismatch {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "'{id}' is registered with the name '{actual}', but the model names it '{expected}'"
+            ),
+            Finding::DescriptionMismatch {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "'{id}' is registered with the description '{actual}', but the model describes it as '{expected}'"
+            ),
+            Finding::DuplicateRegistration { id } => {
+                write!(f, "'{id}' is registered more than once")
+            }
+            Finding::NoEndpoints { id } => write!(f, "'{id}' is registered without endpoints"),
+            Finding::MissingUri { id } => write!(f, "'{id}' has an endpoint without a URI"),
+            Finding::UnknownProtocol { id, protocol } => {
+                write!(f, "'{id}' has an endpoint with the unknown protocol '{protocol}'")
+            }
+            Finding::UnknownOperation { id, operation } => {
+                write!(f, "'{id}' has an endpoint with the unknown operation '{operation}'")
+            }
+            Finding::OperationNotAllowed {
+                id,
+                operation,
+                reason,
+            } => write!(f, "'{id}' does not allow the '{operation}' operation: {reason}"),
+            Finding::MissingProvider { id } => write!(f, "No provider registered '{id}'"),
+        }
+    }
+}
+
+/// The findings of a conformance check.
+#[derive(Clone, Debug, Default)]
+pub struct ConformanceReport {
+    /// The number of registrations that were checked.
+    pub checked: usize,
+    /// The findings, in the order of the registrations.
+    pub findings: Vec<Finding>,
+}
+
+impl ConformanceReport {
+    /// Does the report contain any errors?
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity() == Severity::Error)
+    }
+
+    /// Iterate over the findings with the provided severity.
+    ///
+    /// # Arguments
+    /// * `severity` - The severity.
+    pub fn findings_with(&self, severity: Severity) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(move |finding| finding.severity() == severity)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}: {finding}", finding.severity())?;
+        }
+
+        write!(
+            f,
+            "Checked {} registrations: {} errors, {} warnings",
+            self.checked,
+            self.findings_with(Severity::Error).count(),
+            self.findings_with(Severity::Warning).count()
+        )
+    }
+}
+
+/// Checks registrations against the entities of a model.
+pub struct ConformanceChecker<'a> {
+    registry: &'a ModelRegistry,
+}
+
+impl<'a> ConformanceChecker<'a> {
+    /// Create a checker for a model.
+    ///
+    /// # Arguments
+    /// * `registry` - The model.
+    pub fn new(registry: &'a ModelRegistry) -> Self {
+        ConformanceChecker { registry }
+    }
+
+    /// Check registrations, and that every expected entity was registered.
+    ///
+    /// # Arguments
+    /// * `registrations` - The registered entities.
+    /// * `expected_ids` - The ids of the entities that must have a provider.
+    pub fn check_registrations(
+        &self,
+        registrations: &[EntityAccessInfo],
+        expected_ids: &[String],
+    ) -> ConformanceReport {
+        let mut report = ConformanceReport {
+            checked: registrations.len(),
+            findings: Vec::new(),
+        };
+
+        let mut registered = HashSet::new();
+        for entity_access_info in registrations {
+            if !registered.insert(entity_access_info.id.as_str()) {
+                report.findings.push(Finding::DuplicateRegistration {
+                    id: entity_access_info.id.clone(),
+                });
+                continue;
+            }
+
+            report
+                .findings
+                .extend(self.check_entity_access_info(entity_access_info));
+        }
+
+        report.findings.extend(
+            expected_ids
+                .iter()
+                .filter(|id| !registered.contains(id.as_str()))
+                .map(|id| Finding::MissingProvider { id: id.clone() }),
+        );
+
+        report
+    }
+
+    /// Check a single registered entity.
+    ///
+    /// # Arguments
+    /// * `entity_access_info` - The registered entity.
+    pub fn check_entity_access_info(&self, entity_access_info: &EntityAccessInfo) -> Vec<Finding> {
+        let id = entity_access_info.id.clone();
+
+        if let Err(reason) = Dtmi::parse(&id) {
+            return vec![Finding::InvalidId { id, reason }];
+        }
+
+        let entity = match self.registry.get_entity(&id) {
+            Some(entity) => entity,
+            None => return vec![Finding::UnknownEntity { id }],
+        };
+
+        if !is_value_entity(entity) {
+            return vec![Finding::NotAValueEntity {
+                id,
+                element_type: entity.content.types.first().cloned().unwrap_or_default(),
+            }];
+        }
+
+        let mut findings = Vec::new();
+
+        if entity_access_info.name != entity.content.name {
+            findings.push(Finding::NameMismatch {
+                id: id.clone(),
+                expected: entity.content.name.clone(),
+                actual: entity_access_info.name.clone(),
+            });
+        }
+
+        if entity_access_info.description != entity.content.description_text() {
+            findings.push(Finding::DescriptionMismatch {
+                id: id.clone(),
+                expected: entity.content.description_text().to_string(),
+                actual: entity_access_info.description.clone(),
+            });
+        }
+
+        if entity_access_info.endpoint_info_list.is_empty() {
+            findings.push(Finding::NoEndpoints { id: id.clone() });
+        }
+
+        for endpoint_info in &entity_access_info.endpoint_info_list {
+            if endpoint_info.uri.is_empty() {
+                findings.push(Finding::MissingUri { id: id.clone() });
+            }
+
+            if !PROTOCOLS.contains(&endpoint_info.protocol.as_str()) {
+                findings.push(Finding::UnknownProtocol {
+                    id: id.clone(),
+                    protocol: endpoint_info.protocol.clone(),
+                });
+            }
+
+            for operation in &endpoint_info.operations {
+                if !OPERATIONS.contains(&operation.as_str()) {
+                    findings.push(Finding::UnknownOperation {
+                        id: id.clone(),
+                        operation: operation.clone(),
+                    });
+                } else if let Err(reason) = check_operation(entity, operation) {
+                    findings.push(Finding::OperationNotAllowed {
+                        id: id.clone(),
+                        operation: operation.clone(),
+                        reason,
+                    });
+                }
+            }
+        }
+
+        findings
+    }
+}
+
+/// Does an entity have a value that a provider can provide?
+///
+/// # Arguments
+/// * `entity` - The entity.
+fn is_value_entity(entity: &Entity) -> bool {
+    entity.content.is(element_type::PROPERTY)
+        || entity.content.is(element_type::TELEMETRY)
+        || entity.content.is(element_type::COMMAND)
+}
+
+/// Check that the model allows an operation for an entity.
+///
+/// # Arguments
+/// * `entity` - The entity.
+/// * `operation` - The operation.
+fn check_operation(entity: &Entity, operation: &str) -> Result<(), String> {
+    let content = &entity.content;
+
+    if content.is(element_type::COMMAND) {
+        return if operation == digital_twin_operation::INVOKE {
+            Ok(())
+        } else {
+            Err("a Command can only be invoked".to_string())
+        };
+    }
+
+    match operation {
+        digital_twin_operation::INVOKE => Err("only a Command can be invoked".to_string()),
+        digital_twin_operation::GET | digital_twin_operation::SET
+            if content.is(element_type::TELEMETRY) =>
+        {
+            Err("Telemetry can only be subscribed to or streamed".to_string())
+        }
+        digital_twin_operation::SET if !content.writable => {
+            Err("the Property is not writable".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Get the ids of the entities that are part of an Interface and have a value, including the
+/// entities inside its Components.
+///
+/// # Arguments
+/// * `registry` - The model.
+/// * `interface_id` - The Interface's DTMI.
+pub fn value_entity_ids(
+    registry: &ModelRegistry,
+    interface_id: &str,
+) -> Result<Vec<String>, String> {
+    if registry.get_interface(interface_id).is_none() {
+        return Err(format!("Unknown Interface '{interface_id}'"));
+    }
+
+    let mut ids: Vec<String> = registry
+        .entities()
+        .filter(|entity| entity.root_interface_id == interface_id && is_value_entity(entity))
+        .map(|entity| entity.id.clone())
+        .collect();
+    ids.sort();
+
+    Ok(ids)
+}
+
+/// A `RegisterRequest` as recorded in JSON.
+#[derive(Deserialize)]
+struct RecordedRegisterRequest {
+    entity_access_info_list: Vec<EntityAccessInfo>,
+}
+
+/// The contents of a file of recorded registrations.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecordedRegistrations {
+    Request(RecordedRegisterRequest),
+    Requests(Vec<RecordedRegisterRequest>),
+    EntityAccessInfoList(Vec<EntityAccessInfo>),
+}
+
+/// Load recorded registrations from a JSON file. The file contains either a `RegisterRequest`,
+/// an array of them, or an array of `EntityAccessInfo`.
+///
+/// # Arguments
+/// * `path` - The path to the file.
+pub fn load_recorded_registrations(path: &Path) -> Result<Vec<EntityAccessInfo>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read '{}' due to '{err}'", path.display()))?;
+    let recorded: RecordedRegistrations = serde_json::from_str(&json).map_err(|err| {
+        format!(
+            "Failed to parse the registrations in '{}' due to '{err}'",
+            path.display()
+        )
+    })?;
+
+    Ok(match recorded {
+        RecordedRegistrations::Request(request) => request.entity_access_info_list,
+        RecordedRegistrations::Requests(requests) => requests
+            .into_iter()
+            .flat_map(|request| request.entity_access_info_list)
+            .collect(),
+        RecordedRegistrations::EntityAccessInfoList(list) => list,
+    })
+}
+
+/// Look up the registrations of entities in the In-Vehicle Digital Twin Service. Entities that
+/// are not registered are left out. The service can only be queried by id, so entities that are
+/// registered with ids that are not in the model cannot be found this way.
+///
+/// # Arguments
+/// * `invehicle_digital_twin_uri` - The In-Vehicle Digital Twin URI.
+/// * `ids` - The ids of the entities to look up.
+/// * `retry_policy` - How the calls to the In-Vehicle Digital Twin Service are retried.
+pub async fn find_registrations(
+    invehicle_digital_twin_uri: &str,
+    ids: &[String],
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<EntityAccessInfo>, String> {
+    let client = retry_policy
+        .run("Connect", move || async move {
+            Ok(InvehicleDigitalTwinClient::new(
+                connect(invehicle_digital_twin_uri)
+                    .await
+                    .map_err(connection_error)?,
+            ))
+        })
+        .await
+        .map_err(|status| {
+            format!(
+                "Failed to connect to '{invehicle_digital_twin_uri}' due to '{}'",
+                status.message()
+            )
+        })?;
+
+    let mut registrations = Vec::new();
+    for id in ids {
+        let result = retry_policy
+            .run("FindById", || {
+                let mut client = client.clone();
+                let request = tonic::Request::new(FindByIdRequest { id: id.clone() });
+                async move { client.find_by_id(request).await }
+            })
+            .await;
+        match result {
+            Ok(response) => {
+                if let Some(entity_access_info) = response.into_inner().entity_access_info {
+                    registrations.push(entity_access_info);
+                }
+            }
+            Err(status) if status.code() == Code::NotFound => {
+                debug!("'{id}' is not registered");
+            }
+            Err(status) => {
+                return Err(format!(
+                    "Failed to find '{id}' due to '{}'",
+                    status.message()
+                ))
+            }
+        }
+    }
+
+    Ok(registrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use invehicle_stack_interfaces::invehicle_digital_twin::v1::EndpointInfo;
+
+    use super::*;
+
+    /// A model with every kind of entity that the checker distinguishes.
+    const DEVICE_DOCUMENT: &str = r#"{
+        "@context": ["dtmi:dtdl:context;3"],
+        "@type": "Interface",
+        "@id": "dtmi:test:Device;1",
+        "contents": [
+            { "@type": "Property", "name": "Mode", "description": "The mode", "schema": "string", "writable": true },
+            { "@type": "Property", "name": "Serial", "description": "The serial number", "schema": "string" },
+            { "@type": "Telemetry", "name": "Speed", "description": "The speed", "schema": "double" },
+            { "@type": "Command", "name": "Reset", "description": "Reset the device", "response": { "name": "done", "schema": "boolean" } },
+            { "@type": "Relationship", "name": "hasPart", "target": "dtmi:test:Device;1" }
+        ]
+    }"#;
+
+    const MODE: &str = "dtmi:test:Device:Mode;1";
+    const SERIAL: &str = "dtmi:test:Device:Serial;1";
+    const SPEED: &str = "dtmi:test:Device:Speed;1";
+    const RESET: &str = "dtmi:test:Device:Reset;1";
+    const HAS_PART: &str = "dtmi:test:Device:hasPart;1";
+
+    fn device_registry() -> ModelRegistry {
+        let mut registry = ModelRegistry::new();
+        registry.load_str(DEVICE_DOCUMENT).unwrap();
+        registry
+    }
+
+    /// A registration of an entity with one endpoint that offers the provided operations.
+    fn registration(
+        registry: &ModelRegistry,
+        id: &str,
+        protocol: &str,
+        operations: &[&str],
+    ) -> EntityAccessInfo {
+        let (name, description) = registry.get_entity(id).map_or_else(
+            || (String::new(), String::new()),
+            |entity| {
+                (
+                    entity.content.name.clone(),
+                    entity.content.description_text().to_string(),
+                )
+            },
+        );
+
+        EntityAccessInfo {
+            name,
+            id: id.to_string(),
+            description,
+            endpoint_info_list: vec![EndpointInfo {
+                protocol: protocol.to_string(),
+                operations: operations.iter().map(|op| op.to_string()).collect(),
+                uri: "http://0.0.0.0:4010".to_string(),
+                context: id.to_string(),
+            }],
+        }
+    }
+
+    fn not_allowed(id: &str, operation: &str, reason: &str) -> Finding {
+        Finding::OperationNotAllowed {
+            id: id.to_string(),
+            operation: operation.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn operations_are_checked_against_the_element_type() {
+        use digital_twin_operation::*;
+
+        let registry = device_registry();
+        let checker = ConformanceChecker::new(&registry);
+        let cases: &[(&str, &[&str], Vec<Finding>)] = &[
+            (MODE, &[GET, SET, MANAGEDSUBSCRIBE], vec![]),
+            (SERIAL, &[GET, SUBSCRIBE], vec![]),
+            (
+                SERIAL,
+                &[SET],
+                vec![not_allowed(SERIAL, SET, "the Property is not writable")],
+            ),
+            (
+                MODE,
+                &[INVOKE],
+                vec![not_allowed(MODE, INVOKE, "only a Command can be invoked")],
+            ),
+            (SPEED, &[SUBSCRIBE, STREAM], vec![]),
+            (
+                SPEED,
+                &[GET],
+                vec![not_allowed(
+                    SPEED,
+                    GET,
+                    "Telemetry can only be subscribed to or streamed",
+                )],
+            ),
+            (RESET, &[INVOKE], vec![]),
+            (
+                RESET,
+                &[GET],
+                vec![not_allowed(RESET, GET, "a Command can only be invoked")],
+            ),
+            (
+                MODE,
+                &["Delete"],
+                vec![Finding::UnknownOperation {
+                    id: MODE.to_string(),
+                    operation: "Delete".to_string(),
+                }],
+            ),
+        ];
+
+        for (id, operations, expected) in cases {
+            let info = registration(&registry, id, digital_twin_protocol::GRPC, operations);
+
+            assert_eq!(
+                &checker.check_entity_access_info(&info),
+                expected,
+                "{id} {operations:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn registrations_are_checked_against_the_entity() {
+        let registry = device_registry();
+        let checker = ConformanceChecker::new(&registry);
+        let get = &[digital_twin_operation::GET][..];
+
+        let mut invalid = registration(&registry, MODE, digital_twin_protocol::GRPC, get);
+        invalid.id = "dtmi:test:Device:Mode;0".to_string();
+        assert!(matches!(
+            checker.check_entity_access_info(&invalid)[..],
+            [Finding::InvalidId { .. }]
+        ));
+
+        let unknown = registration(&registry, "dtmi:test:Device:Color;1", "grpc", get);
+        assert_eq!(
+            checker.check_entity_access_info(&unknown),
+            [Finding::UnknownEntity {
+                id: "dtmi:test:Device:Color;1".to_string()
+            }]
+        );
+
+        let relationship = registration(&registry, HAS_PART, digital_twin_protocol::GRPC, get);
+        assert_eq!(
+            checker.check_entity_access_info(&relationship),
+            [Finding::NotAValueEntity {
+                id: HAS_PART.to_string(),
+                element_type: element_type::RELATIONSHIP.to_string()
+            }]
+        );
+
+        let mut renamed = registration(&registry, MODE, digital_twin_protocol::GRPC, get);
+        renamed.name = "Status".to_string();
+        renamed.description = "The status".to_string();
+        let findings = checker.check_entity_access_info(&renamed);
+        assert_eq!(
+            findings,
+            [
+                Finding::NameMismatch {
+                    id: MODE.to_string(),
+                    expected: "Mode".to_string(),
+                    actual: "Status".to_string()
+                },
+                Finding::DescriptionMismatch {
+                    id: MODE.to_string(),
+                    expected: "The mode".to_string(),
+                    actual: "The status".to_string()
+                }
+            ]
+        );
+        assert_eq!(findings[1].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn endpoints_are_checked() {
+        let registry = device_registry();
+        let checker = ConformanceChecker::new(&registry);
+
+        let mut no_endpoints = registration(&registry, MODE, digital_twin_protocol::GRPC, &[]);
+        no_endpoints.endpoint_info_list.clear();
+        assert_eq!(
+            checker.check_entity_access_info(&no_endpoints),
+            [Finding::NoEndpoints {
+                id: MODE.to_string()
+            }]
+        );
+
+        let mut no_uri = registration(&registry, MODE, "http", &[digital_twin_operation::GET]);
+        no_uri.endpoint_info_list[0].uri.clear();
+        assert_eq!(
+            checker.check_entity_access_info(&no_uri),
+            [
+                Finding::MissingUri {
+                    id: MODE.to_string()
+                },
+                Finding::UnknownProtocol {
+                    id: MODE.to_string(),
+                    protocol: "http".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn check_registrations_reports_duplicates_and_missing_providers() {
+        let registry = device_registry();
+        let checker = ConformanceChecker::new(&registry);
+        let mode = registration(
+            &registry,
+            MODE,
+            digital_twin_protocol::GRPC,
+            &[digital_twin_operation::GET],
+        );
+
+        let report = checker.check_registrations(
+            &[mode.clone(), mode],
+            &[MODE.to_string(), SERIAL.to_string()],
+        );
+
+        assert_eq!(report.checked, 2);
+        assert_eq!(
+            report.findings,
+            [
+                Finding::DuplicateRegistration {
+                    id: MODE.to_string()
+                },
+                Finding::MissingProvider {
+                    id: SERIAL.to_string()
+                }
+            ]
+        );
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn value_entity_ids_skips_relationships() {
+        let registry = device_registry();
+
+        assert_eq!(
+            value_entity_ids(&registry, "dtmi:test:Device;1").unwrap(),
+            [MODE, RESET, SERIAL, SPEED]
+        );
+        assert!(value_entity_ids(&registry, "dtmi:test:Unknown;1").is_err());
+    }
+
+    #[test]
+    fn the_recorded_smart_trailer_registrations_conform() {
+        let registry = ModelRegistry::with_builtin_models().unwrap();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("registrations/smart_trailer.json");
+
+        let registrations = load_recorded_registrations(&path).unwrap();
+        let report = ConformanceChecker::new(&registry).check_registrations(&registrations, &[]);
+
+        assert!(!registrations.is_empty());
+        assert!(report.findings.is_empty(), "{report}");
+    }
+
+    #[test]
+    fn load_recorded_registrations_reports_the_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+
+        let err = load_recorded_registrations(&path).unwrap_err();
+        assert!(err.contains("Cargo.toml"), "{err}");
+    }
+}
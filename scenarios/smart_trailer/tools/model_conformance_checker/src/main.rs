@@ -0,0 +1,130 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the Apache License, Version 2.0.
+// SPDX-License-Identifier: Apache-2.0
+
+mod config;
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use digital_twin_model::model_registry::ModelRegistry;
+use digital_twin_providers_common::config::ConfigLoader;
+use digital_twin_providers_common::service_resolver::{
+    ChariottResolver, ServiceDescriptor, ServiceResolver,
+};
+use digital_twin_providers_common::tls::configure_clients;
+use env_logger::{Builder, Target};
+use log::{info, LevelFilter};
+use model_conformance_checker::{
+    find_registrations, load_recorded_registrations, value_entity_ids, ConformanceChecker,
+};
+
+use crate::config::ModelConformanceCheckerConfig;
+
+const CONFIG_NAME: &str = "model_conformance_checker";
+
+// Short flags that are kept for compatibility with the configuration keys that they set.
+const FLAG_ALIASES: &[(&str, &str)] = &[
+    ("dtdl_dir", "checker.dtdl_dir"),
+    ("registrations", "checker.registrations"),
+    ("ibeji_uri", "checker.ibeji_uri"),
+    ("chariott_uri", "chariott.uri"),
+    ("expect", "checker.expect"),
+    ("expect_interface", "checker.expect_interface"),
+];
+
+/// Load the model, either from a directory of DTDL files or from the built-in models.
+///
+/// # Arguments
+/// * `dtdl_dir` - The directory of DTDL files, if any.
+fn load_model(dtdl_dir: Option<&str>) -> Result<ModelRegistry, String> {
+    match dtdl_dir {
+        Some(dtdl_dir) => {
+            info!("Loading the model from '{dtdl_dir}'");
+            let mut registry = ModelRegistry::new();
+            registry.load_dir(Path::new(dtdl_dir))?;
+            Ok(registry)
+        }
+        None => {
+            info!("Loading the built-in model");
+            ModelRegistry::with_builtin_models()
+        }
+    }
+}
+
+async fn run() -> Result<bool, Box<dyn std::error::Error>> {
+    let config: ModelConformanceCheckerConfig = FLAG_ALIASES
+        .iter()
+        .fold(ConfigLoader::new(CONFIG_NAME), |loader, (flag, key)| {
+            loader.with_alias(flag, key)
+        })
+        .load()?;
+    configure_clients(&config.tls)?;
+    let checker = &config.checker;
+
+    let retry_policy = config.retry.policy();
+
+    let registry = load_model(checker.dtdl_dir.as_deref())?;
+
+    let mut expected_ids = checker.expect.clone();
+    if let Some(id) = expected_ids
+        .iter()
+        .find(|id| registry.get_entity(id).is_none())
+    {
+        return Err(format!("The expected entity '{id}' is not in the model").into());
+    }
+    for interface_id in &checker.expect_interface {
+        expected_ids.extend(value_entity_ids(&registry, interface_id)?);
+    }
+
+    let registrations = match &checker.registrations {
+        Some(path) => {
+            info!("Checking the registrations recorded in '{path}'");
+            load_recorded_registrations(Path::new(path))?
+        }
+        None => {
+            let invehicle_digital_twin_uri = match &checker.ibeji_uri {
+                Some(uri) => uri.clone(),
+                None => {
+                    ChariottResolver::new(&config.chariott.uri, retry_policy.clone())
+                        .resolve(&ServiceDescriptor::invehicle_digital_twin())
+                        .await?
+                }
+            };
+
+            info!("Checking the registrations in the In-Vehicle Digital Twin Service at {invehicle_digital_twin_uri}");
+            let mut ids = Vec::new();
+            for interface in registry.interfaces() {
+                ids.extend(value_entity_ids(&registry, &interface.id)?);
+            }
+            ids.sort();
+            ids.dedup();
+
+            find_registrations(&invehicle_digital_twin_uri, &ids, &retry_policy).await?
+        }
+    };
+
+    let report =
+        ConformanceChecker::new(&registry).check_registrations(&registrations, &expected_ids);
+    println!("{report}");
+
+    Ok(!report.has_errors())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Setup logging.
+    Builder::new()
+        .filter(None, LevelFilter::Info)
+        .target(Target::Stderr)
+        .init();
+
+    match run().await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::from(2)
+        }
+    }
+}